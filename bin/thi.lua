@@ -167,6 +167,57 @@ if mode == "source" then
     io.write("    pub fn bytecode(&self) -> u8 {\n");
     io.write("       *self as u8\n");
     io.write("    }\n");
+    io.write("\n");
+    io.write("    /// The mnemonic of the op, as written in assembly.\n");
+    io.write("    pub fn mnemonic(&self) -> &'static str {\n");
+    io.write("        match self {\n");
+    for _, v in ipairs(ast) do
+        local suffix = ""
+        if v.args:len() > 0 then
+            suffix = table.concat(map(Arg.typeLetter, v.args), "")
+        end
+        io.write("            Op::" .. v.name:sub(1, 1):upper() .. v.name:sub(2):lower() .. suffix:upper() .. " => \"" .. v.name:upper() .. "\",\n");
+    end
+    io.write("        }\n");
+    io.write("    }\n");
+    io.write("\n");
+    io.write("    /// The operands of the op, as written in assembly: `r0, [r1 + w0]` for instance.\n");
+    io.write("    pub fn syntax(&self) -> &'static str {\n");
+    io.write("        match self {\n");
+    for _, v in ipairs(ast) do
+        local suffix = ""
+        if v.args:len() > 0 then
+            suffix = table.concat(map(Arg.typeLetter, v.args), "")
+        end
+        io.write("            Op::" .. v.name:sub(1, 1):upper() .. v.name:sub(2):lower() .. suffix:upper() .. " => \"" .. v:syntax() .. "\",\n");
+    end
+    io.write("        }\n");
+    io.write("    }\n");
+    local function writeText(doc, method, text)
+        io.write("\n");
+        io.write("    /// " .. doc .. "\n");
+        io.write("    pub fn " .. method .. "(&self) -> Option<&'static str> {\n");
+        io.write("        match self {\n");
+        for _, v in ipairs(ast) do
+            local suffix = ""
+            if v.args:len() > 0 then
+                suffix = table.concat(map(Arg.typeLetter, v.args), "")
+            end
+            local value = text(v)
+            if value == "" or value == "n/a" then
+                value = "None"
+            else
+                value = "Some(\"" .. value:gsub("\\", "\\\\"):gsub("\"", "\\\"") .. "\")"
+            end
+            io.write("            Op::" .. v.name:sub(1, 1):upper() .. v.name:sub(2):lower() .. suffix:upper() .. " => " .. value .. ",\n");
+        end
+        io.write("        }\n");
+        io.write("    }\n");
+    end
+    writeText("The effect of the op, in Markdown.", "effect", function(v) return v.effect end)
+    writeText("The condition for the op to take effect, in Markdown.", "condition", function(v) return v.cond end)
+    writeText("The flags the op updates: `n, z` for instance.", "flags", function(v) return table.concat(map(Flag.toString, v.flags), ", ") end)
+    writeText("The comment on the op, in Markdown.", "comment", function(v) return v.comment end)
     io.write("}\n");
     io.write("\n");
     io.write("impl From<u8> for Op {\n");
@@ -184,6 +235,40 @@ if mode == "source" then
     io.write("    }\n")
     io.write("}\n")
     io.write("\n")
+    io.write("/// Calls `$m!` with each mnemonic, as a method name and as written, and the operands its forms\n")
+    io.write("/// take: `(o1, o2)` when they all take as many, `(..)` otherwise.\n")
+    io.write("macro_rules! for_each_mnemonic {\n")
+    io.write("    ($m:ident) => {\n")
+    io.write("        $m! {\n")
+    local names = {}
+    local operands = {}
+    for _, v in ipairs(ast) do
+        local name = v.name:upper()
+        local count = v:operands()
+        if operands[name] == nil then
+            table.insert(names, name)
+            operands[name] = count
+        elseif operands[name] ~= count then
+            operands[name] = -1
+        end
+    end
+    table.sort(names)
+    for _, name in ipairs(names) do
+        local list = ".."
+        if operands[name] >= 0 then
+            local names = {}
+            for i = 1, operands[name] do
+                table.insert(names, "o" .. i)
+            end
+            list = table.concat(names, ", ")
+        end
+        io.write("            " .. name:lower() .. " \"" .. name .. "\" (" .. list .. ");\n")
+    end
+    io.write("        }\n")
+    io.write("    };\n")
+    io.write("}\n")
+    io.write("pub(crate) use for_each_mnemonic;\n")
+    io.write("\n")
     io.write("\n")
     io.close(file)
 elseif mode == "doc" then
@@ -63,6 +63,7 @@ local keyword = enum {
     "flags",
     "effect",
     "comment",
+    "syntax",
 
     "reg",
     "index"
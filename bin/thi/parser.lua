@@ -145,6 +145,14 @@ end
 function Instruction:size()
     return 1 + self.args:size()
 end
+function Instruction:syntax()
+    return self.syntaxText or self.args:toString()
+end
+function Instruction:operands()
+    local syntax = self:syntax()
+    if syntax == "" then return 0 end
+    return select(2, syntax:gsub(", ", "")) + 1
+end
 
 -- Parser ---------------------------------------
 Parser = {}
@@ -325,6 +333,7 @@ function Parser:parse()
         "Cond",
         "Effect",
         "Comment",
+        "Syntax",
     }
     local function parsePair()
         local token = next(TokenType.LPAR)
@@ -348,6 +357,9 @@ function Parser:parse()
         elseif key == "comment" then
             pair.type = PairType.Comment
             pair.value = parseText()
+        elseif key == "syntax" then
+            pair.type = PairType.Syntax
+            pair.value = parseText():gsub("`", "")
         elseif key == "flags" then
             pair.type = PairType.Flag
             pair.value = Flags:new(parseFlags())
@@ -394,6 +406,8 @@ function Parser:parse()
                         instruction.effect = pair.value
                     elseif pair.type == PairType.Comment then
                         instruction.comment = pair.value
+                    elseif pair.type == PairType.Syntax then
+                        instruction.syntaxText = pair.value
                     elseif pair.type == PairType.Flag then
                         instruction.flags = pair.value
                     elseif nested and pair.type == PairType.Args then
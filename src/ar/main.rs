@@ -0,0 +1,109 @@
+use std::fs::{self, OpenOptions};
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, crate_authors, crate_version};
+
+use tha::archive::Archive;
+use tha::object::Object;
+
+fn main() {
+    let matches = parse_opts();
+
+    if let Some(list) = matches.value_of("list") {
+        match fs::read(list).map_err(|e| e.to_string()).and_then(|bytes| Archive::read(&bytes)) {
+            Ok(archive) => print(&archive),
+            Err(err) => println!("Input error: {} in {}", err, list),
+        }
+        return;
+    }
+
+    let input: Vec<_> = matches.values_of("input").unwrap().collect();
+    let output = matches.value_of("output").unwrap();
+
+    let mut members = vec![];
+    for f in input {
+        let name = match Path::new(f).file_name() {
+            Some(name) => name.to_string_lossy().to_string(),
+            None => {
+                println!("Input error: Not a file {}", f);
+                return;
+            }
+        };
+        match fs::read(f).map_err(|e| e.to_string()).and_then(|bytes| Object::read(&bytes)) {
+            Ok(object) => members.push((name, object)),
+            Err(err) => {
+                println!("Input error: {} in {}", err, f);
+                return;
+            }
+        }
+    }
+
+    let archive = match Archive::new(members) {
+        Ok(archive) => archive,
+        Err(err) => {
+            println!("Archive error: {}", err);
+            return;
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(output)
+        .unwrap();
+    if let Err(err) = archive.write(&mut file) {
+        println!("Output error: {}", err);
+        return;
+    }
+
+    println!("Wrote {} objects to {}", archive.members.len(), output);
+}
+
+/// Prints each member and the labels it defines.
+fn print(archive: &Archive) {
+    for (i, (name, _)) in archive.members.iter().enumerate() {
+        let mut labels: Vec<&String> = archive.index.iter()
+            .filter(|(_, member)| **member as usize == i)
+            .map(|(label, _)| label)
+            .collect();
+        labels.sort();
+        println!("{}", name);
+        labels.iter().for_each(|label| println!("    {}", label));
+    }
+}
+
+fn parse_opts<'a>() -> ArgMatches<'a> {
+    App::new("Thorium Archiver")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Bundles objects written by tha -c into an archive for thld")
+        .arg(
+            Arg::with_name("input")
+                .help("Input objects")
+                .long("input")
+                .short("i")
+                .multiple(true)
+                .number_of_values(1)
+                .required_unless("list")
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Output archive")
+                .long("output")
+                .short("o")
+                .multiple(false)
+                .number_of_values(1)
+                .required_unless("list")
+        )
+        .arg(
+            Arg::with_name("list")
+                .help("Lists the members of an archive and the labels they define")
+                .long("list")
+                .short("t")
+                .multiple(false)
+                .number_of_values(1)
+                .conflicts_with_all(&["input", "output"])
+        )
+        .get_matches()
+}
@@ -2,6 +2,19 @@ use std::collections::HashMap;
 
 use crate::parser::{Directive, Instruction, Node};
 
+/// A label's location: the origin of the segment it belongs to and its offset in that segment.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Address {
+    pub segment: u32,
+    pub offset: u32,
+}
+
+impl Address {
+    pub fn absolute(&self) -> u32 {
+        self.segment.wrapping_add(self.offset)
+    }
+}
+
 pub struct AddressResolver<'t> {
     nodes: &'t Vec<Node>,
 }
@@ -13,32 +26,45 @@ impl<'t> AddressResolver<'t> {
         AddressResolver { nodes }
     }
 
-    pub fn resolve(&self) -> Result<HashMap<String, u32>> {
+    pub fn resolve(&self) -> Result<HashMap<String, Address>> {
         let mut map = HashMap::new();
+        let mut segments = vec![];
 
-        let mut position = 0 as u32;
+        let mut segment = 0u32;
+        let mut position = 0u32;
         for node in self.nodes {
-            match node {
-                Node::Directive(Directive::Word(label, _)) => {
-                    if map.contains_key(label) {
-                        return Err(format!("Label {} used more than once", label).to_string());
-                    }
-                    map.insert(label.to_owned(), position);
-                    position += 4 as u32; // todo extract to a word_size constant?
+            let (label, size) = match node {
+                Node::Directive(Directive::Base(origin)) => {
+                    segments.push((segment, position));
+                    segment = *origin;
+                    position = 0;
+                    continue;
                 }
-                Node::Instruction(i) => {
-                    position += i.op().length() as u32;
+                Node::Directive(Directive::Entry(_))
+                | Node::Directive(Directive::Extern(_))
+                | Node::Directive(Directive::Global(_)) => continue,
+                Node::Directive(Directive::Section(name)) => {
+                    return Err(format!("Section {} is not placed in any region", name));
                 }
-                Node::Label(label) => {
-                    if map.contains_key(label) {
-                        return Err(format!("Label {} used more than once", label).to_string());
-                    }
-                    map.insert(label.to_owned(), position);
+                Node::Directive(Directive::Image(label, pixels)) => (Some(label), 4 * pixels.len() as u32),
+                Node::Directive(Directive::Space(label, size)) => (Some(label), *size),
+                Node::Directive(Directive::Word(label, _)) => (Some(label), 4), // todo extract to a word_size constant?
+                Node::Instruction(i) => (None, i.op().length() as u32),
+                Node::Label(label) => (Some(label), 0),
+            };
+            if let Some(label) = label {
+                if map.contains_key(label) {
+                    return Err(format!("Label {} used more than once", label));
                 }
-                _ => continue,
+                map.insert(label.to_owned(), Address { segment, offset: position });
             }
+            position += size;
         }
+        segments.push((segment, position));
+
+        Self::check_segments(segments)?;
 
+        let mut entry = None;
         for node in self.nodes {
             match node {
                 Node::Instruction(Instruction::IA(_, address, _)) => {
@@ -46,12 +72,41 @@ impl<'t> AddressResolver<'t> {
                         return Err(format!("Label {} is missing", address).to_string());
                     }
                 }
+                Node::Directive(Directive::Entry(label)) => {
+                    if !map.contains_key(label) {
+                        return Err(format!("Label {} is missing", label));
+                    }
+                    if let Some(other) = entry.replace(label) {
+                        return Err(format!("Entry point defined more than once, at {} and {}", other, label));
+                    }
+                }
                 _ => continue,
             }
         }
 
         Ok(map)
     }
+
+    /// Makes sure that no two non-empty segments share an address and that no segment goes past
+    /// the end of the address space.
+    pub fn check_segments(mut segments: Vec<(u32, u32)>) -> Result<()> {
+        segments.retain(|(_, size)| *size > 0);
+        segments.sort();
+
+        for (origin, size) in segments.iter() {
+            if origin.checked_add(size - 1).is_none() {
+                return Err(format!("Segment at 0x{:08x} ({} bytes) exceeds the address space", origin, size));
+            }
+        }
+        for pair in segments.windows(2) {
+            let ((o1, s1), (o2, _)) = (pair[0], pair[1]);
+            if o1 + (s1 - 1) >= o2 {
+                return Err(format!("Segment at 0x{:08x} ({} bytes) overlaps segment at 0x{:08x}", o1, s1, o2));
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -73,9 +128,66 @@ mod tests {
 
         let addresses = addresses.unwrap();
         assert_eq!(true, addresses.get(&"label1".to_string()).is_some());
-        assert_eq!(0, *addresses.get(&"label1".to_string()).unwrap());
+        assert_eq!(0, addresses.get(&"label1".to_string()).unwrap().absolute());
         assert_eq!(true, addresses.get(&"label2".to_string()).is_some());
-        assert_eq!(8, *addresses.get(&"label2".to_string()).unwrap());
+        assert_eq!(8, addresses.get(&"label2".to_string()).unwrap().absolute());
+    }
+
+    #[test]
+    fn resolve_segments() {
+        let nodes = vec![
+            Node::Directive(Directive::Base(0x1000)),
+            Node::Instruction(Instruction::I(Op::Nop)),
+            Node::Label("label1".to_string()),
+            Node::Directive(Directive::Base(0x2000)),
+            Node::Instruction(Instruction::I(Op::Nop)),
+            Node::Label("label2".to_string()),
+        ];
+        let addresses = AddressResolver::new(&nodes).resolve();
+
+        assert_eq!(true, addresses.is_ok(), "Expected Ok(...), got {:?}", addresses);
+
+        let addresses = addresses.unwrap();
+        assert_eq!(Some(&Address { segment: 0x1000, offset: 4 }), addresses.get("label1"));
+        assert_eq!(0x1004, addresses.get("label1").unwrap().absolute());
+        assert_eq!(Some(&Address { segment: 0x2000, offset: 4 }), addresses.get("label2"));
+        assert_eq!(0x2004, addresses.get("label2").unwrap().absolute());
+    }
+
+    #[test]
+    fn resolve_overlapping_segments() {
+        let nodes = vec![
+            Node::Directive(Directive::Base(0x1000)),
+            Node::Instruction(Instruction::IW(Op::PushW, 0)),
+            Node::Directive(Directive::Base(0x1004)),
+            Node::Instruction(Instruction::I(Op::Nop)),
+        ];
+        let addresses = AddressResolver::new(&nodes).resolve();
+
+        assert_eq!(Err("Segment at 0x00001000 (8 bytes) overlaps segment at 0x00001004".to_string()), addresses);
+    }
+
+    #[test]
+    fn resolve_empty_segments_do_not_overlap() {
+        let nodes = vec![
+            Node::Directive(Directive::Base(0x1000)),
+            Node::Directive(Directive::Base(0x1000)),
+            Node::Instruction(Instruction::I(Op::Nop)),
+        ];
+        let addresses = AddressResolver::new(&nodes).resolve();
+
+        assert_eq!(true, addresses.is_ok(), "Expected Ok(...), got {:?}", addresses);
+    }
+
+    #[test]
+    fn resolve_segment_exceeds_address_space() {
+        let nodes = vec![
+            Node::Directive(Directive::Base(0xfffffffc)),
+            Node::Instruction(Instruction::IW(Op::PushW, 0)),
+        ];
+        let addresses = AddressResolver::new(&nodes).resolve();
+
+        assert_eq!(Err("Segment at 0xfffffffc (8 bytes) exceeds the address space".to_string()), addresses);
     }
 
     #[test]
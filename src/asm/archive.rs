@@ -0,0 +1,204 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::object::{Object, Reader, write_string};
+
+/// Objects bundled by `thar`, along with an index of the global labels they define, so that `thld`
+/// only links the members a program needs.
+///
+/// It is written as, all numbers being big-endian:
+///
+/// ```text
+/// magic:   "THAR"
+/// version: u8 (1)
+/// index:   u32 count, then for each global label: u16 length, the UTF-8 name, u32 member index
+/// members: u32 count, then for each object: u16 length, the UTF-8 name, u32 length, the object
+/// ```
+#[derive(Debug, PartialEq, Default)]
+pub struct Archive {
+    pub index: HashMap<String, u32>,
+    pub members: Vec<(String, Object)>,
+}
+
+pub const MAGIC: &[u8; 4] = b"THAR";
+pub const VERSION: u8 = 1;
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Archive {
+    /// Bundles `members`, given along with their name; a global label cannot be defined by two
+    /// members.
+    pub fn new(members: Vec<(String, Object)>) -> Result<Archive> {
+        let mut index = HashMap::new();
+        for (i, (name, object)) in members.iter().enumerate() {
+            for symbol in object.symbols.iter().filter(|s| s.global) {
+                if let Some(other) = index.insert(symbol.name.to_owned(), i as u32) {
+                    return Err(format!("Label {} defined in {} and {}", symbol.name, members[other as usize].0, name));
+                }
+            }
+        }
+        Ok(Archive { index, members })
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut index: Vec<(&String, &u32)> = self.index.iter().collect();
+        index.sort();
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(index.len() as u32).to_be_bytes());
+        for (name, member) in index {
+            write_string(&mut bytes, name)?;
+            bytes.extend_from_slice(&member.to_be_bytes());
+        }
+        bytes.extend_from_slice(&(self.members.len() as u32).to_be_bytes());
+        for (name, object) in self.members.iter() {
+            let mut member = vec![];
+            object.write(&mut member)?;
+            write_string(&mut bytes, name)?;
+            bytes.extend_from_slice(&(member.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&member);
+        }
+
+        out.write_all(bytes.as_slice()).map_err(|e| e.to_string())
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<Archive> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err("Not an archive".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported archive version {}", version));
+        }
+
+        let mut archive = Archive::default();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            archive.index.insert(name, reader.u32()?);
+        }
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let length = reader.u32()? as usize;
+            let object = Object::read(reader.take(length)?).map_err(|e| format!("{} in {}", e, name))?;
+            archive.members.push((name, object));
+        }
+        if let Some((name, _)) = archive.index.iter().find(|(_, member)| **member as usize >= archive.members.len()) {
+            return Err(format!("Invalid member for label {}", name));
+        }
+        if !reader.bytes.is_empty() {
+            return Err("Unexpected data at the end of the archive".to_string());
+        }
+
+        Ok(archive)
+    }
+}
+
+/// Selects the members of `archives` defining the global labels `objects` use but do not define,
+/// then the members these use in turn. Archives are searched in order and each member is selected
+/// at most once; its name is the archive's followed by the member's in parentheses.
+pub fn select(objects: &[(String, Object)], archives: &[(String, Archive)]) -> Vec<(String, Object)> {
+    let mut defined: HashSet<&String> = objects.iter()
+        .flat_map(|(_, object)| globals(object))
+        .collect();
+    let mut needed: Vec<&String> = objects.iter()
+        .flat_map(|(_, object)| externs(object))
+        .collect();
+    let mut selected: Vec<(usize, usize)> = vec![];
+
+    while let Some(label) = needed.pop() {
+        if defined.contains(label) {
+            continue;
+        }
+        let member = archives.iter()
+            .enumerate()
+            .find_map(|(a, (_, archive))| archive.index.get(label).map(|m| (a, *m as usize)));
+        if let Some((a, m)) = member {
+            let object = &archives[a].1.members[m].1;
+            defined.extend(globals(object));
+            needed.extend(externs(object).rev());
+            selected.push((a, m));
+        }
+    }
+
+    selected.into_iter()
+        .map(|(a, m)| {
+            let (archive, (member, object)) = (&archives[a].0, &archives[a].1.members[m]);
+            (format!("{}({})", archive, member), object.clone())
+        })
+        .collect()
+}
+
+fn globals(object: &Object) -> impl Iterator<Item=&String> {
+    object.symbols.iter().filter(|s| s.global).map(|s| &s.name)
+}
+
+/// The labels an object uses without defining them.
+fn externs(object: &Object) -> impl DoubleEndedIterator<Item=&String> {
+    object.relocations.iter()
+        .map(|r| &r.symbol)
+        .filter(move |label| !object.symbols.iter().any(|s| s.name == **label))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    use super::*;
+
+    fn object(source: &str) -> Object {
+        let mut lexer = Lexer::from_text(source);
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse().unwrap();
+        Object::new(&nodes).unwrap()
+    }
+
+    #[test]
+    fn write_read() {
+        let archive = Archive::new(vec![
+            ("memcpy.o".to_string(), object("#global memcpy\n:memcpy\n    RET\n")),
+            ("video.o".to_string(), object("#global clear\n#global swap\n:clear\n    RET\n:swap\n    RET\n:loop\n")),
+        ]).unwrap();
+
+        let mut out = vec![];
+        let r = archive.write(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(b"THAR\x01\x00\x00\x00\x03\x00\x05clear\x00\x00\x00\x01", &out[..20]);
+        assert_eq!(Ok(archive), Archive::read(&out));
+    }
+
+    #[test]
+    fn new_duplicate_label() {
+        let archive = Archive::new(vec![
+            ("a.o".to_string(), object("#global memcpy\n:memcpy\n")),
+            ("b.o".to_string(), object("#global memcpy\n:memcpy\n")),
+        ]);
+
+        assert_eq!(Err("Label memcpy defined in a.o and b.o".to_string()), archive);
+    }
+
+    #[test]
+    fn select_needed_members() {
+        let objects = vec![("main.o".to_string(), object(":main\n    CALL &clear\n"))];
+        let archives = vec![
+            ("video.thar".to_string(), Archive::new(vec![
+                ("clear.o".to_string(), object("#global clear\n:clear\n    CALL &memset\n    CALL &loop\n:loop\n    RET\n")),
+                ("swap.o".to_string(), object("#global swap\n:swap\n    RET\n")),
+            ]).unwrap()),
+            ("runtime.thar".to_string(), Archive::new(vec![
+                ("memcpy.o".to_string(), object("#global memcpy\n:memcpy\n    RET\n")),
+                ("memset.o".to_string(), object("#global memset\n:memset\n    RET\n")),
+                ("loop.o".to_string(), object("#global loop\n:loop\n    RET\n")),
+            ]).unwrap()),
+        ];
+
+        let selected: Vec<String> = select(&objects, &archives).into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(vec!["video.thar(clear.o)".to_string(), "runtime.thar(memset.o)".to_string()], selected);
+    }
+}
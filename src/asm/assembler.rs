@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+
+use crate::address_resolver::{Address, AddressResolver};
+use crate::checker::{Checker, VmConfig};
+use crate::constants::REG_COUNT;
+use crate::container::{self, Container};
+use crate::cst::Cst;
+use crate::layout::Layout;
+use crate::lexer::{Lexer, Position, Token};
+use crate::object::Object;
+use crate::parser::{Node, NodeError, Parser, Spans, Test};
+use crate::visibility::Visibility;
+
+/// A text to assemble, along with the path of the file it comes from, if any.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Source {
+    pub file: Option<String>,
+    pub text: String,
+}
+
+impl Source {
+    pub fn from_file(path: &str) -> std::result::Result<Source, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Source { file: Some(path.to_string()), text })
+    }
+
+    pub fn from_text(text: &str) -> Source {
+        Source { file: None, text: text.to_string() }
+    }
+
+    fn lexer(&self) -> Lexer {
+        let lexer = Lexer::from_text(&self.text);
+        match &self.file {
+            Some(file) => lexer.with_file(file),
+            None => lexer,
+        }
+    }
+}
+
+/// The stage that failed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Syntax,
+    Semantic,
+    Layout,
+}
+
+/// An error, along with the file and the position it is reported at when the stage knows them.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub kind: Kind,
+    pub message: String,
+    pub file: Option<String>,
+    pub position: Option<Position>,
+}
+
+impl Diagnostic {
+    fn new(kind: Kind, message: String) -> Diagnostic {
+        Diagnostic { kind, message, file: None, position: None }
+    }
+
+    fn at(mut self, file: Option<String>, position: Option<Position>) -> Self {
+        self.file = file;
+        self.position = position;
+        self
+    }
+
+    /// The diagnostic of an error found in the nodes, at the span of the node.
+    fn from_node(kind: Kind, error: NodeError, spans: &Spans) -> Diagnostic {
+        let span = spans.nodes.get(error.index);
+        Diagnostic::new(kind, error.message).at(span.and_then(|s| s.file.clone()), span.map(|s| s.position.clone()))
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            Kind::Syntax => write!(f, "Syntax error: {}", self.message),
+            Kind::Semantic => write!(f, "Semantic error: {}", self.message),
+            Kind::Layout => write!(f, "Layout error: {}", self.message),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    layout: Layout,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// Places the sections started by `#section` with `layout`.
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+/// The sources once parsed: their nodes, the spans of the nodes and the variables, and their
+/// `#test` blocks.
+pub struct Parsed {
+    pub sources: Vec<Source>,
+    pub nodes: Vec<Node>,
+    pub symbols: HashMap<String, Token>,
+    pub spans: Spans,
+    pub tests: Vec<Test>,
+}
+
+/// The parsed sources once their labels are given an address.
+pub struct Resolved {
+    pub sources: Vec<Source>,
+    pub nodes: Vec<Node>,
+    pub symbols: HashMap<String, Token>,
+    pub spans: Spans,
+    pub tests: Vec<Test>,
+    pub addresses: HashMap<String, Address>,
+}
+
+/// An assembled program, with what it was assembled from. The `ranges` are the absolute
+/// addresses each node was emitted at.
+pub struct Image {
+    pub sources: Vec<Source>,
+    pub nodes: Vec<Node>,
+    pub symbols: HashMap<String, Token>,
+    pub spans: Spans,
+    pub tests: Vec<Test>,
+    pub addresses: HashMap<String, Address>,
+    pub ranges: Vec<Range<u32>>,
+    pub container: Container,
+}
+
+type Result<T> = std::result::Result<T, Vec<Diagnostic>>;
+
+/// Assembles the `sources`, in order, into an image, as `tha` does.
+pub fn assemble(sources: &[Source], options: &Options) -> Result<Image> {
+    parse(sources)?.resolve(options)?.emit()
+}
+
+/// The tokens of each of the `sources`.
+pub fn tokens(sources: &[Source]) -> Result<Vec<Vec<Token>>> {
+    sources.iter()
+        .map(|source| {
+            let mut lexer = source.lexer();
+            lexer.by_ref().collect::<std::result::Result<Vec<Token>, String>>()
+                .map_err(|e| vec![Diagnostic::new(Kind::Syntax, e).at(source.file.clone(), Some(lexer.position().clone()))])
+        })
+        .collect()
+}
+
+/// Parses the `sources`, in order, the variables of one being visible in the next ones.
+pub fn parse(sources: &[Source]) -> Result<Parsed> {
+    let mut nodes = vec![];
+    let mut symbols = HashMap::new();
+    let mut spans = Spans::default();
+    let mut tests = vec![];
+    for (index, source) in sources.iter().enumerate() {
+        let mut lexer = source.lexer();
+        let cst = Cst::from_lexer(&mut lexer)
+            .map_err(|e| vec![Diagnostic::new(Kind::Syntax, e).at(source.file.clone(), Some(lexer.position().clone()))])?;
+        let mut parser = Parser::from_cst(&cst, &mut nodes, &mut symbols)
+            .with_spans(&mut spans)
+            .with_source(index)
+            .with_tests(&mut tests);
+        parser.parse()
+            .map_err(|e| vec![Diagnostic::new(Kind::Syntax, e).at(source.file.clone(), parser.position().cloned())])?;
+    }
+    Ok(Parsed { sources: sources.to_vec(), nodes, symbols, spans, tests })
+}
+
+impl Parsed {
+    /// Applies the visibility of the labels, places the sections and resolves the addresses.
+    pub fn resolve(mut self, options: &Options) -> Result<Resolved> {
+        Visibility::new(&mut self.nodes, &self.spans).apply()
+            .map_err(|errors| errors.into_iter().map(|e| Diagnostic::from_node(Kind::Semantic, e, &self.spans)).collect::<Vec<_>>())?;
+        options.layout.place_nodes(&mut self.nodes).map_err(|e| vec![Diagnostic::new(Kind::Layout, e)])?;
+        let addresses = AddressResolver::new(&self.nodes).resolve().map_err(|e| vec![Diagnostic::new(Kind::Semantic, e)])?;
+
+        Ok(Resolved {
+            sources: self.sources,
+            nodes: self.nodes,
+            symbols: self.symbols,
+            spans: self.spans,
+            tests: self.tests,
+            addresses,
+        })
+    }
+
+    /// Compiles the nodes as a relocatable object, leaving the `#extern` labels to the linker.
+    pub fn compile(mut self) -> Result<Object> {
+        Visibility::new(&mut self.nodes, &self.spans).allow_externs().apply()
+            .map_err(|errors| errors.into_iter().map(|e| Diagnostic::from_node(Kind::Semantic, e, &self.spans)).collect::<Vec<_>>())?;
+        check(&self.nodes, &self.spans)?;
+        Object::new(&self.nodes).map_err(|e| vec![Diagnostic::new(Kind::Semantic, e)])
+    }
+}
+
+impl Resolved {
+    pub fn emit(self) -> Result<Image> {
+        check(&self.nodes, &self.spans)?;
+        let (segments, ranges) = crate::emitter::Emitter::new(&self.nodes, &self.addresses).emit();
+        let container = Container {
+            entry: container::entry(&self.nodes, &self.addresses),
+            registers: container::registers(&self.nodes),
+            segments,
+        };
+
+        Ok(Image {
+            sources: self.sources,
+            nodes: self.nodes,
+            symbols: self.symbols,
+            spans: self.spans,
+            tests: self.tests,
+            addresses: self.addresses,
+            ranges,
+            container,
+        })
+    }
+}
+
+fn check(nodes: &[Node], spans: &Spans) -> Result<()> {
+    match Checker::new(VmConfig { register_count: REG_COUNT as u8 }).check(nodes) {
+        None => Ok(()),
+        Some(errors) => Err(errors.into_iter().map(|e| Diagnostic::from_node(Kind::Semantic, e, spans)).collect()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emitter::Segment;
+
+    use super::*;
+
+    #[test]
+    fn assemble_sources() {
+        let sources = vec![
+            Source::from_text("$start = 0x1000\n"),
+            Source::from_text("#base $start\n#entry main\n:main\n    MOV   r1, &main\n    HALT\n"),
+        ];
+
+        let r = assemble(&sources, &Options::new());
+
+        assert!(r.is_ok(), "Expected Ok(...), got {:?}", r.err());
+        let image = r.unwrap();
+        assert_eq!(Container {
+            entry: Some(0x1000),
+            registers: 2,
+            segments: vec![Segment { origin: 0x1000, bytes: vec![3, 1, 0, 0, 0, 0, 0x10, 0, 1, 0, 0, 0] }],
+        }, image.container);
+        assert_eq!(vec![0x1000..0x1000, 0x1000..0x1000, 0x1000..0x1000, 0x1000..0x1008, 0x1008..0x100c], image.ranges);
+        assert_eq!(Some(0x1000), image.addresses.get("1:main").map(Address::absolute));
+    }
+
+    #[test]
+    fn assemble_text_sources_local_labels() {
+        let sources = vec![
+            Source::from_text("#extern helper\n:loop\n    CALL  &helper\n    J     @loop\n"),
+            Source::from_text("#global helper\n:helper\n    RET\n:loop\n    J     @loop\n"),
+        ];
+
+        let r = assemble(&sources, &Options::new());
+
+        assert!(r.is_ok(), "Expected Ok(...), got {:?}", r.err());
+        let image = r.unwrap();
+        assert_eq!(Some(0), image.addresses.get("0:loop").map(Address::absolute));
+        assert_eq!(Some(16), image.addresses.get("helper").map(Address::absolute));
+        assert_eq!(Some(20), image.addresses.get("1:loop").map(Address::absolute));
+    }
+
+    #[test]
+    fn stages() {
+        let sources = vec![Source::from_text(":a\n    J     @a\n")];
+
+        let tokens = tokens(&sources);
+        assert_eq!(Ok(5), tokens.map(|t| t[0].len()));
+
+        let parsed = parse(&sources);
+        assert!(parsed.is_ok(), "Expected Ok(...), got {:?}", parsed.err());
+        let parsed = parsed.unwrap();
+        assert_eq!(2, parsed.nodes.len());
+
+        let resolved = parsed.resolve(&Options::new());
+        assert!(resolved.is_ok(), "Expected Ok(...), got {:?}", resolved.err());
+        assert_eq!(Some(0), resolved.unwrap().addresses.get("0:a").map(Address::absolute));
+    }
+
+    #[test]
+    fn diagnostics() {
+        let r = assemble(&[Source::from_text("    MOV   r0,\n")], &Options::new()).err();
+        assert_eq!(Some(Kind::Syntax), r.map(|d| d[0].kind));
+
+        let r = assemble(&[Source::from_text("    MOV   r40, 1\n    MOV   r41, 1\n")], &Options::new()).err();
+        assert_eq!(Some(vec![
+            "Semantic error: r40 is not a valid register".to_string(),
+            "Semantic error: r41 is not a valid register".to_string(),
+        ]), r.map(|d| d.iter().map(Diagnostic::to_string).collect()));
+
+        let r = assemble(&[Source::from_text("#section code\n    HALT\n")], &Options::new()).err();
+        assert_eq!(Some(Kind::Layout), r.map(|d| d[0].kind));
+    }
+
+    #[test]
+    fn diagnostics_position() {
+        let source = Source { file: Some("a.a".to_string()), text: "    HALT\n    FOO\n".to_string() };
+        let r = assemble(&[source], &Options::new()).err();
+        assert_eq!(Some("Syntax error: Invalid mnemonic 'FOO'".to_string()), r.as_ref().map(|d| d[0].to_string()));
+        assert_eq!(Some((Some("a.a".to_string()), Some(Position::new(2, 5)))), r.map(|d| (d[0].file.clone(), d[0].position.clone())));
+
+        let r = assemble(&[Source::from_text("    HALT\n    MOV   r40, 1\n")], &Options::new()).err();
+        assert_eq!(Some((None, Some(Position::new(2, 5)))), r.map(|d| (d[0].file.clone(), d[0].position.clone())));
+
+        let r = assemble(&[Source::from_text("#section code\n    HALT\n")], &Options::new()).err();
+        assert_eq!(Some((None, None)), r.map(|d| (d[0].file.clone(), d[0].position.clone())));
+    }
+}
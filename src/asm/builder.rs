@@ -0,0 +1,353 @@
+use std::fmt;
+
+use crate::constants::{REG_BP, REG_CS, REG_IDT, REG_IR, REG_PC, REG_SP};
+use crate::disassembler::Form;
+use crate::op::Op;
+use crate::parser::{AddressKind, Directive, Instruction, Node};
+use crate::registers;
+
+/// A register, as written in assembly.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Register {
+    R(u8),
+    Pc,
+    Sp,
+    Bp,
+    Cs,
+    Ir,
+    Idt,
+}
+
+pub const R0: Register = Register::R(0);
+pub const R1: Register = Register::R(1);
+pub const R2: Register = Register::R(2);
+pub const R3: Register = Register::R(3);
+pub const R4: Register = Register::R(4);
+pub const R5: Register = Register::R(5);
+pub const R6: Register = Register::R(6);
+pub const R7: Register = Register::R(7);
+pub const R8: Register = Register::R(8);
+pub const R9: Register = Register::R(9);
+pub const R10: Register = Register::R(10);
+pub const R11: Register = Register::R(11);
+pub const R12: Register = Register::R(12);
+pub const R13: Register = Register::R(13);
+pub const R14: Register = Register::R(14);
+pub const R15: Register = Register::R(15);
+pub const R16: Register = Register::R(16);
+pub const R17: Register = Register::R(17);
+pub const R18: Register = Register::R(18);
+pub const R19: Register = Register::R(19);
+pub const R20: Register = Register::R(20);
+pub const R21: Register = Register::R(21);
+pub const R22: Register = Register::R(22);
+pub const R23: Register = Register::R(23);
+pub const R24: Register = Register::R(24);
+pub const R25: Register = Register::R(25);
+pub const R26: Register = Register::R(26);
+pub const R27: Register = Register::R(27);
+pub const R28: Register = Register::R(28);
+pub const R29: Register = Register::R(29);
+pub const R30: Register = Register::R(30);
+pub const R31: Register = Register::R(31);
+pub const PC: Register = Register::Pc;
+pub const SP: Register = Register::Sp;
+pub const BP: Register = Register::Bp;
+pub const CS: Register = Register::Cs;
+pub const IR: Register = Register::Ir;
+pub const IDT: Register = Register::Idt;
+
+impl Register {
+    /// The number of the register, as encoded in the instructions.
+    pub fn number(&self) -> usize {
+        match self {
+            Register::R(r) => *r as usize,
+            Register::Pc => REG_PC,
+            Register::Sp => REG_SP,
+            Register::Bp => REG_BP,
+            Register::Cs => REG_CS,
+            Register::Ir => REG_IR,
+            Register::Idt => REG_IDT,
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // r32 and above have no name, the Checker reports them
+            Register::R(r) => write!(f, "r{}", r),
+            special => write!(f, "{}", registers::name(special.number()).unwrap()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Register(Register),
+    Word(u32),
+    /// `&label` or `@label`.
+    Address(String, AddressKind),
+    /// `[r + w]`.
+    Offset(Register, u32),
+}
+
+/// A word, or a byte for the ops taking one.
+pub fn imm(value: u32) -> Operand {
+    Operand::Word(value)
+}
+
+/// The absolute address of `label`: `&label`.
+pub fn abs(label: &str) -> Operand {
+    Operand::Address(label.to_string(), AddressKind::Absolute)
+}
+
+/// The address of `label` relative to its segment: `@label`.
+pub fn seg(label: &str) -> Operand {
+    Operand::Address(label.to_string(), AddressKind::Segment)
+}
+
+/// The address held by `register` plus `offset`: `[register + offset]`.
+pub fn at(register: Register, offset: u32) -> Operand {
+    Operand::Offset(register, offset)
+}
+
+impl From<Register> for Operand {
+    fn from(register: Register) -> Self {
+        Operand::Register(register)
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operand::Register(r) => write!(f, "{}", r),
+            Operand::Word(w) => write!(f, "{}", w),
+            Operand::Address(label, AddressKind::Absolute) => write!(f, "&{}", label),
+            Operand::Address(label, AddressKind::Segment) => write!(f, "@{}", label),
+            Operand::Offset(r, w) => write!(f, "[{} + {}]", r, w),
+        }
+    }
+}
+
+/// Builds nodes without going through the source text, for code generators:
+///
+/// ```
+/// use tha::builder::{imm, seg, Builder, R0};
+///
+/// let mut builder = Builder::new();
+/// builder.mov(R0, imm(5));
+/// builder.label("loop");
+/// builder.dec(R0);
+/// builder.jne(seg("loop"));
+/// builder.halt();
+/// assert_eq!(5, builder.build().unwrap().len());
+/// ```
+///
+/// Each instruction is checked against the operand forms of the ops of its mnemonic, as the
+/// parser does, and the errors are returned by `build`. The nodes are to be given to the
+/// `AddressResolver` and the `Emitter`, or the `Checker` to validate the registers.
+#[derive(Debug, Default)]
+pub struct Builder {
+    nodes: Vec<Node>,
+    calls: usize,
+    errors: Vec<String>,
+}
+
+type Result<T> = std::result::Result<T, Vec<String>>;
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    pub fn build(self) -> Result<Vec<Node>> {
+        match self.errors.is_empty() {
+            true => Ok(self.nodes),
+            false => Err(self.errors),
+        }
+    }
+
+    pub fn label(&mut self, name: &str) -> &mut Self {
+        self.calls += 1;
+        self.nodes.push(Node::Label(name.to_string()));
+        self
+    }
+
+    pub fn directive(&mut self, directive: Directive) -> &mut Self {
+        self.calls += 1;
+        self.nodes.push(Node::Directive(directive));
+        self
+    }
+
+    pub fn base(&mut self, origin: u32) -> &mut Self {
+        self.directive(Directive::Base(origin))
+    }
+
+    pub fn entry(&mut self, label: &str) -> &mut Self {
+        self.directive(Directive::Entry(label.to_string()))
+    }
+
+    pub fn word(&mut self, label: &str, value: i32) -> &mut Self {
+        self.directive(Directive::Word(label.to_string(), value))
+    }
+
+    pub fn space(&mut self, label: &str, size: u32) -> &mut Self {
+        self.directive(Directive::Space(label.to_string(), size))
+    }
+
+    /// Adds the instruction of `mnemonic` taking the `operands`, or records an error if none does,
+    /// reported at the index of the call among all the calls adding a node.
+    pub fn instruction(&mut self, mnemonic: &str, operands: &[Operand]) -> &mut Self {
+        let call = self.calls;
+        self.calls += 1;
+        let ops: Vec<Op> = (0..=255u8)
+            .filter(|b| Op::from(*b).bytecode() == *b)
+            .map(Op::from)
+            .filter(|op| op.mnemonic() == mnemonic)
+            .collect();
+
+        if ops.is_empty() {
+            self.errors.push(format!("Invalid mnemonic '{}' at call {}", mnemonic, call));
+            return self;
+        }
+
+        match ops.into_iter().find_map(|op| instruction(op, operands)) {
+            Some(instruction) => self.nodes.push(Node::Instruction(instruction)),
+            None => self.errors.push(format!(
+                "Invalid operands for {} at call {}: {}",
+                mnemonic,
+                call,
+                operands.iter().map(Operand::to_string).collect::<Vec<String>>().join(", ")
+            )),
+        }
+        self
+    }
+}
+
+macro_rules! mnemonics {
+    (@method $name:ident $mnemonic:literal (..)) => {
+        #[doc = concat!("`", $mnemonic, "` with the operands of any of its forms.")]
+        pub fn $name(&mut self, operands: &[Operand]) -> &mut Self {
+            self.instruction($mnemonic, operands)
+        }
+    };
+    (@method $name:ident $mnemonic:literal ($($operand:ident),*)) => {
+        pub fn $name(&mut self, $($operand: impl Into<Operand>),*) -> &mut Self {
+            self.instruction($mnemonic, &[$($operand.into()),*])
+        }
+    };
+    ($($name:ident $mnemonic:literal $operands:tt;)*) => {
+        impl Builder {
+            $(mnemonics!(@method $name $mnemonic $operands);)*
+        }
+    };
+}
+
+crate::op::for_each_mnemonic!(mnemonics);
+
+/// The instruction of `op` taking the `operands`, if its form allows them. As with the parser,
+/// only `MOV` takes a segment relative address along with a register.
+fn instruction(op: Op, operands: &[Operand]) -> Option<Instruction> {
+    use Operand::{Address, Offset, Register as R, Word};
+
+    let r = |r: &Register| r.to_string();
+    match (Form::of(op), operands) {
+        (Form::None, []) => Some(Instruction::I(op)),
+        (Form::B, [Word(b)]) if *b <= 255 => Some(Instruction::IB(op, *b as u8)),
+        (Form::R, [R(r1)]) => Some(Instruction::IR(op, r(r1))),
+        (Form::Rr, [R(r1), R(r2)]) => Some(Instruction::IRR(op, r(r1), r(r2))),
+        (Form::Rrr, [R(r1), R(r2), R(r3)]) => Some(Instruction::IRRR(op, r(r1), r(r2), r(r3))),
+        (Form::Rw, [R(r1), Word(w)]) => Some(Instruction::IRW(op, r(r1), *w)),
+        (Form::Rw, [R(r1), Address(label, kind)]) if *kind == AddressKind::Absolute || op == Op::MovRW => {
+            Some(Instruction::IRA(op, r(r1), label.to_owned(), kind.clone()))
+        }
+        (Form::W, [Word(w)]) => Some(Instruction::IW(op, *w)),
+        (Form::Wr, [Word(w), R(r1)]) => Some(Instruction::IRW(op, r(r1), *w)),
+        (Form::Wr, [Address(label, AddressKind::Absolute), R(r1)]) => {
+            Some(Instruction::IRA(op, r(r1), label.to_owned(), AddressKind::Absolute))
+        }
+        (Form::Ro, [R(r1), Offset(r2, w)]) => Some(Instruction::IRRW(op, r(r1), r(r2), *w)),
+        (Form::A, [Address(label, AddressKind::Absolute)]) => Some(Instruction::IA(op, label.to_owned(), AddressKind::Absolute)),
+        (Form::S, [Address(label, AddressKind::Segment)]) => Some(Instruction::IA(op, label.to_owned(), AddressKind::Segment)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::address_resolver::AddressResolver;
+    use crate::emitter::Emitter;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    use super::*;
+
+    fn parse(source: &str) -> Vec<Node> {
+        let (mut nodes, mut symbols) = (vec![], HashMap::new());
+        Parser::from_lexer(&mut Lexer::from_text(source), &mut nodes, &mut symbols).parse().unwrap();
+        nodes
+    }
+
+    #[test]
+    fn build_as_parsed() {
+        let mut builder = Builder::new();
+        builder.base(0x100).entry("start");
+        builder.label("start");
+        builder.mov(R0, imm(5)).mov(R1, abs("data")).mov(R2, seg("data")).mov(R3, R0);
+        builder.label("loop");
+        builder.load(R4, at(R1, 4)).load(R5, abs("data")).stor(imm(0x1000), R4).stor(abs("data"), R5);
+        builder.push(&[R0.into(), R1.into(), R2.into()]).push(&[imm(1)]).pop(&[R0.into()]);
+        builder.add(R0, imm(1)).cmp(SP, BP).int(imm(3)).call(R7).dec(R0);
+        builder.jne(seg("loop")).j(abs("loop")).call(seg("loop")).halt();
+        builder.word("data", -1);
+
+        let r = builder.build();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(parse("#base 0x100\n#entry start\n:start\n\
+                          MOV r0, 5\nMOV r1, &data\nMOV r2, @data\nMOV r3, r0\n:loop\n\
+                          LOAD r4, [r1 + 4]\nLOAD r5, &data\nSTOR 0x1000, r4\nSTOR &data, r5\n\
+                          PUSH r0, r1, r2\nPUSH 1\nPOP r0\n\
+                          ADD r0, 1\nCMP sp, bp\nINT 3\nCALL r7\nDEC r0\n\
+                          JNE @loop\nJ &loop\nCALL @loop\nHALT\n\
+                          #word data 0xffffffff\n"), r.unwrap());
+    }
+
+    #[test]
+    fn build_invalid() {
+        let mut builder = Builder::new();
+        builder.halt();
+        builder.mov(imm(5), R0);
+        builder.load(R0, seg("data"));
+        builder.int(imm(256));
+        builder.push(&[R0.into(), R1.into(), R2.into(), R3.into()]);
+        builder.instruction("FOO", &[]);
+
+        assert_eq!(Err(vec![
+            "Invalid operands for MOV at call 1: 5, r0".to_string(),
+            "Invalid operands for LOAD at call 2: r0, @data".to_string(),
+            "Invalid operands for INT at call 3: 256".to_string(),
+            "Invalid operands for PUSH at call 4: r0, r1, r2, r3".to_string(),
+            "Invalid mnemonic 'FOO' at call 5".to_string(),
+        ]), builder.build());
+    }
+
+    #[test]
+    fn emit() {
+        let mut builder = Builder::new();
+        builder.label("loop");
+        builder.mov(R0, imm(5));
+        builder.jne(seg("loop"));
+
+        let nodes = builder.build().unwrap();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+        let (segments, _) = Emitter::new(&nodes, &addresses).emit();
+
+        let expected = parse(":loop\n    MOV   r0, 5\n    JNE   @loop\n");
+        let expected_addresses = AddressResolver::new(&expected).resolve().unwrap();
+        assert_eq!(Emitter::new(&expected, &expected_addresses).emit().0, segments);
+    }
+}
@@ -1,40 +1,29 @@
-use std::collections::HashMap;
-
-use crate::parser::{Instruction, Node};
-use crate::constants::{REG_PC, REG_SP, REG_CS, REG_IR, REG_IDT, REG_BP};
+use crate::parser::{Instruction, Node, NodeError};
+use crate::registers;
 
 pub struct VmConfig {
     pub register_count: u8,
 }
 
 pub struct Checker {
-    registers: HashMap<String, usize>,
+    register_count: usize,
 }
 
 impl Checker {
     pub fn new(vm_config: VmConfig) -> Checker {
-        let mut registers = HashMap::new();
-        registers.insert("pc".to_string(), REG_PC);
-        registers.insert("sp".to_string(), REG_SP);
-        registers.insert("bp".to_string(), REG_BP);
-        registers.insert("cs".to_string(), REG_CS);
-        registers.insert("ir".to_string(), REG_IR);
-        registers.insert("idt".to_string(), REG_IDT);
-        for r in 0..vm_config.register_count as usize {
-            registers.insert(format!("r{}", r).to_string(), r);
-        }
         Checker {
-            registers,
+            register_count: vm_config.register_count as usize,
         }
     }
 
-    pub fn check(&self, nodes: &Vec<Node>) -> Option<Vec<String>> {
-        let errors: Vec<String> = nodes.iter()
-            .flat_map(|node| match node {
-                Node::Instruction(Instruction::IR(_, r)) => self.check_register_is_valid(vec![r]),
-                Node::Instruction(Instruction::IRR(_, r1, r2)) => self.check_register_is_valid(vec![r1, r2]),
-                Node::Instruction(Instruction::IRRR(_, r1, r2, r3)) => self.check_register_is_valid(vec![r1, r2, r3]),
-                Node::Instruction(Instruction::IRW(_, r, _)) => self.check_register_is_valid(vec![r]),
+    pub fn check(&self, nodes: &[Node]) -> Option<Vec<NodeError>> {
+        let errors: Vec<NodeError> = nodes.iter()
+            .enumerate()
+            .flat_map(|(index, node)| match node {
+                Node::Instruction(Instruction::IR(_, r)) => self.check_register_is_valid(index, vec![r]),
+                Node::Instruction(Instruction::IRR(_, r1, r2)) => self.check_register_is_valid(index, vec![r1, r2]),
+                Node::Instruction(Instruction::IRRR(_, r1, r2, r3)) => self.check_register_is_valid(index, vec![r1, r2, r3]),
+                Node::Instruction(Instruction::IRW(_, r, _)) => self.check_register_is_valid(index, vec![r]),
                 _ => vec![],
             }).collect();
 
@@ -45,10 +34,10 @@ impl Checker {
         }
     }
 
-    fn check_register_is_valid(&self, registers: Vec<&String>) -> Vec<String> {
+    fn check_register_is_valid(&self, index: usize, registers: Vec<&String>) -> Vec<NodeError> {
         registers.iter()
-            .filter(|r| !self.registers.contains_key(**r))
-            .map(|r| format!("{} is not a valid register", r))
+            .filter(|r| registers::number_among(r, self.register_count).is_none())
+            .map(|r| NodeError { index, name: r.to_string(), message: format!("{} is not a valid register", r) })
             .collect()
     }
 }
@@ -56,7 +45,7 @@ impl Checker {
 #[cfg(test)]
 mod tests {
     use crate::op::Op;
-    use crate::parser::{Instruction, Node};
+    use crate::parser::{Instruction, Node, NodeError};
 
     use super::*;
 
@@ -77,13 +66,18 @@ mod tests {
 
     #[test]
     fn test_register_invalid_rr() {
-        let nodes = vec![Node::Instruction(Instruction::IRR(Op::MovRR, "r32".to_string(), "r33".to_string()))];
+        let nodes = vec![
+            Node::Instruction(Instruction::I(Op::Nop)),
+            Node::Instruction(Instruction::IRR(Op::MovRR, "r32".to_string(), "r33".to_string())),
+        ];
 
         let checker = Checker::new(VM_CONFIG);
         let result = checker.check(&nodes);
 
-        assert_eq!(true, result.is_some());
-        assert_eq!(2, result.unwrap().len());
+        assert_eq!(Some(vec![
+            NodeError { index: 1, name: "r32".to_string(), message: "r32 is not a valid register".to_string() },
+            NodeError { index: 1, name: "r33".to_string(), message: "r33 is not a valid register".to_string() },
+        ]), result);
     }
 
     #[test]
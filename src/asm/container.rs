@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+
+use crate::address_resolver::Address;
+use crate::emitter::Segment;
+use crate::object::Reader;
+use crate::parser::{Directive, Instruction, Node};
+
+/// A program ready to be loaded: its segments, where execution starts and how many general
+/// purpose registers it needs. It is written by the `thm` format so that loaders can validate it:
+///
+/// ```text
+/// magic:     "THMI"
+/// version:   u8 (1)
+/// registers: u8, the number of general purpose registers used (r0 to r<registers - 1>)
+/// entry:     u8 1 if there is an entry point (0 otherwise), u32 its address
+/// segments:  u32 count, then for each segment: u32 load address, u32 length, the bytes
+/// crc:       u32, the CRC-32 (IEEE) of all the previous bytes
+/// ```
+///
+/// All numbers are big-endian.
+#[derive(Debug, PartialEq, Default)]
+pub struct Container {
+    pub entry: Option<u32>,
+    pub registers: u8,
+    pub segments: Vec<Segment>,
+}
+
+pub const MAGIC: &[u8; 4] = b"THMI";
+pub const VERSION: u8 = 1;
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Container {
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.push(self.registers);
+        bytes.push(self.entry.is_some() as u8);
+        bytes.extend_from_slice(&self.entry.unwrap_or(0).to_be_bytes());
+        bytes.extend_from_slice(&(self.segments.len() as u32).to_be_bytes());
+        for segment in self.segments.iter() {
+            bytes.extend_from_slice(&segment.origin.to_be_bytes());
+            bytes.extend_from_slice(&(segment.bytes.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&segment.bytes);
+        }
+        bytes.extend_from_slice(&crc32(&bytes).to_be_bytes());
+
+        out.write_all(bytes.as_slice()).map_err(|e| e.to_string())
+    }
+
+    pub fn from_file(file_path: &str) -> Result<Container> {
+        match fs::read(file_path) {
+            Ok(bytes) => Self::read(&bytes).map_err(|e| format!("{} in {}", e, file_path)),
+            Err(e) => Err(format!("Cannot read {}: {}", file_path, e)),
+        }
+    }
+
+    /// Reads a container, checking its magic number, version and CRC.
+    pub fn read(bytes: &[u8]) -> Result<Container> {
+        if bytes.len() < 4 || &bytes[..4] != MAGIC {
+            return Err("Not a thm image".to_string());
+        }
+        let (content, crc) = match bytes.len().checked_sub(4) {
+            Some(length) if length >= 5 => bytes.split_at(length),
+            _ => return Err("Unexpected end of file".to_string()),
+        };
+        let crc = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+        if crc != crc32(content) {
+            return Err(format!("Invalid CRC 0x{:08x}, expected 0x{:08x}", crc, crc32(content)));
+        }
+
+        let mut reader = Reader { bytes: &content[4..] };
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported thm image version {}", version));
+        }
+        let registers = reader.u8()?;
+        let has_entry = reader.u8()? != 0;
+        let entry = reader.u32()?;
+        let mut segments = vec![];
+        for _ in 0..reader.u32()? {
+            let origin = reader.u32()?;
+            let length = reader.u32()? as usize;
+            segments.push(Segment { origin, bytes: reader.take(length)?.to_vec() });
+        }
+        if !reader.bytes.is_empty() {
+            return Err("Unexpected data at the end of the thm image".to_string());
+        }
+
+        Ok(Container {
+            entry: if has_entry { Some(entry) } else { None },
+            registers,
+            segments,
+        })
+    }
+}
+
+/// The absolute address of the label given by `#entry`, if any.
+pub fn entry(nodes: &[Node], addresses: &HashMap<String, Address>) -> Option<u32> {
+    nodes.iter()
+        .find_map(|node| match node {
+            Node::Directive(Directive::Entry(label)) => addresses.get(label),
+            _ => None,
+        })
+        .map(|address| address.absolute())
+}
+
+/// The number of general purpose registers needed to run `nodes`, i.e. one more than the highest
+/// `r<n>` they use.
+pub fn registers(nodes: &[Node]) -> u8 {
+    nodes.iter()
+        .flat_map(|node| match node {
+            Node::Instruction(Instruction::IR(_, r))
+            | Node::Instruction(Instruction::IRA(_, r, _, _))
+            | Node::Instruction(Instruction::IRW(_, r, _)) => vec![r],
+            Node::Instruction(Instruction::IRR(_, r1, r2))
+            | Node::Instruction(Instruction::IRRW(_, r1, r2, _)) => vec![r1, r2],
+            Node::Instruction(Instruction::IRRR(_, r1, r2, r3)) => vec![r1, r2, r3],
+            _ => vec![],
+        })
+        .filter_map(|r| r.strip_prefix('r').and_then(|n| n.parse::<u8>().ok()))
+        .map(|n| n.saturating_add(1))
+        .max()
+        .unwrap_or(0)
+}
+
+/// The CRC-32 used by zlib and PNG (reflected polynomial 0xedb88320).
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, b| {
+        (0..8).fold(crc ^ *b as u32, |crc, _| (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address_resolver::AddressResolver;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    use super::*;
+
+    #[test]
+    fn write_read() {
+        let container = Container {
+            entry: Some(0x1004),
+            registers: 4,
+            segments: vec![Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4, 5, 6, 7, 8] }],
+        };
+
+        let mut out = vec![];
+        let r = container.write(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(vec![
+            b'T', b'H', b'M', b'I', 1, 4, 1, 0x00, 0x00, 0x10, 0x04,
+            0, 0, 0, 1, 0x00, 0x00, 0x10, 0x00, 0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8,
+        ], out[..out.len() - 4].to_vec());
+        assert_eq!(crc32(&out[..out.len() - 4]).to_be_bytes(), out[out.len() - 4..]);
+        assert_eq!(Ok(container), Container::read(&out));
+    }
+
+    #[test]
+    fn read_invalid_crc() {
+        let mut out = vec![];
+        Container::default().write(&mut out).unwrap();
+        out[5] = 1;
+
+        let r = Container::read(&out);
+
+        assert_eq!(true, matches!(&r, Err(e) if e.starts_with("Invalid CRC")), "Expected Err(Invalid CRC...), got {:?}", r);
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+    }
+
+    #[test]
+    fn entry_and_registers() {
+        let mut lexer = Lexer::from_text("#entry start\n    NOP\n:start\n    MOV r3, 1\n    ADD r1, r0\n    PUSH sp\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse().unwrap();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+
+        assert_eq!(Some(4), entry(&nodes, &addresses));
+        assert_eq!(4, registers(&nodes));
+    }
+}
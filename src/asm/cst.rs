@@ -0,0 +1,167 @@
+use crate::lexer::{Lexer, SyntaxToken, Token, Trivia};
+
+/// What a statement is, after its first token.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    /// A line with nothing but whitespace or a comment.
+    Empty,
+    Directive,
+    Instruction,
+    Label,
+    Section,
+    Variable,
+    /// A statement the parser rejects.
+    Invalid,
+}
+
+/// A statement: its syntax tokens up to and including the `Eol` ending its line, or the one ending
+/// the line of the `}` closing its block.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Statement {
+    pub kind: Kind,
+    pub tokens: Vec<SyntaxToken>,
+}
+
+impl Statement {
+    /// The text of the statement as written, with the trivia before each of its tokens.
+    pub fn text(&self) -> String {
+        self.tokens.iter()
+            .flat_map(|t| t.leading.iter().map(Trivia::text).chain(std::iter::once(t.text.as_str())))
+            .collect()
+    }
+}
+
+/// The concrete syntax tree of a source: its statements, each of them holding its tokens along
+/// with their trivia, and the trivia at the end. Nothing of the source is lost, so that it can be
+/// written back as is or with changes to its trivia only. The parser derives the nodes from its
+/// tokens, see `Parser::from_cst`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cst {
+    pub file: Option<String>,
+    pub statements: Vec<Statement>,
+    pub trailing: Vec<Trivia>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Cst {
+    pub fn from_lexer(lexer: &mut Lexer) -> Result<Cst> {
+        let file = lexer.file().map(|f| f.to_string());
+        let (tokens, trailing) = lexer.syntax_tokens()?;
+
+        let mut statements = vec![];
+        let mut statement: Vec<SyntaxToken> = vec![];
+        let mut depth = 0usize;
+        for token in tokens {
+            match token.token {
+                Token::LBrace(_) => depth += 1,
+                Token::RBrace(_) => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+            let end = depth == 0 && matches!(token.token, Token::Eol(_));
+            statement.push(token);
+            if end {
+                statements.push(Statement { kind: kind(&statement), tokens: statement });
+                statement = vec![];
+            }
+        }
+        if !statement.is_empty() {
+            statements.push(Statement { kind: kind(&statement), tokens: statement });
+        }
+
+        Ok(Cst { file, statements, trailing })
+    }
+
+    /// The syntax tokens of all the statements.
+    pub fn tokens(&self) -> impl Iterator<Item = &SyntaxToken> {
+        self.statements.iter().flat_map(|s| s.tokens.iter())
+    }
+
+    /// The source, as lexed.
+    pub fn text(&self) -> String {
+        let mut text: String = self.statements.iter().map(Statement::text).collect();
+        self.trailing.iter().for_each(|t| text.push_str(t.text()));
+        text
+    }
+}
+
+fn kind(tokens: &[SyntaxToken]) -> Kind {
+    match tokens.first().map(|t| &t.token) {
+        None | Some(Token::Eol(_)) => Kind::Empty,
+        Some(Token::Directive(_, _)) => Kind::Directive,
+        Some(Token::Op(_, _)) => Kind::Instruction,
+        Some(Token::Label(_, _)) => Kind::Label,
+        Some(Token::Section(_, _)) => Kind::Section,
+        Some(Token::Variable(_, _)) => Kind::Variable,
+        Some(_) => Kind::Invalid,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    use crate::parser::Parser;
+
+    use super::*;
+
+    #[test]
+    fn statements() {
+        let r = Cst::from_lexer(&mut Lexer::from_text("// c\n$v = 1\n#struct s {\n  x: word // x\n}\n:l\n  MOV r0, $v \n, \n  "));
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let cst = r.unwrap();
+        let statements: Vec<(Kind, String)> = cst.statements.iter().map(|s| (s.kind, s.text())).collect();
+        assert_eq!(vec![
+            (Kind::Empty, "// c\n".to_string()),
+            (Kind::Variable, "$v = 1\n".to_string()),
+            (Kind::Directive, "#struct s {\n  x: word // x\n}\n".to_string()),
+            (Kind::Label, ":l\n".to_string()),
+            (Kind::Instruction, "  MOV r0, $v \n".to_string()),
+            (Kind::Invalid, ", \n".to_string()),
+        ], statements);
+        assert_eq!(vec![Trivia::Whitespace(crate::lexer::Position::new(9, 1), "  ".to_string())], cst.trailing);
+    }
+
+    #[test]
+    fn lossless_sources() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut files: Vec<_> = fs::read_dir(root.join("examples")).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.push(root.join("src/common/rom.a"));
+
+        for file in files {
+            let source = fs::read_to_string(&file).unwrap();
+            let cst = Cst::from_lexer(&mut Lexer::from_text(&source)).unwrap();
+            assert_eq!(source, cst.text(), "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn lossless_text() {
+        let source = "\t:a  // \"x\"\r\n  #word w 0b1_0\n#image i \"a // b.ppm\"   \n\n// end";
+        let r = Cst::from_lexer(&mut Lexer::from_text(source));
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        assert_eq!(source, r.unwrap().text());
+    }
+
+    #[test]
+    fn derive_nodes() {
+        let source = "$v = 4\n#base 0x100 // origin\n:start\n    MOV r0, $v // load\n    J @start\n";
+
+        let (mut nodes, mut symbols) = (vec![], HashMap::new());
+        Parser::from_lexer(&mut Lexer::from_text(source), &mut nodes, &mut symbols).parse().unwrap();
+
+        let cst = Cst::from_lexer(&mut Lexer::from_text(source)).unwrap();
+        let (mut derived, mut derived_symbols) = (vec![], HashMap::new());
+        let r = Parser::from_cst(&cst, &mut derived, &mut derived_symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(nodes, derived);
+        assert_eq!(symbols, derived_symbols);
+    }
+}
@@ -0,0 +1,187 @@
+use std::convert::TryFrom;
+use std::io::Write;
+use std::ops::Range;
+
+use crate::object::Reader;
+use crate::parser::{Node, Span};
+
+/// Maps the address of each emitted instruction to the file, line and column it comes from.
+///
+/// It is written as, all numbers being big-endian:
+///
+/// ```text
+/// magic:   "THDM"
+/// version: u8 (1)
+/// files:   u16 count, then for each file a u16 length and the UTF-8 path (empty when unknown)
+/// entries: u32 count, then for each instruction, by increasing address:
+///          u32 address, u16 file index, u16 line, u16 column
+/// ```
+#[derive(Debug, PartialEq)]
+pub struct DebugMap {
+    files: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub address: u32,
+    pub file: u16,
+    pub line: u16,
+    pub column: u16,
+}
+
+pub const MAGIC: &[u8; 4] = b"THDM";
+pub const VERSION: u8 = 1;
+
+type Result<T> = std::result::Result<T, String>;
+
+impl DebugMap {
+    /// Builds the map from the nodes, their spans and the address ranges they were emitted at.
+    pub fn new(nodes: &[Node], spans: &[Span], ranges: &[Range<u32>]) -> DebugMap {
+        let mut files: Vec<String> = vec![];
+        let mut entries = vec![];
+
+        for ((node, span), range) in nodes.iter().zip(spans.iter()).zip(ranges.iter()) {
+            if !matches!(node, Node::Instruction(_)) {
+                continue;
+            }
+            let file = span.file.clone().unwrap_or_default();
+            let index = match files.iter().position(|f| *f == file) {
+                Some(index) => index,
+                None => {
+                    files.push(file);
+                    files.len() - 1
+                }
+            };
+            entries.push(Entry {
+                address: range.start,
+                file: index as u16,
+                line: span.position.line(),
+                column: span.position.column(),
+            });
+        }
+        entries.sort_by_key(|e| e.address);
+
+        DebugMap { files, entries }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let files = u16::try_from(self.files.len()).map_err(|_| format!("Too many files for the debug map: {}", self.files.len()))?;
+
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&files.to_be_bytes());
+        for file in self.files.iter() {
+            let length = u16::try_from(file.len()).map_err(|_| format!("Path too long for the debug map: {}", file))?;
+            bytes.extend_from_slice(&length.to_be_bytes());
+            bytes.extend_from_slice(file.as_bytes());
+        }
+        bytes.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in self.entries.iter() {
+            bytes.extend_from_slice(&entry.address.to_be_bytes());
+            bytes.extend_from_slice(&entry.file.to_be_bytes());
+            bytes.extend_from_slice(&entry.line.to_be_bytes());
+            bytes.extend_from_slice(&entry.column.to_be_bytes());
+        }
+
+        out.write_all(bytes.as_slice()).map_err(|e| e.to_string())
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<DebugMap> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err("Not a debug map".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported debug map version {}", version));
+        }
+
+        let mut files = vec![];
+        for _ in 0..reader.u16()? {
+            files.push(reader.string()?);
+        }
+        let mut entries = vec![];
+        for _ in 0..reader.u32()? {
+            let entry = Entry { address: reader.u32()?, file: reader.u16()?, line: reader.u16()?, column: reader.u16()? };
+            if entry.file as usize >= files.len() {
+                return Err(format!("Invalid file {}", entry.file));
+            }
+            entries.push(entry);
+        }
+        if !reader.bytes.is_empty() {
+            return Err("Unexpected data at the end of the debug map".to_string());
+        }
+
+        Ok(DebugMap { files, entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::Position;
+    use crate::op::Op;
+    use crate::parser::Instruction;
+
+    use super::*;
+
+    #[test]
+    fn new() {
+        let nodes = vec![
+            Node::Label("label".to_string()),
+            Node::Instruction(Instruction::I(Op::Nop)),
+            Node::Instruction(Instruction::I(Op::Halt)),
+        ];
+        let spans = vec![
+            Span { file: Some("a.a".to_string()), position: Position::new(1, 1) },
+            Span { file: Some("a.a".to_string()), position: Position::new(2, 5) },
+            Span { file: Some("b.a".to_string()), position: Position::new(3, 1) },
+        ];
+        let ranges = vec![0x1000..0x1000, 0x1000..0x1004, 0x0..0x4];
+
+        let map = DebugMap::new(&nodes, &spans, &ranges);
+
+        assert_eq!(vec!["a.a".to_string(), "b.a".to_string()], map.files);
+        assert_eq!(vec![
+            Entry { address: 0x0, file: 1, line: 3, column: 1 },
+            Entry { address: 0x1000, file: 0, line: 2, column: 5 },
+        ], map.entries);
+    }
+
+    #[test]
+    fn write() {
+        let nodes = vec![Node::Instruction(Instruction::I(Op::Nop))];
+        let spans = vec![Span { file: Some("a.a".to_string()), position: Position::new(2, 5) }];
+        let ranges = vec![Range { start: 0x1000, end: 0x1004 }];
+
+        let mut out = vec![];
+        let r = DebugMap::new(&nodes, &spans, &ranges).write(&mut out);
+
+        assert!(r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(vec![
+            b'T', b'H', b'D', b'M', 1,
+            0, 1, 0, 3, b'a', b'.', b'a',
+            0, 0, 0, 1, 0x00, 0x00, 0x10, 0x00, 0, 0, 0, 2, 0, 5,
+        ], out);
+        assert_eq!(Ok(DebugMap::new(&nodes, &spans, &ranges)), DebugMap::read(&out));
+    }
+
+    #[test]
+    fn write_long_path() {
+        let nodes = vec![Node::Instruction(Instruction::I(Op::Nop))];
+        let spans = vec![Span { file: Some("a".repeat(0x10000)), position: Position::new(1, 1) }];
+        let ranges = vec![Range { start: 0, end: 4 }];
+
+        let r = DebugMap::new(&nodes, &spans, &ranges).write(&mut vec![]);
+
+        assert_eq!(Err(format!("Path too long for the debug map: {}", "a".repeat(0x10000))), r);
+    }
+
+    #[test]
+    fn read_invalid_file() {
+        let map = DebugMap::read(b"THDM\x01\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\x01\x00\x01");
+
+        assert_eq!(Err("Invalid file 0".to_string()), map);
+    }
+}
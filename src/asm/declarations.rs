@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::symbols::{Kind, Symbol};
+
+/// Declares the symbols of the symbol map as constants of the host tools' languages, so that they
+/// do not hard-code the addresses of an assembled program:
+///
+/// ```text
+/// #define START 0x00001000         pub const START: u32 = 0x00001000;
+/// #define BUFFER 0x00001008        pub const BUFFER: u32 = 0x00001008;
+/// #define BUFFER_SIZE 8            pub const BUFFER_SIZE: u32 = 8;
+/// #define V 0x00000010             pub const V: u32 = 0x00000010;
+/// ```
+///
+/// Names are upper-cased and their characters other than letters, digits and `_` replaced by `_`.
+/// Variables lose their `$`. The data directives also declare their size in bytes. A label local
+/// to several files is prefixed with the name of each file defining it.
+pub struct Declarations {
+    constants: Vec<(String, String)>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Declarations {
+    pub fn new(symbols: &[Symbol]) -> Result<Declarations> {
+        let mut count: HashMap<String, usize> = HashMap::new();
+        for symbol in symbols {
+            for name in names(&identifier(&symbol.name), &symbol.kind) {
+                *count.entry(name).or_insert(0) += 1;
+            }
+        }
+
+        let mut constants = vec![];
+        let mut declared: HashMap<String, &str> = HashMap::new();
+        for symbol in symbols {
+            let mut name = identifier(&symbol.name);
+            if names(&name, &symbol.kind).iter().any(|name| count[name] > 1) {
+                if let Some(file) = &symbol.file {
+                    let stem = file.rsplit('/').next().unwrap_or(file);
+                    let stem = stem.split('.').next().unwrap_or(stem);
+                    name = format!("{}_{}", identifier(stem), name);
+                }
+            }
+            let values = vec![format!("0x{:08x}", symbol.address), symbol.size.to_string()];
+            for (name, value) in names(&name, &symbol.kind).into_iter().zip(values) {
+                if let Some(other) = declared.insert(name.clone(), &symbol.name) {
+                    return Err(format!("Constant {} declared for {} and {}", name, other, symbol.name));
+                }
+                constants.push((name, value));
+            }
+        }
+        Ok(Declarations { constants })
+    }
+
+    pub fn write_c_header<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut text = "// Generated by tha, do not edit.\n#ifndef THM_SYMBOLS_H\n#define THM_SYMBOLS_H\n\n".to_string();
+        for (name, value) in self.constants.iter() {
+            text.push_str(&format!("#define {} {}\n", name, value));
+        }
+        text.push_str("\n#endif\n");
+        out.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    pub fn write_rust<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut text = "// Generated by tha, do not edit.\n\n".to_string();
+        for (name, value) in self.constants.iter() {
+            text.push_str(&format!("pub const {}: u32 = {};\n", name, value));
+        }
+        out.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+/// The constants declared for a symbol named `identifier`: its address, and its size for data.
+fn names(identifier: &str, kind: &Kind) -> Vec<String> {
+    match kind {
+        Kind::Word | Kind::Image | Kind::Space => vec![identifier.to_string(), format!("{}_SIZE", identifier)],
+        Kind::Label | Kind::Variable => vec![identifier.to_string()],
+    }
+}
+
+/// An upper-case C and Rust identifier for `name`.
+fn identifier(name: &str) -> String {
+    let identifier: String = name.trim_start_matches('$')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    match identifier.chars().next() {
+        Some(c) if c.is_ascii_digit() => format!("_{}", identifier),
+        None => "_".to_string(),
+        _ => identifier,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address_resolver::AddressResolver;
+    use crate::lexer::Lexer;
+    use crate::parser::{Parser, Spans};
+    use crate::symbols::SymbolMap;
+
+    use super::*;
+
+    fn symbols() -> Vec<Symbol> {
+        vec![
+            Symbol { address: 0x10, kind: Kind::Variable, size: 0, name: "$v".to_string(), file: None },
+            Symbol { address: 0x1000, kind: Kind::Label, size: 0, name: "loop".to_string(), file: Some("src/a.a".to_string()) },
+            Symbol { address: 0x1004, kind: Kind::Word, size: 4, name: "data".to_string(), file: Some("src/a.a".to_string()) },
+            Symbol { address: 0x1008, kind: Kind::Label, size: 0, name: "loop".to_string(), file: Some("src/b.a".to_string()) },
+        ]
+    }
+
+    #[test]
+    fn write_c_header() {
+        let mut out = vec![];
+        let r = Declarations::new(&symbols()).unwrap().write_c_header(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "// Generated by tha, do not edit.\n\
+                        #ifndef THM_SYMBOLS_H\n\
+                        #define THM_SYMBOLS_H\n\
+                        \n\
+                        #define V 0x00000010\n\
+                        #define A_LOOP 0x00001000\n\
+                        #define DATA 0x00001004\n\
+                        #define DATA_SIZE 4\n\
+                        #define B_LOOP 0x00001008\n\
+                        \n\
+                        #endif\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn write_rust() {
+        let mut out = vec![];
+        let r = Declarations::new(&symbols()[..3]).unwrap().write_rust(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "// Generated by tha, do not edit.\n\
+                        \n\
+                        pub const V: u32 = 0x00000010;\n\
+                        pub const LOOP: u32 = 0x00001000;\n\
+                        pub const DATA: u32 = 0x00001004;\n\
+                        pub const DATA_SIZE: u32 = 4;\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn size_collisions() {
+        let symbols = vec![
+            Symbol { address: 0x1000, kind: Kind::Word, size: 4, name: "data".to_string(), file: Some("a.a".to_string()) },
+            Symbol { address: 0x10, kind: Kind::Variable, size: 0, name: "$data_size".to_string(), file: Some("b.a".to_string()) },
+        ];
+
+        let r = Declarations::new(&symbols).map(|declarations| declarations.constants);
+
+        assert_eq!(Ok(vec![
+            ("A_DATA".to_string(), "0x00001000".to_string()),
+            ("A_DATA_SIZE".to_string(), "4".to_string()),
+            ("B_DATA_SIZE".to_string(), "0x00000010".to_string()),
+        ]), r);
+    }
+
+    #[test]
+    fn duplicate_constants() {
+        let mut lexer = Lexer::from_text("#struct point { x: word, y: word }\n#space point $point.size\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).parse().unwrap();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+
+        let r = Declarations::new(&SymbolMap::new(&nodes, &spans, &addresses, &symbols).symbols());
+
+        assert_eq!(Err("Constant POINT_SIZE declared for point and $point.size".to_string()), r.map(|_| ()));
+    }
+
+    #[test]
+    fn identifiers() {
+        assert_eq!("FRAME_BUFFER", identifier("frame-buffer"));
+        assert_eq!("_1ST", identifier("$1st"));
+    }
+}
@@ -0,0 +1,625 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::emitter::Segment;
+use crate::op::Op;
+use crate::parser::{AddressKind, Directive, Instruction, Node};
+use crate::registers;
+use crate::symbols::{Kind, Symbol};
+
+/// How the operands of an op are written, as parsed by `Parser::parse_instruction`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) enum Form {
+    /// No operand.
+    None,
+    /// `<b>`, in the second byte.
+    B,
+    /// `<r>`, `<r>, <r>` and `<r>, <r>, <r>`, in the bytes 1 to 3.
+    R,
+    Rr,
+    Rrr,
+    /// `<r>, <w>`, the word following the instruction, or `<r>, &<label>`.
+    Rw,
+    /// `<w>`.
+    W,
+    /// `<w>, <r>` or `&<label>, <r>`.
+    Wr,
+    /// `<r>, [<r> + <w>]`.
+    Ro,
+    /// `&<label>`, the word being the absolute address.
+    A,
+    /// `@<label>`, the word being the address relative to the segment.
+    S,
+}
+
+impl Form {
+    /// The form of the operands of `op`, as given by their syntax in `instructions.thi`.
+    pub(crate) fn of(op: Op) -> Form {
+        match op.syntax() {
+            "" => Form::None,
+            "b0" => Form::B,
+            "r0" => Form::R,
+            "r0, r1" => Form::Rr,
+            "r0, r1, r2" => Form::Rrr,
+            "r0, w0" => Form::Rw,
+            "w0" => Form::W,
+            "w0, r0" => Form::Wr,
+            "r0, [r1 + w0]" => Form::Ro,
+            "&0" => Form::A,
+            "@0" => Form::S,
+            syntax => panic!("Unknown syntax '{}' of {:?}", syntax, op),
+        }
+    }
+
+    /// The number of bytes the operands use among the three following the opcode.
+    fn bytes(&self) -> usize {
+        match self {
+            Form::None | Form::W | Form::A | Form::S => 0,
+            Form::B | Form::R | Form::Rw | Form::Wr => 1,
+            Form::Rr | Form::Ro => 2,
+            Form::Rrr => 3,
+        }
+    }
+}
+
+/// The name of the register encoded as `byte`.
+fn register(byte: u8) -> Option<String> {
+    registers::name(byte as usize)
+}
+
+/// What a segment is made of, before the labels are known.
+#[derive(Debug, PartialEq)]
+enum Item {
+    /// An instruction, given by its opcode and operand bytes, and its trailing word if any.
+    Instruction(Op, [u8; 4], Option<u32>),
+    Word(u32),
+    Space(u32),
+}
+
+impl Item {
+    fn size(&self) -> u32 {
+        match self {
+            Item::Instruction(op, _, _) => op.length() as u32,
+            Item::Word(_) => 4,
+            Item::Space(size) => *size,
+        }
+    }
+}
+
+/// Turns segments back into assembly that `tha` assembles to the same bytes.
+///
+/// The bytes are decoded as instructions, using the operand forms the emitter writes: the opcode,
+/// then the registers or the byte operand in the bytes 1 to 3, the unused ones being 0, then the
+/// big-endian word, if any. The bytes that do not decode, and those covered by the `#word`,
+/// `#image` and `#space` of the symbol map, are written as `#word` and `#space`.
+///
+/// The jumps and calls refer to labels, taken from the symbol map or named `l_<address>`; the
+/// instructions taking a word, such as `MOV`, refer to a label only when the symbol map has one at
+/// that address. A jump to an address that is not the start of an instruction or data is written
+/// as `#word`, since it cannot be given a label.
+pub struct Disassembler<'t> {
+    segments: &'t [Segment],
+    symbols: &'t [Symbol],
+    entry: Option<u32>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl<'t> Disassembler<'t> {
+    pub fn new(segments: &'t [Segment]) -> Disassembler<'t> {
+        Disassembler { segments, symbols: &[], entry: None }
+    }
+
+    /// Names the addresses after the labels of `symbols` and decodes their data as such.
+    pub fn with_symbols(mut self, symbols: &'t [Symbol]) -> Self {
+        self.symbols = symbols;
+        self
+    }
+
+    /// Writes an `#entry` for the label at `entry`.
+    pub fn with_entry(mut self, entry: Option<u32>) -> Self {
+        self.entry = entry;
+        self
+    }
+
+    pub fn disassemble(&self) -> Result<Vec<Node>> {
+        Ok(self.lines()?.into_iter().map(|(node, _, _)| node).collect())
+    }
+
+    /// Writes the assembly, each instruction and data being followed by its address and bytes.
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut text = String::new();
+        for (node, address, bytes) in self.lines()? {
+            let line = match &node {
+                Node::Instruction(instruction) => format!("    {}", source(instruction)),
+                Node::Label(name) => format!(":{}", name),
+                Node::Directive(directive) => directive_source(directive),
+            };
+            match bytes {
+                [] => text.push_str(&format!("{}\n", line)),
+                bytes => text.push_str(&format!("{:<29} // {:08x}: {}\n", line, address, bytes.iter()
+                    .take(8)
+                    .map(|b| format!("{:02x}", b))
+                    .collect::<Vec<String>>()
+                    .join(" "))),
+            }
+        }
+        out.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// The nodes, along with the address and the bytes they come from.
+    fn lines(&self) -> Result<Vec<(Node, u32, &'t [u8])>> {
+        let segments: Vec<Vec<(u32, Item)>> = self.segments.iter()
+            .map(|segment| self.decode(segment))
+            .collect::<Result<_>>()?;
+
+        // the addresses a label can be written at, with the index of the segment that has it first
+        let mut boundaries: HashMap<u32, usize> = HashMap::new();
+        for (index, (segment, items)) in self.segments.iter().zip(segments.iter()).enumerate() {
+            let end = segment.origin.wrapping_add(segment.bytes.len() as u32);
+            for address in items.iter().map(|(a, _)| *a).chain(std::iter::once(end)) {
+                boundaries.entry(address).or_insert(index);
+            }
+        }
+
+        let mut labels: HashMap<u32, Vec<String>> = HashMap::new();
+        let mut names: HashSet<String> = HashSet::new();
+        for symbol in self.symbols.iter().filter(|s| s.kind != Kind::Variable && boundaries.contains_key(&s.address)) {
+            let name = match names.contains(&symbol.name) {
+                true => format!("{}_{:08x}", symbol.name, symbol.address),
+                false => symbol.name.to_owned(),
+            };
+            names.insert(name.to_owned());
+            labels.entry(symbol.address).or_default().push(name);
+        }
+        let symbols: HashSet<u32> = labels.keys().copied().collect();
+
+        let targets = segments.iter().zip(self.segments.iter())
+            .flat_map(|(items, segment)| items.iter().filter_map(move |(_, item)| match item {
+                Item::Instruction(op, _, Some(word)) => match Form::of(*op) {
+                    Form::A => Some(*word),
+                    Form::S => Some(segment.origin.wrapping_add(*word)),
+                    _ => None,
+                },
+                _ => None,
+            }))
+            .chain(self.entry);
+        for target in targets {
+            if boundaries.contains_key(&target) && !labels.contains_key(&target) {
+                labels.insert(target, vec![format!("l_{:08x}", target)]);
+            }
+        }
+
+        let mut lines = vec![];
+        if let Some(label) = self.entry.and_then(|entry| labels.get(&entry)) {
+            lines.push((Node::Directive(Directive::Entry(label[0].to_owned())), 0, &[][..]));
+        }
+        for (index, (segment, items)) in self.segments.iter().zip(segments).enumerate() {
+            lines.push((Node::Directive(Directive::Base(segment.origin)), segment.origin, &[][..]));
+            let end = segment.origin.wrapping_add(segment.bytes.len() as u32);
+            let label = |address: u32| match boundaries.get(&address) {
+                Some(i) if *i == index => labels.get(&address),
+                _ => None,
+            };
+
+            for (address, item) in items {
+                let offset = address.wrapping_sub(segment.origin) as usize;
+                let bytes = &segment.bytes[offset..offset + item.size() as usize];
+                let mut names = label(address).cloned().unwrap_or_default();
+
+                let data = |name: Option<String>| -> Node {
+                    let name = name.unwrap_or_else(|| format!("d_{:08x}", address));
+                    match item {
+                        Item::Space(size) => Node::Directive(Directive::Space(name, size)),
+                        _ => Node::Directive(Directive::Word(name, u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as i32)),
+                    }
+                };
+                let node = match &item {
+                    Item::Instruction(op, operands, word) => {
+                        // an offset refers to the segment it is in, an absolute address to any
+                        let label = |address: u32, kind: AddressKind| match kind {
+                            AddressKind::Absolute => labels.get(&address),
+                            AddressKind::Segment => label(address),
+                        }.map(|names| names[0].to_owned());
+                        self.instruction(*op, operands, *word, segment.origin, label, &symbols)
+                    }
+                    Item::Word(_) | Item::Space(_) => None,
+                };
+                let node = match node {
+                    Some(node) => node,
+                    None => {
+                        let name = names.pop();
+                        names.iter().for_each(|n| lines.push((Node::Label(n.to_owned()), address, &[][..])));
+                        match item {
+                            // a jump to an unnamed address is written as two words
+                            Item::Instruction(_, _, _) => {
+                                lines.push((data(name), address, &bytes[..4]));
+                                let name = format!("d_{:08x}", address.wrapping_add(4));
+                                let word = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+                                lines.push((Node::Directive(Directive::Word(name, word as i32)), address.wrapping_add(4), &bytes[4..]));
+                            }
+                            _ => lines.push((data(name), address, bytes)),
+                        }
+                        continue;
+                    }
+                };
+                names.iter().for_each(|n| lines.push((Node::Label(n.to_owned()), address, &[][..])));
+                lines.push((node, address, bytes));
+            }
+
+            if let Some(names) = label(end) {
+                names.iter().for_each(|n| lines.push((Node::Label(n.to_owned()), end, &[][..])));
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Splits a segment into instructions and data.
+    fn decode(&self, segment: &Segment) -> Result<Vec<(u32, Item)>> {
+        let data: Vec<(u32, u32)> = self.symbols.iter()
+            .filter(|s| matches!(s.kind, Kind::Word | Kind::Image | Kind::Space))
+            .map(|s| (s.address, s.size))
+            .collect();
+
+        let bytes = segment.bytes.as_slice();
+        let mut items = vec![];
+        let mut offset = 0usize;
+        // the end of the data being decoded, if any
+        let mut data_end = 0usize;
+        while offset < bytes.len() {
+            let address = segment.origin.wrapping_add(offset as u32);
+            let remaining = &bytes[offset..];
+
+            if let Some((_, size)) = data.iter().find(|(a, size)| *a == address && *size > 0) {
+                let size = (*size as usize).min(remaining.len());
+                if remaining[..size].iter().all(|b| *b == 0) {
+                    items.push((address, Item::Space(size as u32)));
+                    offset += size;
+                    continue;
+                }
+                data_end = data_end.max(offset + size);
+            }
+
+            let item = match Self::instruction_at(remaining) {
+                Some(item) if offset >= data_end && !data.iter().any(|(a, _)| *a > address && *a < address + item.size()) => item,
+                _ if remaining.len() >= 4 => Item::Word(u32::from_be_bytes([remaining[0], remaining[1], remaining[2], remaining[3]])),
+                _ if remaining.iter().all(|b| *b == 0) => Item::Space(remaining.len() as u32),
+                _ => return Err(format!("Cannot disassemble the {} bytes at 0x{:08x}", remaining.len(), address)),
+            };
+            offset += item.size() as usize;
+            items.push((address, item));
+        }
+        Ok(items)
+    }
+
+    /// Decodes the instruction starting `bytes`, if they are a valid one.
+    fn instruction_at(bytes: &[u8]) -> Option<Item> {
+        if bytes.len() < 4 || Op::from(bytes[0]).bytecode() != bytes[0] {
+            return None;
+        }
+        let op = Op::from(bytes[0]);
+        let form = Form::of(op);
+        let length = op.length() as usize;
+        if bytes.len() < length || bytes[1 + form.bytes()..4].iter().any(|b| *b != 0) {
+            return None;
+        }
+        let registers = match form {
+            Form::R | Form::Rw | Form::Wr => 1,
+            Form::Rr | Form::Ro => 2,
+            Form::Rrr => 3,
+            _ => 0,
+        };
+        if bytes[1..1 + registers].iter().any(|b| register(*b).is_none()) {
+            return None;
+        }
+        let word = match length {
+            8 => Some(u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])),
+            _ => None,
+        };
+        Some(Item::Instruction(op, [bytes[0], bytes[1], bytes[2], bytes[3]], word))
+    }
+
+    /// The instruction, or `None` when it refers to an address without label.
+    fn instruction<F>(&self, op: Op, bytes: &[u8; 4], word: Option<u32>, origin: u32, label: F, symbols: &HashSet<u32>) -> Option<Node>
+        where F: Fn(u32, AddressKind) -> Option<String>
+    {
+        let r = |i: usize| register(bytes[i]).unwrap();
+        let w = word.unwrap_or(0);
+        let symbol = || if symbols.contains(&w) { label(w, AddressKind::Absolute) } else { None };
+
+        let instruction = match Form::of(op) {
+            Form::None => Instruction::I(op),
+            Form::B => Instruction::IB(op, bytes[1]),
+            Form::R => Instruction::IR(op, r(1)),
+            Form::Rr => Instruction::IRR(op, r(1), r(2)),
+            Form::Rrr => Instruction::IRRR(op, r(1), r(2), r(3)),
+            Form::Rw | Form::Wr => match symbol() {
+                Some(label) => Instruction::IRA(op, r(1), label, AddressKind::Absolute),
+                None => Instruction::IRW(op, r(1), w),
+            },
+            Form::W => Instruction::IW(op, w),
+            Form::Ro => Instruction::IRRW(op, r(1), r(2), w),
+            Form::A => Instruction::IA(op, label(w, AddressKind::Absolute)?, AddressKind::Absolute),
+            Form::S => Instruction::IA(op, label(origin.wrapping_add(w), AddressKind::Segment)?, AddressKind::Segment),
+        };
+        Some(Node::Instruction(instruction))
+    }
+}
+
+/// The instruction as written in assembly.
+pub fn source(instruction: &Instruction) -> String {
+    let (mnemonic, form) = (instruction.op().mnemonic(), Form::of(instruction.op()));
+    let address = |label: &String, kind: &AddressKind| match kind {
+        AddressKind::Absolute => format!("&{}", label),
+        AddressKind::Segment => format!("@{}", label),
+    };
+    let operands = match instruction {
+        Instruction::I(_) => String::new(),
+        Instruction::IA(_, label, kind) => address(label, kind),
+        Instruction::IB(_, b) => b.to_string(),
+        Instruction::IR(_, r) => r.to_owned(),
+        Instruction::IRA(_, r, label, kind) if form == Form::Wr => format!("{}, {}", address(label, kind), r),
+        Instruction::IRA(_, r, label, kind) => format!("{}, {}", r, address(label, kind)),
+        Instruction::IRW(_, r, w) if form == Form::Wr => format!("{}, {}", word(*w), r),
+        Instruction::IRW(_, r, w) => format!("{}, {}", r, word(*w)),
+        Instruction::IRR(_, r1, r2) => format!("{}, {}", r1, r2),
+        Instruction::IRRR(_, r1, r2, r3) => format!("{}, {}, {}", r1, r2, r3),
+        Instruction::IRRW(_, r1, r2, w) => format!("{}, [{} + {}]", r1, r2, word(*w)),
+        Instruction::IW(_, w) => word(*w),
+    };
+    match operands.as_str() {
+        "" => mnemonic.to_string(),
+        operands => format!("{} {}", mnemonic, operands),
+    }
+}
+
+fn directive_source(directive: &Directive) -> String {
+    match directive {
+        Directive::Base(origin) => format!("#base 0x{:08x}", origin),
+        Directive::Entry(label) => format!("#entry {}", label),
+        Directive::Extern(label) => format!("#extern {}", label),
+        Directive::Global(label) => format!("#global {}", label),
+        Directive::Image(label, pixels) => format!("// #image {} ({} pixels)", label, pixels.len()),
+        Directive::Section(name) => format!("#section {}", name),
+        Directive::Space(label, size) => format!("#space {} {}", label, size),
+        Directive::Word(label, value) => format!("#word {} {}", label, word(*value as u32)),
+    }
+}
+
+/// Small values in decimal, the others in hexadecimal.
+fn word(w: u32) -> String {
+    match w {
+        0..=255 => w.to_string(),
+        _ => format!("0x{:08x}", w),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::address_resolver::AddressResolver;
+    use crate::emitter::Emitter;
+    use crate::lexer::Lexer;
+    use crate::parser::{Parser, Spans};
+    use crate::symbols::SymbolMap;
+
+    use super::*;
+
+    fn assemble(source: &str) -> (Vec<Segment>, Vec<Symbol>) {
+        let mut lexer = Lexer::from_text(source);
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).parse().unwrap();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+        let (segments, _) = Emitter::new(&nodes, &addresses).emit();
+        let symbols = SymbolMap::new(&nodes, &spans, &addresses, &symbols).symbols();
+        (segments, symbols)
+    }
+
+    fn emit(nodes: &Vec<Node>) -> Vec<Segment> {
+        let addresses = AddressResolver::new(nodes).resolve().unwrap();
+        Emitter::new(nodes, &addresses).emit().0
+    }
+
+    /// Disassembles `segments`, with and without the symbols, and assembles the result again.
+    fn assert_round_trip(name: &str, segments: &[Segment], symbols: &[Symbol]) {
+        for symbols in [symbols, &[]] {
+            let mut out = vec![];
+            Disassembler::new(segments).with_symbols(symbols).write(&mut out).unwrap();
+            let source = String::from_utf8(out).unwrap();
+
+            assert_eq!(segments, assemble(&source).0.as_slice(), "{} disassembled as\n{}", name, source);
+        }
+    }
+
+    #[test]
+    fn round_trip_sources() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut files: Vec<_> = fs::read_dir(root.join("examples")).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension() == Some("a".as_ref()))
+            // assigns addresses to variables, which the parser does not support
+            .filter(|path| !path.ends_with("halt.a"))
+            .collect();
+        files.sort();
+        files.push(root.join("src/common/rom.a"));
+
+        for file in files {
+            let source = fs::read_to_string(&file).unwrap();
+            // the values of the header written by `thm --gen-header` do not matter here
+            let mut header: Vec<&str> = source.match_indices("$__")
+                .map(|(i, _)| source[i..].split(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '$').next().unwrap())
+                .collect();
+            header.sort();
+            header.dedup();
+            let header: String = header.iter().map(|v| format!("{} = 0\n", v)).collect();
+
+            let (segments, symbols) = assemble(&(header + &source));
+            assert_eq!(false, segments.is_empty(), "{:?} is empty", file);
+            assert_round_trip(&file.to_string_lossy(), &segments, &symbols);
+        }
+    }
+
+    /// A xorshift generator, enough to pick instructions.
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn register(&mut self) -> String {
+            let names: Vec<String> = registers::names().collect();
+            names[self.below(names.len())].clone()
+        }
+
+        fn word(&mut self) -> u32 {
+            match self.below(2) {
+                0 => self.below(256) as u32,
+                _ => self.next() as u32,
+            }
+        }
+    }
+
+    /// Generates random valid instructions in `segments` segments, with `labels` labels each. The
+    /// jumps with an offset refer to a label of their segment, the others to any label.
+    fn generate(random: &mut Random, segments: usize, count: usize, labels: usize) -> Vec<Node> {
+        let ops: Vec<Op> = (0..=255u8).filter(|b| Op::from(*b).bytecode() == *b).map(Op::from).collect();
+        let name = |segment: usize, label: usize| format!("s{}l{}", segment, label);
+
+        let mut nodes = vec![];
+        for segment in 0..segments {
+            nodes.push(Node::Directive(Directive::Base(0x1000 * (2 * segment as u32 + 1))));
+            let mut placed = 0;
+            for _ in 0..count {
+                if placed < labels && random.below(count) < labels {
+                    nodes.push(Node::Label(name(segment, placed)));
+                    placed += 1;
+                }
+                let op = ops[random.below(ops.len())];
+                let any = name(random.below(segments), random.below(labels));
+                let instruction = match Form::of(op) {
+                    Form::None => Instruction::I(op),
+                    Form::B => Instruction::IB(op, random.below(256) as u8),
+                    Form::R => Instruction::IR(op, random.register()),
+                    Form::Rr => Instruction::IRR(op, random.register(), random.register()),
+                    Form::Rrr => Instruction::IRRR(op, random.register(), random.register(), random.register()),
+                    Form::Rw | Form::Wr if random.below(4) == 0 => Instruction::IRA(op, random.register(), any, AddressKind::Absolute),
+                    Form::Rw | Form::Wr => Instruction::IRW(op, random.register(), random.word()),
+                    Form::W => Instruction::IW(op, random.word()),
+                    Form::Ro => Instruction::IRRW(op, random.register(), random.register(), random.word()),
+                    Form::A => Instruction::IA(op, any, AddressKind::Absolute),
+                    Form::S => Instruction::IA(op, name(segment, random.below(labels)), AddressKind::Segment),
+                };
+                nodes.push(Node::Instruction(instruction));
+            }
+            for label in placed..labels {
+                nodes.push(Node::Label(name(segment, label)));
+            }
+        }
+        nodes
+    }
+
+    #[test]
+    fn forms() {
+        let forms: Vec<(&str, Form)> = [Op::Nop, Op::JS, Op::StorRW, Op::LoadRRW, Op::Xbm].iter()
+            .map(|op| (op.mnemonic(), Form::of(*op)))
+            .collect();
+
+        assert_eq!(vec![("NOP", Form::None), ("J", Form::S), ("STOR", Form::Wr), ("LOAD", Form::Ro), ("XBM", Form::B)], forms);
+        // panics on a syntax without form
+        (0..=255u8).map(Op::from).for_each(|op| {
+            Form::of(op);
+        });
+    }
+
+    #[test]
+    fn round_trip_random_instructions() {
+        let mut random = Random(0x2545f4914f6cdd1d);
+        for _ in 0..200 {
+            let nodes = generate(&mut random, 2, 40, 5);
+            let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+            let segments = emit(&nodes);
+
+            let decoded = Disassembler::new(&segments).disassemble().unwrap();
+
+            // without symbols, words are not named and jumps get synthesized labels
+            let instructions = |nodes: &[Node]| -> Vec<Instruction> {
+                nodes.iter()
+                    .filter_map(|node| match node {
+                        Node::Instruction(Instruction::IA(op, _, kind)) => Some(Instruction::IA(*op, String::new(), kind.clone())),
+                        Node::Instruction(Instruction::IRA(op, r, label, _)) => Some(Instruction::IRW(*op, r.to_owned(), addresses[label].absolute())),
+                        Node::Instruction(instruction) => Some(instruction.clone()),
+                        _ => None,
+                    })
+                    .collect()
+            };
+            assert_eq!(instructions(&nodes), instructions(&decoded), "{:?}", nodes);
+            assert_eq!(segments, emit(&decoded), "{:?}", nodes);
+            assert_round_trip("generated", &segments, &[]);
+        }
+    }
+
+    #[test]
+    fn write() {
+        let (segments, _) = assemble("#base 0x1000\n:start\n    MOV r1, 300\n    LOAD r2, [sp + 4]\n    STOR 0x2000, r2\n    J @start\n    CALL &end\n:end\n    RET\n");
+
+        let mut out = vec![];
+        let r = Disassembler::new(&segments).write(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "#base 0x00001000\n\
+                        :l_00001000\n    MOV r1, 0x0000012c        // 00001000: 03 01 00 00 00 00 01 2c\n    \
+                        LOAD r2, [sp + 4]         // 00001008: 2d 02 fc 00 00 00 00 04\n    \
+                        STOR 0x00002000, r2       // 00001010: 2b 02 00 00 00 00 20 00\n    \
+                        J @l_00001000             // 00001018: 22 00 00 00 00 00 00 00\n    \
+                        CALL &l_00001028          // 00001020: 25 00 00 00 00 00 10 28\n\
+                        :l_00001028\n    RET                       // 00001028: 27 00 00 00\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+
+    #[test]
+    fn disassemble_with_symbols() {
+        let (segments, symbols) = assemble(":main\n    MOV r0, &data\n    J &main\n#word data 0x12345678\n#space buffer 6\n");
+
+        let nodes = Disassembler::new(&segments).with_symbols(&symbols).with_entry(Some(0)).disassemble();
+
+        assert_eq!(Ok(vec![
+            Node::Directive(Directive::Entry("main".to_string())),
+            Node::Directive(Directive::Base(0)),
+            Node::Label("main".to_string()),
+            Node::Instruction(Instruction::IRA(Op::MovRW, "r0".to_string(), "data".to_string(), AddressKind::Absolute)),
+            Node::Instruction(Instruction::IA(Op::JA, "main".to_string(), AddressKind::Absolute)),
+            Node::Directive(Directive::Word("data".to_string(), 0x12345678)),
+            Node::Directive(Directive::Space("buffer".to_string(), 8)),
+        ]), nodes);
+    }
+
+    #[test]
+    fn disassemble_invalid_instructions() {
+        let segments = vec![Segment {
+            origin: 0,
+            bytes: vec![0xff, 0, 0, 0, Op::JA.bytecode(), 0, 0, 0, 0, 0, 0, 2, Op::Nop.bytecode(), 1, 0, 0],
+        }];
+
+        let nodes = Disassembler::new(&segments).disassemble();
+
+        assert_eq!(Ok(vec![
+            Node::Directive(Directive::Base(0)),
+            Node::Directive(Directive::Word("d_00000000".to_string(), 0xff000000u32 as i32)),
+            Node::Directive(Directive::Word("d_00000004".to_string(), 0x23000000)),
+            Node::Directive(Directive::Word("d_00000008".to_string(), 2)),
+            Node::Directive(Directive::Word("d_0000000c".to_string(), 0x00010000)),
+        ]), nodes);
+    }
+}
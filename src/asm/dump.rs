@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+
+use crate::address_resolver::Address;
+use crate::json::Json;
+use crate::lexer::{AddressKind as LexerAddressKind, Position, Token};
+use crate::parser::{AddressKind, Directive, Instruction, Node, ParseResult, Span};
+
+/// The version of the schema of the dumps, increased on any incompatible change.
+pub const VERSION: u32 = 1;
+
+/// Dumps the tokens of each file, as written by `tha --emit tokens`:
+///
+/// ```text
+/// {"version":1,"files":[{"file":"a.a","tokens":[
+///   {"kind":"label","line":1,"column":1,"value":"start"},
+///   {"kind":"op","line":2,"column":5,"value":"MOV"},
+///   {"kind":"address","line":3,"column":7,"value":"start","address":"segment"},
+///   {"kind":"integer","line":2,"column":13,"value":1},
+///   {"kind":"eol","line":2,"column":14}, ...]}]}
+/// ```
+///
+/// `kind` is one of `address`, `colon`, `comma`, `directive`, `equal`, `eol`, `identifier`,
+/// `integer`, `label`, `lbrace`, `lbracket`, `op`, `plus`, `rbrace`, `rbracket`, `section`,
+/// `string` or `variable`. Punctuation and `eol` have no `value`.
+pub fn tokens(files: &[(Option<String>, Vec<Token>)]) -> Json {
+    Json::object(vec![
+        ("version", VERSION.into()),
+        ("files", Json::Array(files.iter()
+            .map(|(file, tokens)| Json::object(vec![
+                ("file", file.as_ref().into()),
+                ("tokens", Json::Array(tokens.iter().map(token).collect())),
+            ]))
+            .collect())),
+    ])
+}
+
+/// Dumps the nodes, the resolved addresses and the variables, as written by `tha --emit ast`:
+///
+/// ```text
+/// {"version":1,
+///  "nodes":[
+///   {"file":"a.a","line":1,"column":1,"kind":"label","name":"start"},
+///   {"file":"a.a","line":2,"column":5,"kind":"instruction","op":"MovRW","opcode":3,
+///    "operands":[{"register":"r0"},{"word":1}]},
+///   {"file":"a.a","line":3,"column":1,"kind":"directive","directive":"word","label":"w","value":7}, ...],
+///  "addresses":{"start":{"segment":0,"offset":0,"absolute":0}, ...},
+///  "variables":{"$v":{"kind":"integer","line":1,"column":6,"value":16}, ...}}
+/// ```
+///
+/// Operands are `{"register":name}`, `{"byte":n}`, `{"word":n}` or
+/// `{"address":label,"kind":"absolute"|"segment"}`. The directives are `base` (`origin`),
+/// `entry`, `extern`, `global` and `section` (`name`), `image` (`label`, `pixels`), `space`
+/// (`label`, `size`) and `word` (`label`, `value`). Addresses and variables are sorted by name.
+pub fn ast(result: &ParseResult, spans: &[Span], addresses: &HashMap<String, Address>) -> Json {
+    let mut labels: Vec<(&String, &Address)> = addresses.iter().collect();
+    labels.sort_by_key(|(label, _)| *label);
+    let mut variables: Vec<(&String, &Token)> = result.symbols.iter().collect();
+    variables.sort_by_key(|(name, _)| *name);
+
+    Json::object(vec![
+        ("version", VERSION.into()),
+        ("nodes", Json::Array(result.nodes.iter()
+            .enumerate()
+            .map(|(i, n)| node(n, spans.get(i)))
+            .collect())),
+        ("addresses", Json::Object(labels.into_iter()
+            .map(|(label, address)| (label.to_owned(), Json::object(vec![
+                ("segment", address.segment.into()),
+                ("offset", address.offset.into()),
+                ("absolute", address.absolute().into()),
+            ])))
+            .collect())),
+        ("variables", Json::Object(variables.into_iter()
+            .map(|(name, t)| (name.to_owned(), token(t)))
+            .collect())),
+    ])
+}
+
+fn token(token: &Token) -> Json {
+    let (kind, value) = match token {
+        Token::Address(_, label, _) => ("address", label.into()),
+        Token::Colon(_) => ("colon", Json::Null),
+        Token::Comma(_) => ("comma", Json::Null),
+        Token::Directive(_, name) => ("directive", name.into()),
+        Token::Equal(_) => ("equal", Json::Null),
+        Token::Eol(_) => ("eol", Json::Null),
+        Token::Identifier(_, name) => ("identifier", name.into()),
+        Token::Integer(_, value) => ("integer", (*value).into()),
+        Token::Label(_, name) => ("label", name.into()),
+        Token::LBrace(_) => ("lbrace", Json::Null),
+        Token::LBracket(_) => ("lbracket", Json::Null),
+        Token::Op(_, name) => ("op", name.into()),
+        Token::Plus(_) => ("plus", Json::Null),
+        Token::RBrace(_) => ("rbrace", Json::Null),
+        Token::RBracket(_) => ("rbracket", Json::Null),
+        Token::Section(_, name) => ("section", name.into()),
+        Token::String(_, value) => ("string", value.into()),
+        Token::Variable(_, name) => ("variable", name.into()),
+    };
+
+    let mut members = vec![("kind", Json::string(kind))];
+    members.extend(position(token.position()));
+    if value != Json::Null {
+        members.push(("value", value));
+    }
+    if let Token::Address(_, _, kind) = token {
+        members.push(("address", Json::string(match kind {
+            LexerAddressKind::Absolute => "absolute",
+            LexerAddressKind::Segment => "segment",
+        })));
+    }
+    Json::object(members)
+}
+
+fn node(node: &Node, span: Option<&Span>) -> Json {
+    let mut members = match span {
+        Some(span) => {
+            let mut members = vec![("file", span.file.as_ref().into())];
+            members.extend(position(&span.position));
+            members
+        }
+        None => vec![],
+    };
+
+    match node {
+        Node::Label(name) => {
+            members.push(("kind", Json::string("label")));
+            members.push(("name", name.into()));
+        }
+        Node::Directive(directive) => {
+            members.push(("kind", Json::string("directive")));
+            members.extend(match directive {
+                Directive::Base(origin) => vec![("directive", Json::string("base")), ("origin", (*origin).into())],
+                Directive::Entry(name) => vec![("directive", Json::string("entry")), ("name", name.into())],
+                Directive::Extern(name) => vec![("directive", Json::string("extern")), ("name", name.into())],
+                Directive::Global(name) => vec![("directive", Json::string("global")), ("name", name.into())],
+                Directive::Section(name) => vec![("directive", Json::string("section")), ("name", name.into())],
+                Directive::Image(label, pixels) => vec![
+                    ("directive", Json::string("image")),
+                    ("label", label.into()),
+                    ("pixels", Json::Array(pixels.iter().map(|p| (*p).into()).collect())),
+                ],
+                Directive::Space(label, size) => vec![
+                    ("directive", Json::string("space")),
+                    ("label", label.into()),
+                    ("size", (*size).into()),
+                ],
+                Directive::Word(label, value) => vec![
+                    ("directive", Json::string("word")),
+                    ("label", label.into()),
+                    ("value", (*value).into()),
+                ],
+            });
+        }
+        Node::Instruction(instruction) => {
+            members.push(("kind", Json::string("instruction")));
+            members.push(("op", Json::String(format!("{:?}", instruction.op()))));
+            members.push(("opcode", (instruction.op().bytecode() as u32).into()));
+            members.push(("operands", Json::Array(operands(instruction))));
+        }
+    }
+    Json::object(members)
+}
+
+fn operands(instruction: &Instruction) -> Vec<Json> {
+    let register = |r: &String| Json::object(vec![("register", r.into())]);
+    let word = |w: &u32| Json::object(vec![("word", (*w).into())]);
+    let address = |a: &String, kind: &AddressKind| Json::object(vec![
+        ("address", a.into()),
+        ("kind", Json::string(match kind {
+            AddressKind::Absolute => "absolute",
+            AddressKind::Segment => "segment",
+        })),
+    ]);
+
+    match instruction {
+        Instruction::I(_) => vec![],
+        Instruction::IA(_, a, kind) => vec![address(a, kind)],
+        Instruction::IB(_, b) => vec![Json::object(vec![("byte", (*b as u32).into())])],
+        Instruction::IR(_, r) => vec![register(r)],
+        Instruction::IRA(_, r, a, kind) => vec![register(r), address(a, kind)],
+        Instruction::IRW(_, r, w) => vec![register(r), word(w)],
+        Instruction::IRR(_, r1, r2) => vec![register(r1), register(r2)],
+        Instruction::IRRR(_, r1, r2, r3) => vec![register(r1), register(r2), register(r3)],
+        Instruction::IRRW(_, r1, r2, w) => vec![register(r1), register(r2), word(w)],
+        Instruction::IW(_, w) => vec![word(w)],
+    }
+}
+
+fn position(position: &Position) -> Vec<(&'static str, Json)> {
+    vec![
+        ("line", (position.line() as u32).into()),
+        ("column", (position.column() as u32).into()),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address_resolver::AddressResolver;
+    use crate::lexer::Lexer;
+    use crate::parser::{Parser, Spans};
+
+    use super::*;
+
+    #[test]
+    fn dump_tokens() {
+        let lexed = Lexer::from_text(":start\n    J @start\n").collect::<Result<Vec<Token>, String>>().unwrap();
+
+        let json = tokens(&[(Some("a.a".to_string()), lexed)]);
+
+        assert_eq!(r#"{"version":1,"files":[{"file":"a.a","tokens":["#.to_owned()
+                       + r#"{"kind":"label","line":1,"column":1,"value":"start"},"#
+                       + r#"{"kind":"eol","line":1,"column":7},"#
+                       + r#"{"kind":"op","line":2,"column":5,"value":"J"},"#
+                       + r#"{"kind":"address","line":2,"column":7,"value":"start","address":"segment"},"#
+                       + r#"{"kind":"eol","line":2,"column":13}]}]}"#, json.to_string());
+    }
+
+    #[test]
+    fn dump_ast() {
+        let mut lexer = Lexer::from_text("$v = 16\n:start\n    MOV r0, $v\n#word w 7\n");
+        let mut result = ParseResult { nodes: vec![], symbols: HashMap::new() };
+        let mut spans = Spans::default();
+        Parser::from_lexer(&mut lexer, &mut result.nodes, &mut result.symbols).with_spans(&mut spans).parse().unwrap();
+        let addresses = AddressResolver::new(&result.nodes).resolve().unwrap();
+
+        let json = ast(&result, &spans.nodes, &addresses);
+
+        assert_eq!(r#"{"version":1,"nodes":["#.to_owned()
+                       + r#"{"file":null,"line":2,"column":1,"kind":"label","name":"start"},"#
+                       + r#"{"file":null,"line":3,"column":5,"kind":"instruction","op":"MovRW","opcode":3,"#
+                       + r#""operands":[{"register":"r0"},{"word":16}]},"#
+                       + r#"{"file":null,"line":4,"column":1,"kind":"directive","directive":"word","label":"w","value":7}],"#
+                       + r#""addresses":{"start":{"segment":0,"offset":0,"absolute":0},"w":{"segment":0,"offset":8,"absolute":8}},"#
+                       + r#""variables":{"$v":{"kind":"integer","line":1,"column":6,"value":16}}}"#, json.to_string());
+    }
+}
@@ -1,107 +1,137 @@
 use std::collections::HashMap;
+use std::ops::Range;
 
-use crate::constants::{REG_BP, REG_CS, REG_IDT, REG_IR, REG_PC, REG_SP};
+use crate::address_resolver::Address;
 use crate::parser::{AddressKind, Directive, Instruction, Node};
+use crate::registers;
+
+/// The bytes to be loaded at `origin`, as started by a `#base` directive.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Segment {
+    pub origin: u32,
+    pub bytes: Vec<u8>,
+}
 
 pub struct Emitter<'t> {
     nodes: &'t Vec<Node>,
-    addresses: &'t HashMap<String, u32>,
-    registers: HashMap<String, usize>,
+    addresses: &'t HashMap<String, Address>,
 }
 
 impl<'t> Emitter<'t> {
-    pub fn new(nodes: &'t Vec<Node>, addresses: &'t HashMap<String, u32>) -> Emitter<'t> {
-        let mut registers = HashMap::new();
-        registers.insert("cp".to_string(), REG_PC);
-        registers.insert("sp".to_string(), REG_SP);
-        registers.insert("bp".to_string(), REG_BP);
-        registers.insert("cs".to_string(), REG_CS);
-        registers.insert("ir".to_string(), REG_IR);
-        registers.insert("idt".to_string(), REG_IDT);
-        for r in 0..=31 {
-            registers.insert(format!("r{}", r).to_string(), r);
-        }
+    pub fn new(nodes: &'t Vec<Node>, addresses: &'t HashMap<String, Address>) -> Emitter<'t> {
         Emitter {
             nodes,
             addresses,
-            registers,
         }
     }
 
-    pub fn emit(&self) -> Vec<u8> {
+    /// Emits the non-empty segments, in source order, along with the range of absolute addresses
+    /// each node was emitted at (empty for nodes emitting nothing).
+    pub fn emit(&self) -> (Vec<Segment>, Vec<Range<u32>>) {
+        let mut segments = vec![];
+        let mut ranges = vec![];
+        let mut origin = 0u32;
         let mut bytes = vec![];
-        let mut base_address = 0u32;
 
         for node in self.nodes {
+            let length = bytes.len();
             match node {
-                Node::Directive(directive) => match directive {
-                    Directive::Base(addr) => base_address = *addr,
-                    Directive::Word(_, v) => bytes.extend_from_slice(&v.to_be_bytes()),
-                },
-                Node::Instruction(instruction) => match instruction {
-                    Instruction::I(op) => bytes.append(vec![op.bytecode(), 0, 0, 0].as_mut()),
-                    Instruction::IB(op, imm1) => bytes.append(vec![op.bytecode(), *imm1, 0, 0].as_mut()),
-                    Instruction::IRW(op, r, value) => {
-                        bytes.append(vec![op.bytecode(), *self.decode_register(r) as u8, 0, 0].as_mut());
-                        let b = value.to_be_bytes();
-                        bytes.extend_from_slice(&b);
-                    },
-                    Instruction::IW(op,  value) => {
-                        bytes.append(vec![op.bytecode(), 0, 0, 0].as_mut());
-                        bytes.extend_from_slice(&(value.to_be_bytes()));
-                    },
-                    Instruction::IR(op, r) => bytes.append(vec![op.bytecode(), *self.decode_register(r) as u8, 0, 0].as_mut()),
-                    Instruction::IRR(op, r1, r2) => bytes.append(vec![
-                        op.bytecode(), *self.decode_register(r1) as u8, *self.decode_register(r2) as u8, 0,
-                    ].as_mut()),
-                    Instruction::IRRW(op, r1, r2, w0) => {
-                        bytes.append(vec![
-                            op.bytecode(), *self.decode_register(r1) as u8, *self.decode_register(r2) as u8, 0,
-                        ].as_mut());
-                        bytes.extend_from_slice(&(w0.to_be_bytes()));
-                    },
-                    Instruction::IRRR(op, r1, r2, r3) => bytes.append(vec![
-                        op.bytecode(), *self.decode_register(r1) as u8, *self.decode_register(r2) as u8, *self.decode_register(r3) as u8,
-                    ].as_mut()),
-                    Instruction::IRA(op, r, addr, kind) => {
-                        bytes.append(vec![op.bytecode(), *self.decode_register(r) as u8, 0, 0].as_mut());
-                        let b = (match kind {
-                            AddressKind::Absolute => base_address,
-                            AddressKind::Segment => 0,
-                        } + self.decode_address(addr)).to_be_bytes();
-                        bytes.extend_from_slice(&b);
-                    }
-                    Instruction::IA(op, addr, kind) => {
-                        bytes.append(vec![op.bytecode(), 0, 0, 0].as_mut());
-                        let b = (match kind {
-                            AddressKind::Absolute => base_address,
-                            AddressKind::Segment => 0,
-                        } + self.decode_address(addr)).to_be_bytes();
-                        bytes.extend_from_slice(&b);
+                Node::Directive(Directive::Base(addr)) => {
+                    if !bytes.is_empty() {
+                        segments.push(Segment { origin, bytes });
                     }
+                    origin = *addr;
+                    bytes = vec![];
                 }
-                _ => continue,
+                _ => self.emit_node(node, &mut bytes),
             }
+            let end = origin.wrapping_add(bytes.len() as u32);
+            ranges.push(end.wrapping_sub(bytes.len().saturating_sub(length) as u32)..end);
         }
 
-        bytes
+        if !bytes.is_empty() {
+            segments.push(Segment { origin, bytes });
+        }
+        (segments, ranges)
     }
 
-    fn decode_address(&self, address: &String) -> u32 {
-        self.addresses.get(address).unwrap().to_owned()
+    /// Appends the bytes of a node to `bytes`; directives other than `#image`, `#space` and `#word`
+    /// emit nothing.
+    pub fn emit_node(&self, node: &Node, bytes: &mut Vec<u8>) {
+        match node {
+            Node::Directive(directive) => match directive {
+                Directive::Base(_)
+                | Directive::Entry(_)
+                | Directive::Extern(_)
+                | Directive::Global(_)
+                | Directive::Section(_) => (),
+                Directive::Image(_, pixels) => pixels.iter()
+                    .for_each(|p| bytes.extend_from_slice(&p.to_be_bytes())),
+                Directive::Space(_, size) => bytes.resize(bytes.len() + *size as usize, 0),
+                Directive::Word(_, v) => bytes.extend_from_slice(&v.to_be_bytes()),
+            },
+            Node::Instruction(instruction) => match instruction {
+                Instruction::I(op) => bytes.append(vec![op.bytecode(), 0, 0, 0].as_mut()),
+                Instruction::IB(op, imm1) => bytes.append(vec![op.bytecode(), *imm1, 0, 0].as_mut()),
+                Instruction::IRW(op, r, value) => {
+                    bytes.append(vec![op.bytecode(), self.decode_register(r) as u8, 0, 0].as_mut());
+                    let b = value.to_be_bytes();
+                    bytes.extend_from_slice(&b);
+                },
+                Instruction::IW(op,  value) => {
+                    bytes.append(vec![op.bytecode(), 0, 0, 0].as_mut());
+                    bytes.extend_from_slice(&(value.to_be_bytes()));
+                },
+                Instruction::IR(op, r) => bytes.append(vec![op.bytecode(), self.decode_register(r) as u8, 0, 0].as_mut()),
+                Instruction::IRR(op, r1, r2) => bytes.append(vec![
+                    op.bytecode(), self.decode_register(r1) as u8, self.decode_register(r2) as u8, 0,
+                ].as_mut()),
+                Instruction::IRRW(op, r1, r2, w0) => {
+                    bytes.append(vec![
+                        op.bytecode(), self.decode_register(r1) as u8, self.decode_register(r2) as u8, 0,
+                    ].as_mut());
+                    bytes.extend_from_slice(&(w0.to_be_bytes()));
+                },
+                Instruction::IRRR(op, r1, r2, r3) => bytes.append(vec![
+                    op.bytecode(), self.decode_register(r1) as u8, self.decode_register(r2) as u8, self.decode_register(r3) as u8,
+                ].as_mut()),
+                Instruction::IRA(op, r, addr, kind) => {
+                    bytes.append(vec![op.bytecode(), self.decode_register(r) as u8, 0, 0].as_mut());
+                    let b = self.decode_address(addr, kind).to_be_bytes();
+                    bytes.extend_from_slice(&b);
+                }
+                Instruction::IA(op, addr, kind) => {
+                    bytes.append(vec![op.bytecode(), 0, 0, 0].as_mut());
+                    let b = self.decode_address(addr, kind).to_be_bytes();
+                    bytes.extend_from_slice(&b);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Labels without an address, left for the linker to relocate, are emitted as 0.
+    fn decode_address(&self, address: &String, kind: &AddressKind) -> u32 {
+        match (self.addresses.get(address), kind) {
+            (None, _) => 0,
+            (Some(address), AddressKind::Absolute) => address.absolute(),
+            (Some(address), AddressKind::Segment) => address.offset,
+        }
     }
 
-    fn decode_register(&self, r: &String) -> &usize {
-        self.registers.get(r).unwrap()
+    fn decode_register(&self, r: &str) -> usize {
+        registers::number(r).unwrap()
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::constants::{REG_CS, REG_PC};
     use crate::op::Op;
     use crate::address_resolver::AddressResolver;
+    use crate::lexer::Lexer;
     use crate::parser::AddressKind::Absolute;
-    use crate::parser::Instruction;
+    use crate::parser::{Instruction, Parser};
 
     use super::*;
 
@@ -113,8 +143,60 @@ mod tests {
         ];
         let addresses = AddressResolver::new(&nodes).resolve().unwrap();
 
-        let bytes = Emitter::new(&nodes, &addresses).emit();
+        let (segments, _) = Emitter::new(&nodes, &addresses).emit();
+
+        assert_eq!(1, segments.len());
+        assert_eq!(8, segments[0].bytes.len());
+    }
+
+    #[test]
+    fn emit_segments() {
+        let nodes = vec![
+            Node::Directive(Directive::Base(0x1000)),
+            Node::Instruction(Instruction::IA(Op::JeqA, "label2".to_string(), Absolute)),
+            Node::Directive(Directive::Base(0x2000)),
+            Node::Directive(Directive::Word("label1".to_string(), 0)),
+            Node::Label("label2".to_string()),
+            Node::Instruction(Instruction::IA(Op::JeqS, "label2".to_string(), AddressKind::Segment)),
+        ];
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+
+        let (segments, _) = Emitter::new(&nodes, &addresses).emit();
+
+        let expected = vec![
+            Segment { origin: 0x1000, bytes: vec![Op::JeqA.bytecode(), 0, 0, 0, 0x00, 0x00, 0x20, 0x04] },
+            Segment { origin: 0x2000, bytes: vec![0, 0, 0, 0, Op::JeqS.bytecode(), 0, 0, 0, 0x00, 0x00, 0x00, 0x04] },
+        ];
+        assert_eq!(expected, segments);
+    }
+
+    #[test]
+    fn emit_special_registers() {
+        let mut lexer = Lexer::from_text("    MOV pc, r0\n    MOV r1, cs\n");
+        let mut nodes = vec![];
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut HashMap::new()).parse().unwrap();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+
+        let (segments, _) = Emitter::new(&nodes, &addresses).emit();
+
+        assert_eq!(vec![Segment {
+            origin: 0,
+            bytes: vec![Op::MovRR.bytecode(), REG_PC as u8, 0, 0, Op::MovRR.bytecode(), 1, REG_CS as u8, 0],
+        }], segments);
+    }
+
+    #[test]
+    fn emit_ranges() {
+        let nodes = vec![
+            Node::Instruction(Instruction::I(Op::Nop)),
+            Node::Directive(Directive::Base(0x1000)),
+            Node::Label("label".to_string()),
+            Node::Instruction(Instruction::IW(Op::PushW, 0)),
+        ];
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+
+        let (_, ranges) = Emitter::new(&nodes, &addresses).emit();
 
-        assert_eq!(8, bytes.len());
+        assert_eq!(vec![0..4, 0x1000..0x1000, 0x1000..0x1000, 0x1000..0x1008], ranges);
     }
 }
\ No newline at end of file
@@ -0,0 +1,208 @@
+use crate::cst::Cst;
+use crate::lexer::{Lexer, Token};
+
+/// The column of the mnemonics.
+const OP_COLUMN: usize = 5;
+/// The column of the operands.
+const OPERANDS_COLUMN: usize = 11;
+/// The column of the comments following code.
+const COMMENT_COLUMN: usize = 30;
+
+type Result<T> = std::result::Result<T, String>;
+
+/// A line of source, with its tokens as written and its comment.
+struct Line {
+    tokens: Vec<(Token, String)>,
+    comment: Option<(usize, String)>,
+}
+
+/// Formats assembly source the canonical way:
+///
+/// ```text
+/// // a comment on its own line
+/// #base 0x1000
+/// $count = 0x10
+///
+/// :start
+///     MOV   r0, $count          // comment
+///     LOAD  r1, [r0 + 4]
+/// ```
+///
+/// Directives, labels and variables start at column 1; mnemonics at column 5 and their operands
+/// at column 11. Comments following code start at column 30; the others stay at column 1 or are
+/// indented as instructions. The members of `#struct` and `#enum` blocks are indented as
+/// instructions. Tokens keep their spelling, trailing whitespace is removed and consecutive blank
+/// lines are merged. As only whitespace changes, the assembled bytes do not.
+pub fn format(source: &str) -> Result<String> {
+    let source_lines = lines(source)?;
+
+    let mut text = String::new();
+    let mut blank = true;
+    let mut depth = 0usize;
+    for line in source_lines.iter() {
+        let code = match line.tokens.first() {
+            None => String::new(),
+            Some((Token::Op(_, _), op)) => {
+                let mut code = format!("{}{}", " ".repeat(OP_COLUMN - 1), op);
+                if line.tokens.len() > 1 {
+                    pad(&mut code, OPERANDS_COLUMN);
+                    code.push_str(&join(&line.tokens[1..]));
+                }
+                code
+            }
+            Some((Token::RBrace(_), _)) => join(&line.tokens),
+            Some(_) if depth > 0 => format!("{}{}", " ".repeat(OP_COLUMN - 1), join(&line.tokens)),
+            Some(_) => join(&line.tokens),
+        };
+        for (token, _) in line.tokens.iter() {
+            match token {
+                Token::LBrace(_) => depth += 1,
+                Token::RBrace(_) => depth = depth.saturating_sub(1),
+                _ => (),
+            }
+        }
+
+        let line = match &line.comment {
+            None => code,
+            Some((_, comment)) if !code.is_empty() => {
+                let mut code = code;
+                pad(&mut code, COMMENT_COLUMN);
+                code + comment
+            }
+            Some((1, comment)) if depth == 0 => comment.to_owned(),
+            Some((_, comment)) => format!("{}{}", " ".repeat(OP_COLUMN - 1), comment),
+        };
+
+        if line.is_empty() {
+            blank = true;
+            continue;
+        }
+        if blank && !text.is_empty() {
+            text.push('\n');
+        }
+        blank = false;
+        text.push_str(&line);
+        text.push('\n');
+    }
+
+    let formatted = lines(&text)?;
+    let tokens = |lines: &[Line]| -> Vec<Vec<String>> {
+        lines.iter()
+            .filter(|l| !l.tokens.is_empty())
+            .map(|l| l.tokens.iter().map(|(_, t)| t.to_owned()).collect())
+            .collect()
+    };
+    if tokens(&source_lines) != tokens(&formatted) {
+        return Err("Formatting would change the tokens".to_string());
+    }
+    Ok(text)
+}
+
+/// Splits `source` in lines, from its concrete syntax tree.
+fn lines(source: &str) -> Result<Vec<Line>> {
+    let cst = Cst::from_lexer(&mut Lexer::from_text(source))?;
+
+    let mut lines = vec![];
+    let mut tokens = vec![];
+    for token in cst.tokens() {
+        match token.token {
+            Token::Eol(_) => lines.push(Line {
+                tokens: std::mem::take(&mut tokens),
+                comment: token.comment().map(|(p, c)| (p.column() as usize, c.trim_end().to_string())),
+            }),
+            _ => tokens.push((token.token.clone(), token.text.to_owned())),
+        }
+    }
+    if !tokens.is_empty() {
+        lines.push(Line { tokens, comment: None });
+    }
+    Ok(lines)
+}
+
+/// The tokens separated by spaces, except inside brackets and before commas and colons.
+fn join(tokens: &[(Token, String)]) -> String {
+    let mut text = String::new();
+    for (i, (token, token_text)) in tokens.iter().enumerate() {
+        let space = !matches!(
+            (i.checked_sub(1).map(|i| &tokens[i].0), token),
+            (None, _) | (_, Token::Comma(_)) | (_, Token::Colon(_)) | (_, Token::RBracket(_)) | (Some(Token::LBracket(_)), _)
+        );
+        if space {
+            text.push(' ');
+        }
+        text.push_str(token_text);
+    }
+    text
+}
+
+/// Pads `text` with spaces so that what follows starts at `column`, or after a space when it
+/// is already past it.
+fn pad(text: &mut String, column: usize) {
+    let length = text.chars().count();
+    match length < column - 1 {
+        true => text.push_str(&" ".repeat(column - 1 - length)),
+        false => text.push(' '),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_instructions() {
+        let r = format("  #base 0x1000\n$v=0x1_0\n  :start\nMOV r0,$v\n    LOAD r1,[ r0+4 ]   \nHALT\n");
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "#base 0x1000\n\
+                        $v = 0x1_0\n\
+                        :start\n    \
+                            MOV   r0, $v\n    \
+                            LOAD  r1, [r0 + 4]\n    \
+                            HALT\n";
+        assert_eq!(expected, r.unwrap());
+    }
+
+    #[test]
+    fn format_comments() {
+        let r = format("\n\n// header\n\n\n:start   // entry\n  // indented\n  MOV r0, 1 // one\n  #word w 7\n#image logo \"a//b.ppm\"\n\n");
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "// header\n\
+                        \n\
+                        :start                       // entry\n    \
+                            // indented\n    \
+                            MOV   r0, 1              // one\n\
+                        #word w 7\n\
+                        #image logo \"a//b.ppm\"\n";
+        assert_eq!(expected, r.unwrap());
+    }
+
+    #[test]
+    fn format_blocks() {
+        let r = format("#struct point {\nx:  word,\n  // the ordinate\n y : word\n  }\n#enum color { red , green=4 }\n");
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "#struct point {\n    \
+                            x: word,\n    \
+                            // the ordinate\n    \
+                            y: word\n\
+                        }\n\
+                        #enum color { red, green = 4 }\n";
+        assert_eq!(expected, r.unwrap());
+    }
+
+    #[test]
+    fn format_is_idempotent() {
+        let source = ":start\n    MOV   r0, 1\n\n    HALT                     // done\n";
+
+        assert_eq!(Ok(source.to_string()), format(source));
+    }
+
+    #[test]
+    fn format_syntax_error() {
+        let r = format("MOV r0, _\n");
+
+        assert_eq!(Err("Unexpected `_`".to_string()), r);
+    }
+}
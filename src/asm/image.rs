@@ -0,0 +1,186 @@
+use std::fs;
+use std::iter::Peekable;
+use std::slice::Iter;
+
+#[derive(Debug, PartialEq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    /// One word per pixel, row by row, in the VM framebuffer format: `0x00RRGGBB`.
+    pub pixels: Vec<u32>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Image {
+    pub fn from_file(file_path: &str) -> Result<Image> {
+        match fs::read(file_path) {
+            Ok(bytes) => Self::from_ppm(&bytes).map_err(|e| format!("{} in {}", e, file_path)),
+            Err(e) => Err(format!("Cannot read {}: {}", file_path, e)),
+        }
+    }
+
+    /// Decodes a binary (`P6`) or ASCII (`P3`) PPM image.
+    pub fn from_ppm(bytes: &[u8]) -> Result<Image> {
+        let mut bytes = bytes.iter().peekable();
+
+        let binary = match (bytes.next(), bytes.next()) {
+            (Some(b'P'), Some(b'6')) => true,
+            (Some(b'P'), Some(b'3')) => false,
+            _ => return Err("Expected PPM magic number P3 or P6".to_string()),
+        };
+
+        let width = Self::header_value(&mut bytes, "width")?;
+        let height = Self::header_value(&mut bytes, "height")?;
+        let max = Self::header_value(&mut bytes, "max value")?;
+        if max == 0 || max > 65535 {
+            return Err(format!("Invalid PPM max value {}", max));
+        }
+        if width == 0 || height == 0 {
+            return Err(format!("Invalid PPM size {}x{}", width, height));
+        }
+
+        if binary {
+            // exactly one whitespace separates the header from the raster
+            bytes.next();
+        }
+
+        let count = width.checked_mul(height).ok_or(format!("Invalid PPM size {}x{}", width, height))?;
+        // each sample takes at least a byte, two for binary samples above 255
+        let sample_size = if binary && max >= 256 { 2 } else { 1 };
+        if count as u64 * 3 * sample_size > bytes.len() as u64 {
+            return Err("Unexpected end of PPM data".to_string());
+        }
+
+        let mut pixels = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut rgb = [0u32; 3];
+            for sample in rgb.iter_mut() {
+                let value = if binary {
+                    Self::binary_sample(&mut bytes, max)?
+                } else {
+                    Self::header_value(&mut bytes, "sample")?
+                };
+                if value > max {
+                    return Err(format!("PPM sample {} exceeds max value {}", value, max));
+                }
+                *sample = value * 255 / max;
+            }
+            pixels.push(rgb[0] << 16 | rgb[1] << 8 | rgb[2]);
+        }
+
+        Ok(Image { width, height, pixels })
+    }
+
+    /// Reads an ASCII decimal number, skipping whitespaces and `#`-comments before it.
+    fn header_value(bytes: &mut Peekable<Iter<u8>>, what: &str) -> Result<u32> {
+        loop {
+            match bytes.peek() {
+                Some(b'#') => {
+                    while !matches!(bytes.next(), None | Some(b'\n')) {}
+                }
+                Some(b) if b.is_ascii_whitespace() => {
+                    bytes.next();
+                }
+                _ => break,
+            }
+        }
+
+        let mut value: Option<u32> = None;
+        while let Some(b) = bytes.peek().filter(|b| b.is_ascii_digit()) {
+            value = value.unwrap_or(0)
+                .checked_mul(10)
+                .and_then(|v| v.checked_add((**b - b'0') as u32));
+            if value.is_none() {
+                return Err(format!("PPM {} is too large", what));
+            }
+            bytes.next();
+        }
+
+        value.ok_or(format!("Expected PPM {}", what))
+    }
+
+    fn binary_sample(bytes: &mut Peekable<Iter<u8>>, max: u32) -> Result<u32> {
+        let mut read = || bytes.next().map(|b| *b as u32).ok_or("Unexpected end of PPM data".to_string());
+        if max < 256 {
+            read()
+        } else {
+            Ok(read()? << 8 | read()?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_p3() {
+        let image = Image::from_ppm(b"P3\n# comment\n2 1\n255\n255 0 0  0 128 255\n");
+
+        assert_eq!(true, image.is_ok(), "Expected Ok(...), got {:?}", image);
+        let expected = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![0x00ff0000, 0x000080ff],
+        };
+        assert_eq!(expected, image.unwrap());
+    }
+
+    #[test]
+    fn test_p6() {
+        let image = Image::from_ppm(b"P6 1 2 255\n\x01\x02\x03\x0a\x0b\x0c");
+
+        assert_eq!(true, image.is_ok(), "Expected Ok(...), got {:?}", image);
+        let expected = Image {
+            width: 1,
+            height: 2,
+            pixels: vec![0x00010203, 0x000a0b0c],
+        };
+        assert_eq!(expected, image.unwrap());
+    }
+
+    #[test]
+    fn test_p6_16_bits() {
+        let image = Image::from_ppm(b"P6 1 1 65535\n\xff\xff\x00\x00\x80\x00");
+
+        assert_eq!(true, image.is_ok(), "Expected Ok(...), got {:?}", image);
+        assert_eq!(vec![0x00ff007f], image.unwrap().pixels);
+    }
+
+    #[test]
+    fn test_scaled_max_value() {
+        let image = Image::from_ppm(b"P3 1 1 15 15 0 5");
+
+        assert_eq!(true, image.is_ok(), "Expected Ok(...), got {:?}", image);
+        assert_eq!(vec![0x00ff0055], image.unwrap().pixels);
+    }
+
+    #[test]
+    fn test_invalid_magic() {
+        let image = Image::from_ppm(b"P5 1 1 255\n\x00");
+
+        assert_eq!(Err("Expected PPM magic number P3 or P6".to_string()), image);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let image = Image::from_ppm(b"P6 2 1 255\n\x00\x00\x00");
+
+        assert_eq!(Err("Unexpected end of PPM data".to_string()), image);
+    }
+
+    #[test]
+    fn test_size_overflow() {
+        let image = Image::from_ppm(b"P6 65536 65536 255\n\x00\x00\x00");
+
+        assert_eq!(Err("Invalid PPM size 65536x65536".to_string()), image);
+    }
+
+    #[test]
+    fn test_size_larger_than_data() {
+        let image = Image::from_ppm(b"P6 60000 60000 255\n\x00\x00\x00");
+
+        assert_eq!(Err("Unexpected end of PPM data".to_string()), image);
+    }
+}
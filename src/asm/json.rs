@@ -0,0 +1,333 @@
+use std::fmt;
+
+/// A JSON value, written compactly with the members of objects in the order they were added.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(i64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// An object with the given members.
+    pub fn object(members: Vec<(&str, Json)>) -> Json {
+        Json::Object(members.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    pub fn string(value: &str) -> Json {
+        Json::String(value.to_string())
+    }
+
+    /// Parses a JSON text. Numbers with a fraction or an exponent are truncated to integers.
+    pub fn parse(text: &str) -> Result<Json> {
+        let mut parser = JsonParser { chars: text.chars().collect(), index: 0 };
+        let value = parser.value()?;
+        parser.whitespace();
+        match parser.chars.get(parser.index) {
+            None => Ok(value),
+            Some(c) => Err(format!("Unexpected `{}` at {}", c, parser.index)),
+        }
+    }
+
+    /// The member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Json::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Json>> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+struct JsonParser {
+    chars: Vec<char>,
+    index: usize,
+}
+
+impl JsonParser {
+    fn whitespace(&mut self) {
+        while matches!(self.chars.get(self.index), Some(' ') | Some('\t') | Some('\n') | Some('\r')) {
+            self.index += 1;
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.get(self.index).copied();
+        self.index += 1;
+        c
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        self.whitespace();
+        match self.next() {
+            Some(n) if n == c => Ok(()),
+            Some(n) => Err(format!("Expected `{}`, got `{}` at {}", c, n, self.index - 1)),
+            None => Err(format!("Expected `{}` at end of input", c)),
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json> {
+        let end = self.index + literal.chars().count();
+        match self.chars.get(self.index..end) {
+            Some(chars) if chars.iter().copied().eq(literal.chars()) => {
+                self.index = end;
+                Ok(value)
+            }
+            _ => Err(format!("Expected `{}` at {}", literal, self.index)),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        self.whitespace();
+        match self.chars.get(self.index) {
+            None => Err("Unexpected end of input".to_string()),
+            Some('n') => self.literal("null", Json::Null),
+            Some('t') => self.literal("true", Json::Bool(true)),
+            Some('f') => self.literal("false", Json::Bool(false)),
+            Some('"') => self.string().map(Json::String),
+            Some('[') => {
+                self.index += 1;
+                let mut values = vec![];
+                self.whitespace();
+                if self.chars.get(self.index) == Some(&']') {
+                    self.index += 1;
+                    return Ok(Json::Array(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.whitespace();
+                    match self.next() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Json::Array(values)),
+                        _ => return Err(format!("Expected `,` or `]` at {}", self.index - 1)),
+                    }
+                }
+            }
+            Some('{') => {
+                self.index += 1;
+                let mut members = vec![];
+                self.whitespace();
+                if self.chars.get(self.index) == Some(&'}') {
+                    self.index += 1;
+                    return Ok(Json::Object(members));
+                }
+                loop {
+                    self.whitespace();
+                    if self.chars.get(self.index) != Some(&'"') {
+                        return Err(format!("Expected `\"` at {}", self.index));
+                    }
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.whitespace();
+                    match self.next() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(Json::Object(members)),
+                        _ => return Err(format!("Expected `,` or `}}` at {}", self.index - 1)),
+                    }
+                }
+            }
+            Some(c) if *c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) => Err(format!("Unexpected `{}` at {}", c, self.index)),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.index;
+        while matches!(self.chars.get(self.index), Some(c) if c.is_ascii_digit() || "+-.eE".contains(*c)) {
+            self.index += 1;
+        }
+        let text: String = self.chars[start..self.index].iter().collect();
+        match text.parse::<i64>() {
+            Ok(value) => Ok(Json::Number(value)),
+            Err(_) => text.parse::<f64>()
+                .map(|value| Json::Number(value as i64))
+                .map_err(|_| format!("Invalid number `{}` at {}", text, start)),
+        }
+    }
+
+    /// Parses a string, the opening `"` being at the current index.
+    fn string(&mut self) -> Result<String> {
+        let start = self.index;
+        self.index += 1;
+        let mut string = String::new();
+        loop {
+            match self.next() {
+                None => return Err(format!("Unterminated string at {}", start)),
+                Some('"') => return Ok(string),
+                Some('\\') => match self.next() {
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('/') => string.push('/'),
+                    Some('b') => string.push('\u{8}'),
+                    Some('f') => string.push('\u{c}'),
+                    Some('n') => string.push('\n'),
+                    Some('r') => string.push('\r'),
+                    Some('t') => string.push('\t'),
+                    Some('u') => {
+                        let mut code = self.hex()?;
+                        // a character outside of the basic plane is escaped as a surrogate pair
+                        if (0xd800..0xdc00).contains(&code) && self.chars.get(self.index..self.index + 2) == Some(&['\\', 'u']) {
+                            self.index += 2;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (self.hex()? - 0xdc00);
+                        }
+                        string.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    _ => return Err(format!("Invalid escape at {}", self.index - 1)),
+                },
+                Some(c) => string.push(c),
+            }
+        }
+    }
+
+    fn hex(&mut self) -> Result<u32> {
+        let text: String = self.chars.get(self.index..self.index + 4).unwrap_or_default().iter().collect();
+        self.index += 4;
+        u32::from_str_radix(&text, 16).map_err(|_| format!("Invalid escape `\\u{}` at {}", text, self.index - 4))
+    }
+}
+
+impl From<u32> for Json {
+    fn from(value: u32) -> Self {
+        Json::Number(value as i64)
+    }
+}
+
+impl From<i32> for Json {
+    fn from(value: i32) -> Self {
+        Json::Number(value as i64)
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(Json::Null)
+    }
+}
+
+impl From<&String> for Json {
+    fn from(value: &String) -> Self {
+        Json::String(value.to_owned())
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(value) => write!(f, "{}", value),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(members) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        let json = Json::object(vec![
+            ("name", Json::string("a \"b\"\n")),
+            ("values", Json::Array(vec![Json::from(1u32), Json::from(-2), Json::Null, Json::Bool(true)])),
+            ("empty", Json::Object(vec![])),
+        ]);
+
+        assert_eq!(r#"{"name":"a \"b\"\n","values":[1,-2,null,true],"empty":{}}"#, json.to_string());
+    }
+
+    #[test]
+    fn parse() {
+        let r = Json::parse(r#" {"name": "a \"b\"\n\u00e9\ud83d\ude00", "values": [1, -2, 3.5, null, true, false], "empty": {}, "list": []} "#);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let json = r.unwrap();
+        assert_eq!(Json::object(vec![
+            ("name", Json::string("a \"b\"\n\u{e9}\u{1f600}")),
+            ("values", Json::Array(vec![
+                Json::from(1u32), Json::from(-2), Json::from(3u32), Json::Null, Json::Bool(true), Json::Bool(false),
+            ])),
+            ("empty", Json::Object(vec![])),
+            ("list", Json::Array(vec![])),
+        ]), json);
+        assert_eq!(Some("a \"b\"\n\u{e9}\u{1f600}"), json.get("name").and_then(Json::as_str));
+        assert_eq!(Some(-2), json.get("values").and_then(Json::as_array).and_then(|v| v[1].as_i64()));
+    }
+
+    #[test]
+    fn parse_display() {
+        let text = r#"{"name":"a \"b\"\n","values":[1,-2,null,true],"empty":{}}"#;
+
+        assert_eq!(Ok(text.to_string()), Json::parse(text).map(|json| json.to_string()));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(Err("Expected `,` or `}` at 8".to_string()), Json::parse(r#"{"a": 1 "b": 2}"#));
+        assert_eq!(Err("Unterminated string at 0".to_string()), Json::parse(r#""abc"#));
+        assert_eq!(Err("Unexpected `x` at 3".to_string()), Json::parse("[] x"));
+    }
+}
@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+use crate::parser::{Directive, Node};
+
+/// What a memory region allows, or what a section needs.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+}
+
+impl Permissions {
+    /// Instructions are executed, `#space` buffers are written and `#word` and `#image` are read.
+    pub fn required(node: &Node) -> Permissions {
+        match node {
+            Node::Instruction(_) => Permissions { execute: true, ..Permissions::default() },
+            Node::Directive(Directive::Space(_, _)) => Permissions { write: true, ..Permissions::default() },
+            Node::Directive(Directive::Image(_, _)) | Node::Directive(Directive::Word(_, _)) => {
+                Permissions { read: true, ..Permissions::default() }
+            }
+            _ => Permissions::default(),
+        }
+    }
+
+    pub fn union(self, other: Permissions) -> Permissions {
+        Permissions {
+            read: self.read || other.read,
+            write: self.write || other.write,
+            execute: self.execute || other.execute,
+        }
+    }
+
+    pub fn allows(&self, needed: &Permissions) -> bool {
+        (self.read || !needed.read) && (self.write || !needed.write) && (self.execute || !needed.execute)
+    }
+
+    /// The permissions as the bits `rwx`, from the most significant one.
+    pub fn bits(&self) -> u8 {
+        (self.read as u8) << 2 | (self.write as u8) << 1 | self.execute as u8
+    }
+
+    pub fn from_bits(bits: u8) -> Permissions {
+        Permissions {
+            read: bits & 4 != 0,
+            write: bits & 2 != 0,
+            execute: bits & 1 != 0,
+        }
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}{}",
+               if self.read { 'r' } else { '-' },
+               if self.write { 'w' } else { '-' },
+               if self.execute { 'x' } else { '-' },
+        )
+    }
+}
+
+impl FromStr for Permissions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut permissions = Permissions::default();
+        for c in s.chars() {
+            let permission = match c {
+                'r' => &mut permissions.read,
+                'w' => &mut permissions.write,
+                'x' => &mut permissions.execute,
+                _ => return Err(format!("Invalid permissions '{}'", s)),
+            };
+            if *permission {
+                return Err(format!("Invalid permissions '{}'", s));
+            }
+            *permission = true;
+        }
+        Ok(permissions)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Region {
+    pub name: String,
+    pub origin: u32,
+    pub size: u32,
+    pub permissions: Permissions,
+}
+
+/// The memory regions and the region each section is placed in, read from a file such as:
+///
+/// ```text
+/// // name      origin      size        permissions
+/// region ram   0x00001000  0x00000400  rwx
+/// region rom   0xfe000000  0x02000000  rx
+///
+/// // section   region
+/// section text ram
+/// ```
+///
+/// The sections started by `#section` are packed one after the other, in the order they appear,
+/// in their region.
+#[derive(Debug, PartialEq, Default)]
+pub struct Layout {
+    pub regions: Vec<Region>,
+    pub sections: HashMap<String, String>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Layout {
+    pub fn from_file(file_path: &str) -> Result<Layout> {
+        match fs::read_to_string(file_path) {
+            Ok(text) => Self::parse(&text).map_err(|e| format!("{} in {}", e, file_path)),
+            Err(e) => Err(format!("Cannot read {}: {}", file_path, e)),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Layout> {
+        let mut layout = Layout::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let number = number + 1;
+            let line = match line.find("//") {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+            let words: Vec<&str> = line.split_whitespace().collect();
+            match words.as_slice() {
+                [] => (),
+                ["region", name, origin, size, permissions] => {
+                    if layout.regions.iter().any(|r| r.name == *name) {
+                        return Err(format!("Region {} defined more than once at line {}", name, number));
+                    }
+                    let region = Region {
+                        name: name.to_string(),
+                        origin: Self::number(origin).ok_or(format!("Expected <origin> at line {}", number))?,
+                        size: Self::number(size).ok_or(format!("Expected <size> at line {}", number))?,
+                        permissions: permissions.parse().map_err(|e| format!("{} at line {}", e, number))?,
+                    };
+                    if region.size == 0 || region.origin.checked_add(region.size - 1).is_none() {
+                        return Err(format!("Region {} exceeds the address space at line {}", name, number));
+                    }
+                    if let Some(other) = layout.regions.iter().find(|r| Self::overlap(r, &region)) {
+                        return Err(format!("Region {} overlaps region {} at line {}", name, other.name, number));
+                    }
+                    layout.regions.push(region);
+                }
+                ["section", name, region] => {
+                    if !layout.regions.iter().any(|r| r.name == *region) {
+                        return Err(format!("Unknown region {} at line {}", region, number));
+                    }
+                    if layout.sections.insert(name.to_string(), region.to_string()).is_some() {
+                        return Err(format!("Section {} placed more than once at line {}", name, number));
+                    }
+                }
+                _ => return Err(format!("Expected 'region <name> <origin> <size> <permissions>' or 'section <name> <region>' at line {}", number)),
+            }
+        }
+
+        Ok(layout)
+    }
+
+    fn number(text: &str) -> Option<u32> {
+        let text = text.replace('_', "");
+        match text.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => text.parse().ok(),
+        }
+    }
+
+    fn overlap(a: &Region, b: &Region) -> bool {
+        a.origin <= b.origin + (b.size - 1) && b.origin <= a.origin + (a.size - 1)
+    }
+
+    /// Gives an origin to each section, given by name, size and needed permissions, in order.
+    pub fn place(&self, sections: &[(&str, u32, Permissions)]) -> Result<Vec<u32>> {
+        let mut used: HashMap<&str, u64> = HashMap::new();
+        let mut origins = vec![];
+
+        for (name, size, permissions) in sections {
+            let region = match self.sections.get(*name).and_then(|r| self.regions.iter().find(|region| region.name == *r)) {
+                Some(region) => region,
+                None => return Err(format!("Section {} is not placed in any region", name)),
+            };
+            if !region.permissions.allows(permissions) {
+                return Err(format!("Section {} needs {} but region {} is {}", name, permissions, region.name, region.permissions));
+            }
+            let offset = used.entry(&region.name).or_insert(0);
+            origins.push(region.origin.wrapping_add(*offset as u32));
+            *offset += *size as u64;
+        }
+
+        for region in self.regions.iter() {
+            match used.get(region.name.as_str()) {
+                Some(used) if *used > region.size as u64 => return Err(format!(
+                    "Region {} (0x{:08x}, {} bytes) overflows by {} bytes",
+                    region.name, region.origin, region.size, used - region.size as u64
+                )),
+                _ => (),
+            }
+        }
+
+        Ok(origins)
+    }
+
+    /// Replaces each `#section` of `nodes` by a `#base` at the origin the layout gives it.
+    pub fn place_nodes(&self, nodes: &mut [Node]) -> Result<()> {
+        let mut sections: Vec<(usize, &str, u32, Permissions)> = vec![];
+        for (index, node) in nodes.iter().enumerate() {
+            match node {
+                Node::Directive(Directive::Section(name)) => sections.push((index, name, 0, Permissions::default())),
+                Node::Directive(Directive::Base(_)) => sections.push((index, "", 0, Permissions::default())),
+                _ => if let Some((_, _, size, permissions)) = sections.last_mut() {
+                    *size += node.size();
+                    *permissions = permissions.union(Permissions::required(node));
+                },
+            }
+        }
+        sections.retain(|(_, name, _, _)| !name.is_empty());
+
+        let origins = self.place(&sections.iter()
+            .map(|(_, name, size, permissions)| (*name, *size, *permissions))
+            .collect::<Vec<_>>())?;
+
+        let indexes: Vec<usize> = sections.iter().map(|(index, _, _, _)| *index).collect();
+        for (index, origin) in indexes.into_iter().zip(origins) {
+            nodes[index] = Node::Directive(Directive::Base(origin));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::op::Op;
+    use crate::parser::Instruction;
+
+    use super::*;
+
+    const LAYOUT: &str = "// regions\nregion ram 0x1000 16 rwx\nregion rom 0xfe00_0000 0x0200_0000 rx\n\nsection text rom\nsection data ram // buffers\n";
+
+    #[test]
+    fn parse() {
+        let layout = Layout::parse(LAYOUT);
+
+        assert_eq!(true, layout.is_ok(), "Expected Ok(...), got {:?}", layout);
+        let layout = layout.unwrap();
+        assert_eq!(vec![
+            Region { name: "ram".to_string(), origin: 0x1000, size: 16, permissions: "rwx".parse().unwrap() },
+            Region { name: "rom".to_string(), origin: 0xfe000000, size: 0x02000000, permissions: "rx".parse().unwrap() },
+        ], layout.regions);
+        assert_eq!(Some(&"rom".to_string()), layout.sections.get("text"));
+        assert_eq!(Some(&"ram".to_string()), layout.sections.get("data"));
+    }
+
+    #[test]
+    fn parse_unknown_region() {
+        let layout = Layout::parse("section text ram\n");
+
+        assert_eq!(Err("Unknown region ram at line 1".to_string()), layout);
+    }
+
+    #[test]
+    fn parse_overlapping_regions() {
+        let layout = Layout::parse("region a 0x1000 0x100 rw\nregion b 0x10fc 4 rw\n");
+
+        assert_eq!(Err("Region b overlaps region a at line 2".to_string()), layout);
+    }
+
+    #[test]
+    fn place_nodes() {
+        let mut nodes = vec![
+            Node::Directive(Directive::Section("text".to_string())),
+            Node::Instruction(Instruction::I(Op::Nop)),
+            Node::Directive(Directive::Section("data".to_string())),
+            Node::Directive(Directive::Space("buffer".to_string(), 8)),
+            Node::Directive(Directive::Section("data".to_string())),
+            Node::Directive(Directive::Word("word".to_string(), 0)),
+        ];
+
+        let r = Layout::parse(LAYOUT).unwrap().place_nodes(&mut nodes);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(Node::Directive(Directive::Base(0xfe000000)), nodes[0]);
+        assert_eq!(Node::Directive(Directive::Base(0x1000)), nodes[2]);
+        assert_eq!(Node::Directive(Directive::Base(0x1008)), nodes[4]);
+    }
+
+    #[test]
+    fn place_overflow() {
+        let r = Layout::parse(LAYOUT).unwrap().place(&[
+            ("data", 12, Permissions::default()),
+            ("data", 8, Permissions::default()),
+        ]);
+
+        assert_eq!(Err("Region ram (0x00001000, 16 bytes) overflows by 4 bytes".to_string()), r);
+    }
+
+    #[test]
+    fn place_permissions() {
+        let r = Layout::parse(LAYOUT).unwrap().place(&[("text", 4, "w".parse().unwrap())]);
+
+        assert_eq!(Err("Section text needs -w- but region rom is r-x".to_string()), r);
+    }
+
+    #[test]
+    fn place_unknown_section() {
+        let r = Layout::default().place(&[("text", 4, Permissions::default())]);
+
+        assert_eq!(Err("Section text is not placed in any region".to_string()), r);
+    }
+}
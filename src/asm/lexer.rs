@@ -6,16 +6,24 @@ use std::str::FromStr;
 use std::vec::IntoIter;
 use crate::lexer::AddressKind::{Absolute, Segment};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Position {
     line: u16,
     column: u16,
 }
 
 impl Position {
-    fn new(line: u16, column: u16) -> Position {
+    pub fn new(line: u16, column: u16) -> Position {
         Position { line, column }
     }
+
+    pub fn line(&self) -> u16 {
+        self.line
+    }
+
+    pub fn column(&self) -> u16 {
+        self.column
+    }
 }
 
 impl fmt::Display for Position {
@@ -24,10 +32,13 @@ impl fmt::Display for Position {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Token {
     Address(Position, String, AddressKind),
+    Colon(Position),
     Comma(Position),
+    RBrace(Position),
+    LBrace(Position),
     RBracket(Position),
     LBracket(Position),
     Plus(Position),
@@ -39,10 +50,11 @@ pub enum Token {
     Label(Position, String),
     Op(Position, String),
     Section(Position, String),
+    String(Position, String),
     Variable(Position, String),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum AddressKind {
     Absolute,
     Segment,
@@ -52,9 +64,12 @@ impl Token {
     pub fn position(&self) -> &Position {
         match &self {
             Token::Address(p, _, _) => p,
+            Token::Colon(p) => p,
             Token::Comma(p) => p,
             Token::Directive(p, _) => p,
             Token::Equal(p) => p,
+            Token::LBrace(p) => p,
+            Token::RBrace(p) => p,
             Token::LBracket(p) => p,
             Token::RBracket(p) => p,
             Token::Plus(p) => p,
@@ -64,14 +79,54 @@ impl Token {
             Token::Label(p, _) => p,
             Token::Op(p, _) => p,
             Token::Section(p, _) => p,
+            Token::String(p, _) => p,
             Token::Variable(p, _) => p,
         }
     }
 }
 
+/// The text between tokens, that the parser does not see.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Trivia {
+    Whitespace(Position, String),
+    /// A comment, from `//` up to the end of the line, without the `\n`.
+    Comment(Position, String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(_, text) => text,
+            Trivia::Comment(_, text) => text,
+        }
+    }
+}
+
+/// A token along with its text as written and the trivia before it. The text of an `Eol` is the
+/// `\n`, or nothing when it ends a comment on the last line.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SyntaxToken {
+    pub leading: Vec<Trivia>,
+    pub token: Token,
+    pub text: String,
+}
+
+impl SyntaxToken {
+    /// The comment ending on this token, if any.
+    pub fn comment(&self) -> Option<(&Position, &str)> {
+        self.leading.iter().find_map(|t| match t {
+            Trivia::Comment(position, text) => Some((position, text.as_str())),
+            _ => None,
+        })
+    }
+}
+
 pub struct Lexer {
     raw_data: Peekable<IntoIter<char>>,
     position: Position,
+    offset: usize,
+    file: Option<String>,
+    source: String,
 }
 
 type Result<T> = std::result::Result<T, String>;
@@ -84,15 +139,78 @@ impl Lexer {
                 line: 1,
                 column: 1,
             },
+            offset: 0,
+            file: None,
+            source: text.to_string(),
         }
     }
 
     pub fn from_file(file_path: &str) -> io::Result<Self> {
-        Ok(Self::from_text(&fs::read_to_string(file_path)?))
+        let mut lexer = Self::from_text(&fs::read_to_string(file_path)?);
+        lexer.file = Some(file_path.to_string());
+        Ok(lexer)
+    }
+
+    /// Takes the text as coming from `file_path`, without reading it.
+    pub fn with_file(mut self, file_path: &str) -> Self {
+        self.file = Some(file_path.to_string());
+        self
+    }
+
+    /// The path of the file being lexed, if the text comes from a file.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+
+    /// The position of the next character, the one an error stops the lexing at.
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// The whole text being lexed.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Lexes the whole text without losing any of it: the syntax tokens, and the trivia after the
+    /// last one. Written in order, their texts are the text lexed.
+    pub fn syntax_tokens(&mut self) -> Result<(Vec<SyntaxToken>, Vec<Trivia>)> {
+        let chars: Vec<char> = self.source.chars().collect();
+        let mut tokens = vec![];
+        loop {
+            let (start, position) = (self.offset, self.position.clone());
+            let token = self.next();
+            let text = &chars[start..self.offset];
+
+            let whitespace = text.iter().take_while(|c| c.is_whitespace() && **c != '\n').count();
+            let mut leading = vec![];
+            if whitespace > 0 {
+                leading.push(Trivia::Whitespace(position, text[..whitespace].iter().collect()));
+            }
+            let token = match token {
+                None => return Ok((tokens, leading)),
+                Some(token) => token?,
+            };
+
+            let mut text: String = text[whitespace..].iter().collect();
+            // a comment is lexed as the end of its line
+            if let Token::Eol(position) = &token {
+                if text.starts_with("//") {
+                    let newline = text.ends_with('\n') as usize;
+                    let comment = text[..text.len() - newline].to_string();
+                    leading.push(Trivia::Comment(position.clone(), comment));
+                    text = text[text.len() - newline..].to_string();
+                }
+            }
+            tokens.push(SyntaxToken { leading, token, text });
+        }
     }
 
     fn next_char(&mut self) -> Option<char> {
         let char = self.raw_data.next();
+        if char.is_some() {
+            self.offset += 1;
+        }
         match char {
             Some('\n') => {
                 self.position.column = 1;
@@ -125,6 +243,8 @@ impl Lexer {
 
     fn is_section(c: char) -> bool { c == '.' }
 
+    fn is_string(c: char) -> bool { c == '"' }
+
     fn is_variable(c: char) -> bool {
         c == '$'
     }
@@ -152,6 +272,24 @@ impl Lexer {
         }
     }
 
+    /// Parses a string in the form `$[a-z][A-Za-z0-9_]*(.[a-z][A-Za-z0-9_]*)*` where the `$`
+    /// comes as parameter.
+    fn variable(&mut self, c: char) -> Result<String> {
+        let mut variable = self.identifier(c)?;
+
+        while let Some('.') = self.raw_data.peek() {
+            self.next_char();
+            let part = self.identifier('\0')?;
+            if part.is_empty() {
+                return Err(format!("Expected identifier after `.` at {}", self.position));
+            }
+            variable.push('.');
+            variable.push_str(&part);
+        }
+
+        Ok(variable)
+    }
+
     /// Parses a string in the form `[A-Z][A-Za-z0-9_]*` where the first char comes as parameter.
     fn op(&mut self, c: char) -> Result<String> {
         let mut op: String = c.to_string();
@@ -167,6 +305,25 @@ impl Lexer {
         }
     }
 
+    /// Parses a string in the form `"[^"\n]*"` where the opening `"` was already consumed.
+    fn string(&mut self) -> Result<String> {
+        let mut string = String::new();
+
+        loop {
+            match self.raw_data.peek() {
+                Some('"') => {
+                    self.next_char();
+                    return Ok(string);
+                }
+                Some('\n') | None => return Err(format!("Unterminated string at {}", self.position)),
+                Some(c) => {
+                    string.push(*c);
+                    self.next_char();
+                }
+            }
+        }
+    }
+
     /// Parses a string in one of the forms `0b[01]+` or `0x[0-9A-Fa-f]+` or `[0-9_]+` where the
     /// first char comes as parameter.
     fn number(&mut self, c: char) -> Result<u32> {
@@ -289,6 +446,8 @@ impl Iterator for Lexer {
                 Some('=') => return Some(Ok(Token::Equal(position))),
                 Some('[') => return Some(Ok(Token::LBracket(position))),
                 Some(']') => return Some(Ok(Token::RBracket(position))),
+                Some('{') => return Some(Ok(Token::LBrace(position))),
+                Some('}') => return Some(Ok(Token::RBrace(position))),
                 Some('+') => return Some(Ok(Token::Plus(position))),
                 Some(c) if c.is_whitespace() => continue,
                 Some(c) if Self::is_absolute_address(c) => return Some(self.identifier('\0').map(|s| Token::Address(position, s, Absolute))),
@@ -296,10 +455,15 @@ impl Iterator for Lexer {
                 Some(c) if Self::is_directive(c) => return Some(self.identifier('\0').map(|s| Token::Directive(position, s))),
                 Some(c) if Self::is_identifier(c) => return Some(self.identifier(c).map(|s| Token::Identifier(position, s))),
                 Some(c) if Self::is_number(c) => return Some(self.number(c).map(|n| Token::Integer(position, n))),
-                Some(c) if Self::is_label(c) => return Some(self.identifier('\0').map(|s| Token::Label(position, s))),
+                Some(c) if Self::is_label(c) => return Some(self.identifier('\0').map(|s| if s.is_empty() {
+                    Token::Colon(position)
+                } else {
+                    Token::Label(position, s)
+                })),
                 Some(c) if Self::is_op(c) => return Some(self.op(c).map(|s| Token::Op(position, s))),
                 Some(c) if Self::is_section(c) => return Some(self.identifier('\0').map(|s| Token::Section(position, s))),
-                Some(c) if Self::is_variable(c) => return Some(self.identifier(c).map(|s| Token::Variable(position, s))),
+                Some(c) if Self::is_string(c) => return Some(self.string().map(|s| Token::String(position, s))),
+                Some(c) if Self::is_variable(c) => return Some(self.variable(c).map(|s| Token::Variable(position, s))),
                 Some(c) => return Some(Err(format!("Unexpected `{}`", c))),
                 None => return None,
             }
@@ -424,6 +588,84 @@ mod tests {
         assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
     }
 
+    #[test]
+    fn test_rbrace() {
+        let r = Lexer::from_text(" } ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_ok(), "Expected Ok(Token::RBrace), got {:?}", item);
+
+        let expected = Token::RBrace(Position::new(1, 2));
+        let actual = item.unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_lbrace() {
+        let r = Lexer::from_text(" { ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_ok(), "Expected Ok(Token::LBrace), got {:?}", item);
+
+        let expected = Token::LBrace(Position::new(1, 2));
+        let actual = item.unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_colon() {
+        let r = Lexer::from_text(" : ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_ok(), "Expected Ok(Token::Colon), got {:?}", item);
+
+        let expected = Token::Colon(Position::new(1, 2));
+        let actual = item.unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_variable() {
+        let r = Lexer::from_text(" $point ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_ok(), "Expected Ok(Token::Variable), got {:?}", item);
+
+        let expected = Token::Variable(Position::new(1, 2), "$point".to_string());
+        let actual = item.unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_variable_namespaced() {
+        let r = Lexer::from_text(" $point.x ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_ok(), "Expected Ok(Token::Variable), got {:?}", item);
+
+        let expected = Token::Variable(Position::new(1, 2), "$point.x".to_string());
+        let actual = item.unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_variable_namespaced_invalid() {
+        let r = Lexer::from_text(" $point. ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_err(), "Expected Err(...), got {:?}", item);
+
+        let expected = "Expected identifier after `.` at 1:9".to_string();
+        let actual = item.err().unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
     #[test]
     fn test_directive() {
         let r = Lexer::from_text(" #base 12 ").next();
@@ -489,6 +731,32 @@ mod tests {
         assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
     }
 
+    #[test]
+    fn test_string() {
+        let r = Lexer::from_text(" \"logo.ppm\" ").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_ok(), "Expected Ok(Token::String), got {:?}", item);
+
+        let expected = Token::String(Position::new(1, 2), "logo.ppm".to_string());
+        let actual = item.unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn test_string_unterminated() {
+        let r = Lexer::from_text(" \"logo.ppm\n").next();
+        assert_eq!(true, r.is_some(), "Expected Some(...), got {:?}", r);
+
+        let item = r.unwrap();
+        assert_eq!(true, item.is_err(), "Expected Err(...), got {:?}", item);
+
+        let expected = "Unterminated string at 1:11".to_string();
+        let actual = item.err().unwrap();
+        assert_eq!(expected, actual, "Expected {:?}, got {:?}", expected, actual);
+    }
+
     #[test]
     fn test_identifier() {
         let r = Lexer::from_text(" string ").next();
@@ -601,4 +869,49 @@ mod tests {
             i += 1;
         }
     }
+
+    #[test]
+    fn test_syntax_tokens() {
+        let text = "  MOV r0, 0x1_0 // one\n\t// two";
+        let r = Lexer::from_text(text).syntax_tokens();
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let (tokens, trailing) = r.unwrap();
+        let expected = vec![
+            SyntaxToken {
+                leading: vec![Trivia::Whitespace(Position::new(1, 1), "  ".to_string())],
+                token: Token::Op(Position::new(1, 3), "MOV".to_string()),
+                text: "MOV".to_string(),
+            },
+            SyntaxToken {
+                leading: vec![Trivia::Whitespace(Position::new(1, 6), " ".to_string())],
+                token: Token::Identifier(Position::new(1, 7), "r0".to_string()),
+                text: "r0".to_string(),
+            },
+            SyntaxToken { leading: vec![], token: Token::Comma(Position::new(1, 9)), text: ",".to_string() },
+            SyntaxToken {
+                leading: vec![Trivia::Whitespace(Position::new(1, 10), " ".to_string())],
+                token: Token::Integer(Position::new(1, 11), 16),
+                text: "0x1_0".to_string(),
+            },
+            SyntaxToken {
+                leading: vec![
+                    Trivia::Whitespace(Position::new(1, 16), " ".to_string()),
+                    Trivia::Comment(Position::new(1, 17), "// one".to_string()),
+                ],
+                token: Token::Eol(Position::new(1, 17)),
+                text: "\n".to_string(),
+            },
+            SyntaxToken {
+                leading: vec![
+                    Trivia::Whitespace(Position::new(2, 1), "\t".to_string()),
+                    Trivia::Comment(Position::new(2, 2), "// two".to_string()),
+                ],
+                token: Token::Eol(Position::new(2, 2)),
+                text: "".to_string(),
+            },
+        ];
+        assert_eq!(expected, tokens);
+        assert_eq!(Vec::<Trivia>::new(), trailing);
+    }
 }
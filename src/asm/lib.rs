@@ -0,0 +1,31 @@
+pub mod op;
+pub mod constants;
+pub mod registers;
+pub mod lexer;
+pub mod parser;
+pub mod address_resolver;
+pub mod checker;
+pub mod container;
+pub mod cst;
+pub mod emitter;
+pub mod debug_map;
+pub mod declarations;
+pub mod disassembler;
+pub mod dump;
+pub mod formatter;
+pub mod image;
+pub mod json;
+pub mod layout;
+pub mod archive;
+pub mod linker;
+pub mod listing;
+pub mod object;
+pub mod output;
+pub mod symbols;
+pub mod visibility;
+pub mod assembler;
+pub mod builder;
+pub mod simulator;
+pub mod test_runner;
+
+pub use assembler::{assemble, Diagnostic, Image, Options, Source};
@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+
+use crate::address_resolver::{Address, AddressResolver};
+use crate::emitter::Segment;
+use crate::layout::{Layout, Permissions};
+use crate::object::Object;
+use crate::parser::AddressKind;
+
+/// Lays out the sections of the objects, in order, resolves the labels, within each object then
+/// among the global labels of all objects, and applies the relocations. Linking the objects of
+/// several sources gives the same image as assembling the sources together.
+pub struct Linker<'t> {
+    objects: &'t [(String, Object)],
+    layout: Option<&'t Layout>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl<'t> Linker<'t> {
+    /// Links `objects`, given along with the name of the file they were read from.
+    pub fn new(objects: &'t [(String, Object)]) -> Linker<'t> {
+        Linker { objects, layout: None }
+    }
+
+    /// Places the named sections in the regions of `layout`.
+    pub fn with_layout(mut self, layout: &'t Layout) -> Self {
+        self.layout = Some(layout);
+        self
+    }
+
+    pub fn link(&self) -> Result<(Vec<Segment>, HashMap<String, Address>)> {
+        // an unnamed section continues the section before it, even in the next object
+        let mut named: Vec<(&str, u32, Permissions)> = vec![];
+        let mut placed = false;
+        for section in self.objects.iter().flat_map(|(_, object)| object.sections.iter()) {
+            let size = section.bytes.len() as u32;
+            match (section.origin, &section.name) {
+                (Some(_), _) => placed = false,
+                (None, Some(name)) => {
+                    named.push((name, size, section.permissions));
+                    placed = true;
+                }
+                (None, None) => if let (true, Some((_, total, permissions))) = (placed, named.last_mut()) {
+                    *total += size;
+                    *permissions = permissions.union(section.permissions);
+                },
+            }
+        }
+        let mut origins = self.layout.unwrap_or(&Layout::default()).place(&named)?.into_iter();
+
+        let mut segments = vec![Segment { origin: 0, bytes: vec![] }];
+        let mut placements = vec![];
+
+        for (_, object) in self.objects {
+            let mut sections = vec![];
+            for section in object.sections.iter() {
+                match (section.origin, &section.name) {
+                    (Some(origin), _) => segments.push(Segment { origin, bytes: vec![] }),
+                    (None, Some(_)) => segments.push(Segment { origin: origins.next().unwrap(), bytes: vec![] }),
+                    (None, None) => (),
+                }
+                let index = segments.len() - 1;
+                sections.push((index, segments[index].bytes.len() as u32));
+                segments[index].bytes.extend_from_slice(&section.bytes);
+            }
+            placements.push(sections);
+        }
+
+        AddressResolver::check_segments(segments.iter()
+            .map(|s| (s.origin, s.bytes.len() as u32))
+            .collect())?;
+
+        let mut addresses = HashMap::new();
+        let mut globals: HashMap<&String, (&String, Address)> = HashMap::new();
+        let mut locals: Vec<HashMap<&String, Address>> = vec![];
+        for ((file, object), sections) in self.objects.iter().zip(placements.iter()) {
+            let mut own = HashMap::new();
+            for symbol in object.symbols.iter() {
+                let (segment, offset) = sections[symbol.section as usize];
+                let address = Address {
+                    segment: segments[segment].origin,
+                    offset: offset + symbol.offset,
+                };
+                if symbol.global {
+                    if let Some((other, _)) = globals.insert(&symbol.name, (file, address)) {
+                        return Err(format!("Label {} defined in {} and {}", symbol.name, other, file));
+                    }
+                }
+                own.insert(&symbol.name, address);
+                addresses.insert(symbol.name.to_owned(), address);
+            }
+            locals.push(own);
+        }
+
+        for (((file, object), sections), own) in self.objects.iter().zip(placements.iter()).zip(locals.iter()) {
+            for relocation in object.relocations.iter() {
+                let address = match own.get(&relocation.symbol).or_else(|| globals.get(&relocation.symbol).map(|(_, a)| a)) {
+                    Some(address) => address,
+                    None => return Err(format!("Label {} is missing in {}", relocation.symbol, file)),
+                };
+                let value = match relocation.kind {
+                    AddressKind::Absolute => address.absolute(),
+                    AddressKind::Segment => address.offset,
+                };
+                let (segment, offset) = sections[relocation.section as usize];
+                let start = (offset + relocation.offset) as usize;
+                segments[segment].bytes[start..start + 4].copy_from_slice(&value.to_be_bytes());
+            }
+        }
+
+        segments.retain(|s| !s.bytes.is_empty());
+        Ok((segments, addresses))
+    }
+
+    /// The address of the label given by `#entry`, which at most one object can give.
+    pub fn entry(&self, addresses: &HashMap<String, Address>) -> Result<Option<u32>> {
+        let mut entry: Option<(&String, &String)> = None;
+        for (file, object) in self.objects.iter() {
+            if let Some(label) = &object.entry {
+                if let Some((other, _)) = entry.replace((file, label)) {
+                    return Err(format!("Entry point defined in {} and {}", other, file));
+                }
+            }
+        }
+        match entry {
+            None => Ok(None),
+            Some((file, label)) => match addresses.get(label) {
+                Some(address) => Ok(Some(address.absolute())),
+                None => Err(format!("Label {} is missing in {}", label, file)),
+            },
+        }
+    }
+
+    /// The number of general purpose registers the objects need.
+    pub fn registers(&self) -> u8 {
+        self.objects.iter().map(|(_, object)| object.registers).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::emitter::Emitter;
+    use crate::lexer::Lexer;
+    use crate::op::Op;
+    use crate::parser::{Node, Parser, Spans};
+    use crate::visibility::Visibility;
+
+    use super::*;
+
+    fn object(source: &str) -> Object {
+        let mut lexer = Lexer::from_text(source);
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse().unwrap();
+        Object::new(&nodes).unwrap()
+    }
+
+    /// Parses the sources, given with their file name, and applies the labels visibility.
+    fn parse(files: &[(String, &str)], allow_externs: bool) -> Vec<Node> {
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        for (index, (file, source)) in files.iter().enumerate() {
+            let mut lexer = Lexer::from_text(source);
+            let start = spans.nodes.len();
+            Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).with_source(index).parse().unwrap();
+            spans.nodes[start..].iter_mut().for_each(|span| span.file = Some(file.to_owned()));
+        }
+        let mut visibility = Visibility::new(&mut nodes, &spans);
+        if allow_externs {
+            visibility = visibility.allow_externs();
+        }
+        visibility.apply().unwrap();
+        nodes
+    }
+
+    #[test]
+    fn link_as_assembled_together() {
+        let files = [
+            ("a.a".to_string(), "#global start\n#global data\n#extern end\n:start\n    J @end\n:loop\n    J @loop\n#base 0x2000\n:data\n#word word 1\n"),
+            ("b.a".to_string(), "#global end\n#extern data\n#extern start\n    MOV r0, &data\n:loop\n    J @loop\n#base 0x1000\n:end\n    J &start\n"),
+        ];
+        let objects: Vec<(String, Object)> = files.iter()
+            .map(|file| (file.0.replace(".a", ".o"), Object::new(&parse(std::slice::from_ref(file), true)).unwrap()))
+            .collect();
+
+        let linked = Linker::new(&objects).link();
+
+        let nodes = parse(&files, false);
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+        let (segments, _) = Emitter::new(&nodes, &addresses).emit();
+
+        assert_eq!(Ok((segments, addresses)), linked);
+    }
+
+    #[test]
+    fn link_with_layout() {
+        let objects = vec![
+            ("a.o".to_string(), object("#global start\n#section text\n:start\n    J &end\n#section data\n#word a 1\n")),
+            ("b.o".to_string(), object("#global end\n#section text\n:end\n    J &start\n#section data\n#word b 2\n")),
+        ];
+        let layout = Layout::parse("region ram 0x1000 0x100 rw\nregion rom 0x2000 0x100 rx\nsection text rom\nsection data ram\n").unwrap();
+
+        let linked = Linker::new(&objects).with_layout(&layout).link();
+
+        assert_eq!(true, linked.is_ok(), "Expected Ok(...), got {:?}", linked);
+        let (segments, addresses) = linked.unwrap();
+        assert_eq!(vec![
+            Segment { origin: 0x2000, bytes: vec![Op::JA.bytecode(), 0, 0, 0, 0x00, 0x00, 0x20, 0x08] },
+            Segment { origin: 0x1000, bytes: vec![0, 0, 0, 1] },
+            Segment { origin: 0x2008, bytes: vec![Op::JA.bytecode(), 0, 0, 0, 0x00, 0x00, 0x20, 0x00] },
+            Segment { origin: 0x1004, bytes: vec![0, 0, 0, 2] },
+        ], segments);
+        assert_eq!(Some(&Address { segment: 0x1004, offset: 0 }), addresses.get("b"));
+    }
+
+    #[test]
+    fn link_continued_section() {
+        let objects = vec![
+            ("a.o".to_string(), object("#section text\n    NOP\n")),
+            ("b.o".to_string(), object("    NOP\n#section text\n    HALT\n")),
+        ];
+        let layout = Layout::parse(include_str!("../common/thm.layout")).unwrap();
+
+        let linked = Linker::new(&objects).with_layout(&layout).link();
+
+        assert_eq!(true, linked.is_ok(), "Expected Ok(...), got {:?}", linked);
+        assert_eq!(vec![
+            Segment { origin: 0x1000, bytes: vec![Op::Nop.bytecode(), 0, 0, 0, Op::Nop.bytecode(), 0, 0, 0] },
+            Segment { origin: 0x1008, bytes: vec![Op::Halt.bytecode(), 0, 0, 0] },
+        ], linked.unwrap().0);
+    }
+
+    #[test]
+    fn link_continued_section_overflow() {
+        let objects = vec![
+            ("a.o".to_string(), object("#section rom\n    NOP\n")),
+            ("b.o".to_string(), object("    NOP\n")),
+        ];
+        let layout = Layout::parse("region rom 0x2000 4 rx\nsection rom rom\n").unwrap();
+
+        let linked = Linker::new(&objects).with_layout(&layout).link();
+
+        assert_eq!(Err("Region rom (0x00002000, 4 bytes) overflows by 4 bytes".to_string()), linked);
+    }
+
+    #[test]
+    fn link_entry() {
+        let objects = vec![
+            ("a.o".to_string(), object("    MOV r7, 0\n")),
+            ("b.o".to_string(), object("#entry start\n    NOP\n:start\n    MOV r2, 0\n")),
+        ];
+
+        let linker = Linker::new(&objects);
+        let (_, addresses) = linker.link().unwrap();
+
+        assert_eq!(Ok(Some(12)), linker.entry(&addresses));
+        assert_eq!(8, linker.registers());
+    }
+
+    #[test]
+    fn link_duplicate_entry() {
+        let objects = vec![
+            ("a.o".to_string(), object("#entry a\n:a\n")),
+            ("b.o".to_string(), object("#entry b\n:b\n")),
+        ];
+
+        let linker = Linker::new(&objects);
+        let (_, addresses) = linker.link().unwrap();
+
+        assert_eq!(Err("Entry point defined in a.o and b.o".to_string()), linker.entry(&addresses));
+    }
+
+    #[test]
+    fn link_unplaced_section() {
+        let objects = vec![("a.o".to_string(), object("#section text\n    NOP\n"))];
+
+        let linked = Linker::new(&objects).link();
+
+        assert_eq!(Err("Section text is not placed in any region".to_string()), linked);
+    }
+
+    #[test]
+    fn link_missing_label() {
+        let objects = vec![("a.o".to_string(), object("    J @missing\n"))];
+
+        let linked = Linker::new(&objects).link();
+
+        assert_eq!(Err("Label missing is missing in a.o".to_string()), linked);
+    }
+
+    #[test]
+    fn link_local_labels() {
+        let objects = vec![
+            ("a.o".to_string(), object(":loop\n    J &loop\n")),
+            ("b.o".to_string(), object(":loop\n    J &loop\n")),
+        ];
+
+        let linked = Linker::new(&objects).link();
+
+        assert_eq!(true, linked.is_ok(), "Expected Ok(...), got {:?}", linked);
+        assert_eq!(vec![Segment {
+            origin: 0,
+            bytes: vec![Op::JA.bytecode(), 0, 0, 0, 0, 0, 0, 0, Op::JA.bytecode(), 0, 0, 0, 0, 0, 0, 8],
+        }], linked.unwrap().0);
+    }
+
+    #[test]
+    fn link_duplicate_label() {
+        let objects = vec![
+            ("a.o".to_string(), object("#global start\n:start\n")),
+            ("b.o".to_string(), object("#global start\n:start\n")),
+        ];
+
+        let linked = Linker::new(&objects).link();
+
+        assert_eq!(Err("Label start defined in a.o and b.o".to_string()), linked);
+    }
+
+    #[test]
+    fn link_overlapping_sections() {
+        let objects = vec![
+            ("a.o".to_string(), object("#base 0x1000\n    NOP\n")),
+            ("b.o".to_string(), object("#base 0x1000\n    NOP\n")),
+        ];
+
+        let linked = Linker::new(&objects).link();
+
+        assert_eq!(Err("Segment at 0x00001000 (4 bytes) overlaps segment at 0x00001000".to_string()), linked);
+    }
+}
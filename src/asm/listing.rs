@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+
+use crate::address_resolver::Address;
+use crate::emitter::Segment;
+use crate::lexer::Token;
+use crate::parser::Span;
+
+/// Writes, for each line of each source, its number, the address and the bytes emitted for it
+/// and the source text, followed by the labels and variables.
+pub struct Listing<'t> {
+    sources: &'t [(String, String)],
+    spans: &'t [Span],
+    ranges: &'t [Range<u32>],
+    segments: &'t [Segment],
+    addresses: &'t HashMap<String, Address>,
+    symbols: &'t HashMap<String, Token>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Past this number of bytes, only the first ones are listed.
+const MAX_BYTES: usize = 8;
+
+impl<'t> Listing<'t> {
+    pub fn new(
+        sources: &'t [(String, String)],
+        spans: &'t [Span],
+        ranges: &'t [Range<u32>],
+        segments: &'t [Segment],
+        addresses: &'t HashMap<String, Address>,
+        symbols: &'t HashMap<String, Token>,
+    ) -> Listing<'t> {
+        Listing { sources, spans, ranges, segments, addresses, symbols }
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        self.write_listing(out).map_err(|e| e.to_string())
+    }
+
+    fn write_listing<W: Write>(&self, out: &mut W) -> std::io::Result<()> {
+        let mut lines: HashMap<(&str, u16), &Range<u32>> = HashMap::new();
+        for (span, range) in self.spans.iter().zip(self.ranges.iter()) {
+            if let Some(file) = span.file.as_ref() {
+                lines.entry((file.as_str(), span.position.line())).or_insert(range);
+            }
+        }
+
+        for (file, source) in self.sources {
+            writeln!(out, "File: {}", file)?;
+            for (number, text) in source.lines().enumerate() {
+                let number = number + 1;
+                let line = match lines.get(&(file.as_str(), number as u16)) {
+                    Some(range) => format!("{:>5} {:08x} {:<21} {}", number, range.start, self.bytes(range), text),
+                    None => format!("{:>5} {:8} {:<21} {}", number, "", "", text),
+                };
+                writeln!(out, "{}", line.trim_end())?;
+            }
+            writeln!(out)?;
+        }
+
+        let mut labels: Vec<(&String, u32)> = self.addresses.iter()
+            .map(|(label, address)| (label, address.absolute()))
+            .collect();
+        labels.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        writeln!(out, "Labels:")?;
+        for (label, address) in labels {
+            writeln!(out, "{:08x} {}", address, label)?;
+        }
+        writeln!(out)?;
+
+        let mut variables: Vec<(&String, u32)> = self.symbols.iter()
+            .filter_map(|(name, token)| match token {
+                Token::Integer(_, value) => Some((name, *value)),
+                _ => None,
+            })
+            .collect();
+        variables.sort();
+        writeln!(out, "Variables:")?;
+        for (name, value) in variables {
+            writeln!(out, "{:08x} {}", value, name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Formats the bytes at `range` as space-separated words.
+    fn bytes(&self, range: &Range<u32>) -> String {
+        let bytes = match self.segments.iter().find(|s| s.origin <= range.start && range.end <= s.origin.wrapping_add(s.bytes.len() as u32)) {
+            Some(segment) if !range.is_empty() => {
+                let start = (range.start - segment.origin) as usize;
+                &segment.bytes[start..start + (range.end - range.start) as usize]
+            }
+            _ => return String::new(),
+        };
+
+        let mut words: Vec<String> = bytes[..bytes.len().min(MAX_BYTES)]
+            .chunks(4)
+            .map(|word| word.iter().map(|b| format!("{:02x}", b)).collect())
+            .collect();
+        if bytes.len() > MAX_BYTES {
+            words.push("...".to_string());
+        }
+        words.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address_resolver::AddressResolver;
+    use crate::emitter::Emitter;
+    use crate::lexer::Lexer;
+    use crate::parser::{Parser, Spans};
+
+    use super::*;
+
+    #[test]
+    fn write() {
+        let source = "$v = 1\n:start\n    PUSH $v\n#word data 0x0102_0304\n".to_string();
+        let mut lexer = Lexer::from_text(&source);
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).parse().unwrap();
+        let spans: Vec<Span> = spans.nodes.into_iter()
+            .map(|span| Span { file: Some("test.a".to_string()), ..span })
+            .collect();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+        let (segments, ranges) = Emitter::new(&nodes, &addresses).emit();
+        let sources = vec![("test.a".to_string(), source)];
+
+        let mut out = vec![];
+        let r = Listing::new(&sources, &spans, &ranges, &segments, &addresses, &symbols).write(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let push = format!("    3 00000000 {:02x}000000 00000001         PUSH $v", crate::op::Op::PushW.bytecode());
+        let expected = format!("{}\n{}\n{}\n{}\n{}\n\n{}\n{}\n{}\n\n{}\n{}\n",
+            "File: test.a",
+            "    1                                $v = 1",
+            "    2 00000000                       :start",
+            push,
+            "    4 00000008 01020304              #word data 0x0102_0304",
+            "Labels:",
+            "00000000 start",
+            "00000008 data",
+            "Variables:",
+            "00000001 $v",
+        );
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+    }
+}
@@ -1,71 +1,290 @@
-use std::collections::HashMap;
-use std::fs::OpenOptions;
+use std::fs::{self, File, OpenOptions};
 use std::io::Write;
+use std::process;
 
-use clap::{App, Arg, ArgMatches, crate_authors, crate_version};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand, crate_authors, crate_version};
 
-use constants::REG_COUNT;
-
-use crate::address_resolver::AddressResolver;
-use crate::checker::{Checker, VmConfig};
-use crate::emitter::Emitter;
-use crate::lexer::{Lexer, Token};
-use crate::parser::Parser;
-
-mod op;
-mod constants;
-mod lexer;
-mod parser;
-mod address_resolver;
-mod checker;
-mod emitter;
+use tha::assembler::{self, Diagnostic, Options, Source};
+use tha::debug_map::DebugMap;
+use tha::declarations::Declarations;
+use tha::dump;
+use tha::formatter;
+use tha::json::Json;
+use tha::layout::Layout;
+use tha::listing::Listing;
+use tha::output::{self, Format};
+use tha::parser::ParseResult;
+use tha::symbols::SymbolMap;
+use tha::test_runner::{self, Runner};
 
 fn main() {
     let matches = parse_opts();
-    let input: Vec<_> = matches.values_of("input").unwrap().collect();
+    if let Some(matches) = matches.subcommand_matches("fmt") {
+        fmt(matches.values_of("files").unwrap().collect(), matches.is_present("check"));
+        return;
+    }
+    if let Some(matches) = matches.subcommand_matches("test") {
+        test(matches.values_of("files").unwrap().collect(), matches.value_of("rom"), matches.value_of("step_limit"));
+        return;
+    }
+
     let output = matches.value_of("output").unwrap();
+    let format: Format = matches.value_of("format").unwrap().parse().unwrap();
 
-    let mut symbols: HashMap<String, Token> = HashMap::new();
-    let mut nodes = vec![];
-    for f in input {
-        let mut lexer = Lexer::from_file(f).unwrap();
-        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
-        if let Err(err) = parser.parse() {
-            println!("Syntax error: {}", err);
-            return;
+    let mut sources = vec![];
+    for f in matches.values_of("input").unwrap() {
+        match Source::from_file(f) {
+            Ok(source) => sources.push(source),
+            Err(err) => {
+                println!("Input error: {}", err);
+                return;
+            }
         }
     }
 
-    let addresses = match AddressResolver::new(&nodes).resolve() {
-        Err(err) => {
-            println!("Semantic error: {}", err);
-            return;
+    if matches.value_of("emit") == Some("tokens") {
+        match assembler::tokens(&sources) {
+            Ok(tokens) => {
+                let files: Vec<_> = sources.iter().map(|s| s.file.clone()).zip(tokens).collect();
+                write_file(output, "tokens", |file| write_json(file, dump::tokens(&files)));
+            }
+            Err(diagnostics) => report(&diagnostics),
         }
-        Ok(addresses) => addresses,
+        return;
+    }
+
+    let parsed = match assembler::parse(&sources) {
+        Ok(parsed) => parsed,
+        Err(diagnostics) => return report(&diagnostics),
     };
 
-    match Checker::new(VmConfig {
-        register_count: REG_COUNT as u8,
-    }).check(&nodes) {
-        None => (),
-        Some(errors) => {
-            errors.iter().for_each(|error| println!("Semantic error: {}", error));
+    if matches.is_present("compile") {
+        match parsed.compile() {
+            Ok(object) => {
+                write_file(output, "object", |file| object.write(file));
+            }
+            Err(diagnostics) => report(&diagnostics),
+        }
+        return;
+    }
+
+    let layout = match matches.value_of("layout").map(Layout::from_file) {
+        None => Layout::default(),
+        Some(Ok(layout)) => layout,
+        Some(Err(err)) => {
+            println!("Layout error: {}", err);
             return;
         }
+    };
+
+    let resolved = match parsed.resolve(&Options::new().with_layout(layout)) {
+        Ok(resolved) => resolved,
+        Err(diagnostics) => return report(&diagnostics),
+    };
+
+    if matches.value_of("emit") == Some("ast") {
+        let result = ParseResult { nodes: resolved.nodes, symbols: resolved.symbols };
+        let ast = dump::ast(&result, &resolved.spans.nodes, &resolved.addresses);
+        write_file(output, "ast", |file| write_json(file, ast));
+        return;
     }
 
-    let code = Emitter::new(&nodes, &addresses).emit();
+    let image = match resolved.emit() {
+        Ok(image) => image,
+        Err(diagnostics) => return report(&diagnostics),
+    };
 
-    OpenOptions::new()
+    let mut file = OpenOptions::new()
         .create(true)
         .write(true)
         .truncate(true)
         .open(output)
-        .unwrap()
-        .write_all(code.as_slice())
         .unwrap();
+    if let Err(err) = output::write(format, &image.container, &mut file) {
+        println!("Output error: {}", err);
+        return;
+    }
+
+    for segment in image.container.segments.iter() {
+        println!("Wrote {} bytes at 0x{:08x} to {}", segment.bytes.len(), segment.origin, output);
+    }
+
+    if let Some(listing) = matches.value_of("listing") {
+        let sources: Vec<_> = image.sources.iter()
+            .map(|s| (s.file.clone().unwrap_or_default(), s.text.clone()))
+            .collect();
+        let listing_writer = Listing::new(&sources, &image.spans.nodes, &image.ranges, &image.container.segments, &image.addresses, &image.symbols);
+        if !write_file(listing, "listing", |file| listing_writer.write(file)) {
+            return;
+        }
+    }
+
+    if matches.is_present("debug") {
+        let debug_map = DebugMap::new(&image.nodes, &image.spans.nodes, &image.ranges);
+        if !write_file(&format!("{}.dbg", output), "debug map", |file| debug_map.write(file)) {
+            return;
+        }
+    }
 
-    println!("Wrote {} bytes to {}", code.len(), output);
+    let symbol_map = SymbolMap::new(&image.nodes, &image.spans, &image.addresses, &image.symbols)
+        .with_enums(matches.is_present("enums"));
+    if let Some(symbols_file) = matches.value_of("symbols") {
+        if !write_file(symbols_file, "symbols", |file| symbol_map.write(file)) {
+            return;
+        }
+    }
+
+    if !matches.is_present("c_header") && !matches.is_present("rust") {
+        return;
+    }
+    let declarations = match Declarations::new(&symbol_map.symbols()) {
+        Ok(declarations) => declarations,
+        Err(err) => {
+            println!("Declarations error: {}", err);
+            return;
+        }
+    };
+    if let Some(header) = matches.value_of("c_header") {
+        if !write_file(header, "C header", |file| declarations.write_c_header(file)) {
+            return;
+        }
+    }
+    if let Some(constants) = matches.value_of("rust") {
+        write_file(constants, "Rust constants", |file| declarations.write_rust(file));
+    }
+}
+
+fn report(diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        match (&diagnostic.file, &diagnostic.position) {
+            (Some(file), Some(position)) => println!("{}:{}: {}", file, position, diagnostic),
+            (None, Some(position)) => println!("{}: {}", position, diagnostic),
+            _ => println!("{}", diagnostic),
+        }
+    }
+}
+
+/// Formats the files in place or, with `check`, lists those that are not formatted and exits with
+/// status 1 if any.
+fn fmt(files: Vec<&str>, check: bool) {
+    let mut unformatted = false;
+    for file in files {
+        let source = match fs::read_to_string(file) {
+            Ok(source) => source,
+            Err(err) => {
+                println!("Input error: {}: {}", file, err);
+                process::exit(2);
+            }
+        };
+        let formatted = match formatter::format(&source) {
+            Ok(formatted) => formatted,
+            Err(err) => {
+                println!("Syntax error: {}: {}", file, err);
+                process::exit(2);
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("Not formatted: {}", file);
+            unformatted = true;
+        } else if let Err(err) = fs::write(file, formatted) {
+            println!("Output error: {}: {}", file, err);
+            process::exit(2);
+        } else {
+            println!("Formatted {}", file);
+        }
+    }
+    if unformatted {
+        process::exit(1);
+    }
+}
+
+/// Runs the `#test` blocks of the files, exiting with status 1 if any fails, or 2 if a file cannot
+/// be read or assembled.
+fn test(files: Vec<&str>, rom: Option<&str>, step_limit: Option<&str>) {
+    let source = |file: &str| match Source::from_file(file) {
+        Ok(source) => source,
+        Err(err) => {
+            println!("Input error: {}", err);
+            process::exit(2);
+        }
+    };
+
+    let mut runner = Runner::new();
+    if let Some(rom) = rom {
+        match test_runner::rom(&source(rom)) {
+            Ok(rom) => runner = runner.with_rom(rom),
+            Err(errors) => {
+                errors.iter().for_each(|error| println!("{}: {}", rom, error));
+                process::exit(2);
+            }
+        }
+    }
+    if let Some(step_limit) = step_limit {
+        match step_limit.parse() {
+            Ok(step_limit) => runner = runner.with_step_limit(step_limit),
+            Err(err) => {
+                println!("Input error: step limit {}: {}", step_limit, err);
+                process::exit(2);
+            }
+        }
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for file in files {
+        let outcomes = match runner.run(&source(file)) {
+            Ok(outcomes) => outcomes,
+            Err(errors) => {
+                errors.iter().for_each(|error| println!("{}: {}", file, error));
+                process::exit(2);
+            }
+        };
+        for outcome in outcomes {
+            if outcome.passed() {
+                println!("test {}:{} {} ... ok", file, outcome.span.position, outcome.name);
+                passed += 1;
+            } else {
+                println!("test {}:{} {} ... FAILED", file, outcome.span.position, outcome.name);
+                outcome.failures.iter().for_each(|failure| println!("    {}", failure));
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} passed, {} failed", passed, failed);
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+fn write_json(file: &mut File, json: Json) -> Result<(), String> {
+    writeln!(file, "{}", json).map_err(|e| e.to_string())
+}
+
+/// Creates (or truncates) the file at `path` and fills it with `write`, reporting the outcome.
+fn write_file<F>(path: &str, what: &str, write: F) -> bool
+    where F: FnOnce(&mut File) -> Result<(), String>
+{
+    let result = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|mut file| write(&mut file));
+
+    match result {
+        Ok(_) => {
+            println!("Wrote {} to {}", what, path);
+            true
+        }
+        Err(err) => {
+            println!("Output error: {}", err);
+            false
+        }
+    }
 }
 
 fn parse_opts<'a>() -> ArgMatches<'a> {
@@ -73,6 +292,46 @@ fn parse_opts<'a>() -> ArgMatches<'a> {
         .version(crate_version!())
         .author(crate_authors!())
         .about("The Thorium VM")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("fmt")
+                .about("Formats source files in place")
+                .arg(
+                    Arg::with_name("check")
+                        .help("Lists the files that are not formatted instead, exiting with status 1 if any")
+                        .long("check")
+                )
+                .arg(
+                    Arg::with_name("files")
+                        .help("Source files")
+                        .multiple(true)
+                        .required(true)
+                )
+        )
+        .subcommand(
+            SubCommand::with_name("test")
+                .about("Runs the #test blocks of source files in a simulator")
+                .arg(
+                    Arg::with_name("rom")
+                        .help("ROM source file, assembled and loaded in the ROM")
+                        .long("rom")
+                        .multiple(false)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::with_name("step_limit")
+                        .help("Number of instructions a test may execute, unless it sets its own limit")
+                        .long("step-limit")
+                        .multiple(false)
+                        .number_of_values(1)
+                )
+                .arg(
+                    Arg::with_name("files")
+                        .help("Source files")
+                        .multiple(true)
+                        .required(true)
+                )
+        )
         .arg(
             Arg::with_name("input")
                 .help("Input files")
@@ -91,5 +350,75 @@ fn parse_opts<'a>() -> ArgMatches<'a> {
                 .number_of_values(1)
                 .required(true)
         )
+        .arg(
+            Arg::with_name("compile")
+                .help("Writes a relocatable object instead of an image, to be linked with thld")
+                .short("c")
+                .conflicts_with_all(&["layout", "listing", "debug", "symbols", "enums", "c_header", "rust", "emit"])
+        )
+        .arg(
+            Arg::with_name("emit")
+                .help("Writes the tokens, or the nodes with the resolved addresses and the variables, as JSON instead of an image")
+                .long("emit")
+                .multiple(false)
+                .number_of_values(1)
+                .possible_values(&["tokens", "ast"])
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Output format")
+                .long("format")
+                .short("f")
+                .multiple(false)
+                .number_of_values(1)
+                .possible_values(&["bin", "segments", "ihex", "srec", "thm"])
+                .default_value("bin")
+        )
+        .arg(
+            Arg::with_name("layout")
+                .help("Memory layout file placing the sections started by #section")
+                .long("layout")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("listing")
+                .help("Listing output file")
+                .long("listing")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("debug")
+                .help("Writes the debug map next to the output file, with a .dbg extension")
+                .long("debug")
+                .short("g")
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .help("Symbols output file")
+                .long("symbols")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("enums")
+                .help("Writes the members of the #enum directives along with the other variables to the symbols, C header and Rust files")
+                .long("export-enums")
+        )
+        .arg(
+            Arg::with_name("c_header")
+                .help("C header output file, with a #define for each symbol")
+                .long("emit-c-header")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("rust")
+                .help("Rust output file, with a pub const for each symbol")
+                .long("emit-rust")
+                .multiple(false)
+                .number_of_values(1)
+        )
         .get_matches()
 }
\ No newline at end of file
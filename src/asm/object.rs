@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::container;
+use crate::emitter::Emitter;
+use crate::layout::Permissions;
+use crate::parser::{AddressKind, Directive, Instruction, Node};
+
+/// A relocatable object, as written by `tha -c` and linked by `thld`.
+///
+/// Each `#base` starts a section at a fixed origin and each `#section` a named section, placed by
+/// the layout; the content before the first of them has neither and continues the last section
+/// of the previously linked object. Address operands are left as 0 and listed as relocations, to
+/// be resolved once all objects are laid out.
+///
+/// It is written as, all numbers being big-endian:
+///
+/// ```text
+/// magic:       "THOB"
+/// version:     u8 (1)
+/// sections:    u32 count, then for each section:
+///              u8 1 if it has an origin (0 otherwise), u32 origin, u16 length, the UTF-8 name
+///              (empty when unnamed), u8 needed permissions (bits rwx), u32 length, the bytes
+/// symbols:     u32 count, then for each label: u16 length, the UTF-8 name, u32 section, u32 offset,
+///              u8 1 if it is global (0 otherwise)
+/// relocations: u32 count, then for each address operand: u32 section, u32 offset of the word,
+///              u8 kind (0 absolute, 1 segment), u16 length, the UTF-8 label
+/// entry:       u16 length, the UTF-8 label given by `#entry` (empty when there is none)
+/// registers:   u8, the number of general purpose registers used
+/// ```
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct Object {
+    pub sections: Vec<Section>,
+    pub symbols: Vec<Definition>,
+    pub relocations: Vec<Relocation>,
+    pub entry: Option<String>,
+    pub registers: u8,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Section {
+    pub origin: Option<u32>,
+    pub name: Option<String>,
+    pub permissions: Permissions,
+    pub bytes: Vec<u8>,
+}
+
+impl Section {
+    fn new(origin: Option<u32>, name: Option<String>) -> Section {
+        Section { origin, name, permissions: Permissions::default(), bytes: vec![] }
+    }
+}
+
+/// A label defined at `offset` in the section at index `section`. Only global labels are visible
+/// from other objects.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Definition {
+    pub name: String,
+    pub section: u32,
+    pub offset: u32,
+    pub global: bool,
+}
+
+/// A word at `offset` in the section at index `section` to be replaced by the address of `symbol`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Relocation {
+    pub section: u32,
+    pub offset: u32,
+    pub symbol: String,
+    pub kind: AddressKind,
+}
+
+pub const MAGIC: &[u8; 4] = b"THOB";
+pub const VERSION: u8 = 1;
+
+type Result<T> = std::result::Result<T, String>;
+
+impl Object {
+    pub fn new(nodes: &Vec<Node>) -> Result<Object> {
+        let addresses = HashMap::new();
+        let emitter = Emitter::new(nodes, &addresses);
+        let globals: Vec<&String> = nodes.iter()
+            .filter_map(|node| match node {
+                Node::Directive(Directive::Global(label)) => Some(label),
+                _ => None,
+            })
+            .collect();
+        let mut object = Object { registers: container::registers(nodes), ..Object::default() };
+        let mut section = Section::new(None, None);
+
+        for node in nodes {
+            let offset = section.bytes.len() as u32;
+            let index = object.sections.len() as u32;
+            let (label, address) = match node {
+                Node::Directive(Directive::Base(origin)) => {
+                    object.sections.push(section);
+                    section = Section::new(Some(*origin), None);
+                    continue;
+                }
+                Node::Directive(Directive::Section(name)) => {
+                    object.sections.push(section);
+                    section = Section::new(None, Some(name.to_owned()));
+                    continue;
+                }
+                Node::Directive(Directive::Image(label, _))
+                | Node::Directive(Directive::Space(label, _))
+                | Node::Directive(Directive::Word(label, _))
+                | Node::Label(label) => (Some(label), None),
+                Node::Instruction(Instruction::IA(_, address, kind))
+                | Node::Instruction(Instruction::IRA(_, _, address, kind)) => (None, Some((address, kind))),
+                Node::Directive(Directive::Entry(label)) => {
+                    if object.entry.replace(label.to_owned()).is_some() {
+                        return Err("Entry point defined more than once".to_string());
+                    }
+                    continue;
+                }
+                Node::Directive(Directive::Extern(_))
+                | Node::Directive(Directive::Global(_))
+                | Node::Instruction(_) => (None, None),
+            };
+            if let Some(label) = label {
+                if object.symbols.iter().any(|s| s.name == *label) {
+                    return Err(format!("Label {} used more than once", label));
+                }
+                object.symbols.push(Definition {
+                    name: label.to_owned(),
+                    section: index,
+                    offset,
+                    global: globals.contains(&label),
+                });
+            }
+            if let Some((address, kind)) = address {
+                object.relocations.push(Relocation {
+                    section: index,
+                    offset: offset + 4,
+                    symbol: address.to_owned(),
+                    kind: kind.clone(),
+                });
+            }
+            section.permissions = section.permissions.union(Permissions::required(node));
+            emitter.emit_node(node, &mut section.bytes);
+        }
+        object.sections.push(section);
+
+        Ok(object)
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(MAGIC);
+        bytes.push(VERSION);
+        bytes.extend_from_slice(&(self.sections.len() as u32).to_be_bytes());
+        for section in self.sections.iter() {
+            bytes.push(section.origin.is_some() as u8);
+            bytes.extend_from_slice(&section.origin.unwrap_or(0).to_be_bytes());
+            write_string(&mut bytes, section.name.as_deref().unwrap_or(""))?;
+            bytes.push(section.permissions.bits());
+            bytes.extend_from_slice(&(section.bytes.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(&section.bytes);
+        }
+        bytes.extend_from_slice(&(self.symbols.len() as u32).to_be_bytes());
+        for symbol in self.symbols.iter() {
+            write_string(&mut bytes, &symbol.name)?;
+            bytes.extend_from_slice(&symbol.section.to_be_bytes());
+            bytes.extend_from_slice(&symbol.offset.to_be_bytes());
+            bytes.push(symbol.global as u8);
+        }
+        bytes.extend_from_slice(&(self.relocations.len() as u32).to_be_bytes());
+        for relocation in self.relocations.iter() {
+            bytes.extend_from_slice(&relocation.section.to_be_bytes());
+            bytes.extend_from_slice(&relocation.offset.to_be_bytes());
+            bytes.push(match relocation.kind {
+                AddressKind::Absolute => 0,
+                AddressKind::Segment => 1,
+            });
+            write_string(&mut bytes, &relocation.symbol)?;
+        }
+        write_string(&mut bytes, self.entry.as_deref().unwrap_or(""))?;
+        bytes.push(self.registers);
+
+        out.write_all(bytes.as_slice()).map_err(|e| e.to_string())
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<Object> {
+        let mut reader = Reader { bytes };
+        if reader.take(4)? != MAGIC {
+            return Err("Not an object file".to_string());
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(format!("Unsupported object file version {}", version));
+        }
+
+        let mut object = Object::default();
+        for _ in 0..reader.u32()? {
+            let has_origin = reader.u8()? != 0;
+            let origin = reader.u32()?;
+            let name = reader.string()?;
+            let permissions = Permissions::from_bits(reader.u8()?);
+            let length = reader.u32()? as usize;
+            object.sections.push(Section {
+                origin: if has_origin { Some(origin) } else { None },
+                name: if name.is_empty() { None } else { Some(name) },
+                permissions,
+                bytes: reader.take(length)?.to_vec(),
+            });
+        }
+        for _ in 0..reader.u32()? {
+            object.symbols.push(Definition {
+                name: reader.string()?,
+                section: reader.section(&object)?,
+                offset: reader.u32()?,
+                global: reader.u8()? != 0,
+            });
+        }
+        for _ in 0..reader.u32()? {
+            let section = reader.section(&object)?;
+            let offset = reader.u32()?;
+            let kind = match reader.u8()? {
+                0 => AddressKind::Absolute,
+                1 => AddressKind::Segment,
+                kind => return Err(format!("Invalid relocation kind {}", kind)),
+            };
+            if offset as usize + 4 > object.sections[section as usize].bytes.len() {
+                return Err(format!("Relocation at {} is out of section {}", offset, section));
+            }
+            object.relocations.push(Relocation { section, offset, symbol: reader.string()?, kind });
+        }
+        let entry = reader.string()?;
+        object.entry = if entry.is_empty() { None } else { Some(entry) };
+        object.registers = reader.u8()?;
+        if !reader.bytes.is_empty() {
+            return Err("Unexpected data at the end of the object file".to_string());
+        }
+
+        Ok(object)
+    }
+}
+
+pub(crate) fn write_string(bytes: &mut Vec<u8>, string: &str) -> Result<()> {
+    if string.len() > u16::MAX as usize {
+        return Err(format!("Name too long: {}", string));
+    }
+    bytes.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+/// Reads the big-endian numbers and the strings of the object, archive and debug map files.
+pub(crate) struct Reader<'t> {
+    pub bytes: &'t [u8],
+}
+
+impl<'t> Reader<'t> {
+    pub fn take(&mut self, length: usize) -> Result<&'t [u8]> {
+        if self.bytes.len() < length {
+            return Err("Unexpected end of file".to_string());
+        }
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn string(&mut self) -> Result<String> {
+        let length = self.u16()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    /// Reads a section index, making sure it exists in `object`.
+    fn section(&mut self, object: &Object) -> Result<u32> {
+        let section = self.u32()?;
+        if section as usize >= object.sections.len() {
+            return Err(format!("Invalid section {}", section));
+        }
+        Ok(section)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::op::Op;
+
+    use super::*;
+
+    #[test]
+    fn new() {
+        let nodes = vec![
+            Node::Directive(Directive::Global("start".to_string())),
+            Node::Label("start".to_string()),
+            Node::Directive(Directive::Extern("external".to_string())),
+            Node::Instruction(Instruction::IA(Op::JA, "external".to_string(), AddressKind::Absolute)),
+            Node::Directive(Directive::Base(0x2000)),
+            Node::Directive(Directive::Word("data".to_string(), 1)),
+            Node::Instruction(Instruction::IRA(Op::MovRW, "r0".to_string(), "start".to_string(), AddressKind::Segment)),
+            Node::Directive(Directive::Section("bss".to_string())),
+            Node::Directive(Directive::Space("buffer".to_string(), 4)),
+        ];
+
+        let object = Object::new(&nodes);
+
+        assert_eq!(true, object.is_ok(), "Expected Ok(...), got {:?}", object);
+        let object = object.unwrap();
+        assert_eq!(vec![
+            Section {
+                origin: None,
+                name: None,
+                permissions: "x".parse().unwrap(),
+                bytes: vec![Op::JA.bytecode(), 0, 0, 0, 0, 0, 0, 0],
+            },
+            Section {
+                origin: Some(0x2000),
+                name: None,
+                permissions: "rx".parse().unwrap(),
+                bytes: vec![0, 0, 0, 1, Op::MovRW.bytecode(), 0, 0, 0, 0, 0, 0, 0],
+            },
+            Section {
+                origin: None,
+                name: Some("bss".to_string()),
+                permissions: "w".parse().unwrap(),
+                bytes: vec![0, 0, 0, 0],
+            },
+        ], object.sections);
+        assert_eq!(vec![
+            Definition { name: "start".to_string(), section: 0, offset: 0, global: true },
+            Definition { name: "data".to_string(), section: 1, offset: 0, global: false },
+            Definition { name: "buffer".to_string(), section: 2, offset: 0, global: false },
+        ], object.symbols);
+        assert_eq!(vec![
+            Relocation { section: 0, offset: 4, symbol: "external".to_string(), kind: AddressKind::Absolute },
+            Relocation { section: 1, offset: 8, symbol: "start".to_string(), kind: AddressKind::Segment },
+        ], object.relocations);
+    }
+
+    #[test]
+    fn new_duplicate_label() {
+        let nodes = vec![
+            Node::Label("start".to_string()),
+            Node::Label("start".to_string()),
+        ];
+
+        let object = Object::new(&nodes);
+
+        assert_eq!(Err("Label start used more than once".to_string()), object);
+    }
+
+    #[test]
+    fn write_read() {
+        let object = Object {
+            sections: vec![Section {
+                origin: Some(0x1000),
+                name: Some("s".to_string()),
+                permissions: "rx".parse().unwrap(),
+                bytes: vec![1, 2, 3, 4, 0, 0, 0, 0],
+            }],
+            symbols: vec![Definition { name: "a".to_string(), section: 0, offset: 0, global: true }],
+            relocations: vec![Relocation { section: 0, offset: 4, symbol: "b".to_string(), kind: AddressKind::Segment }],
+            entry: Some("a".to_string()),
+            registers: 2,
+        };
+
+        let mut out = vec![];
+        let r = object.write(&mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(vec![
+            b'T', b'H', b'O', b'B', 1,
+            0, 0, 0, 1, 1, 0x00, 0x00, 0x10, 0x00, 0, 1, b's', 5, 0, 0, 0, 8, 1, 2, 3, 4, 0, 0, 0, 0,
+            0, 0, 0, 1, 0, 1, b'a', 0, 0, 0, 0, 0, 0, 0, 0, 1,
+            0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 4, 1, 0, 1, b'b',
+            0, 1, b'a', 2,
+        ], out);
+        assert_eq!(Ok(object), Object::read(&out));
+    }
+
+    #[test]
+    fn read_truncated() {
+        let object = Object::read(b"THOB\x01\x00\x00\x00\x01");
+
+        assert_eq!(Err("Unexpected end of file".to_string()), object);
+    }
+}
@@ -0,0 +1,385 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::container::Container;
+use crate::emitter::Segment;
+
+/// The layout of the file written by `tha`.
+///
+/// * `bin` is the raw content of the only segment; it cannot represent several segments.
+/// * `segments` is, for each segment, its origin (big-endian word), its length in bytes
+///   (big-endian word) and its content.
+/// * `ihex` is Intel HEX, using extended linear address records for the upper 16 bits and a start
+///   linear address record for the entry point.
+/// * `srec` is Motorola S-record, using S3 records (32 bits addresses) and an S7 record holding the
+///   entry point, 0 when there is none.
+/// * `thm` is the [`Container`], which also holds the entry point and the number of registers
+///   used, and is checked by a CRC.
+///
+/// Only `thm` keeps the number of registers; `bin` and `segments` do not keep the entry point.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Bin,
+    Segments,
+    Ihex,
+    Srec,
+    Thm,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bin" => Ok(Format::Bin),
+            "segments" => Ok(Format::Segments),
+            "ihex" => Ok(Format::Ihex),
+            "srec" => Ok(Format::Srec),
+            "thm" => Ok(Format::Thm),
+            _ => Err(format!("Unknown format '{}'", s)),
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+/// Number of data bytes per Intel HEX or S-record record.
+const RECORD_LENGTH: usize = 16;
+
+pub fn write<W: Write>(format: Format, container: &Container, out: &mut W) -> Result<()> {
+    let segments = container.segments.as_slice();
+    let bytes = match format {
+        Format::Bin => match segments {
+            [] => vec![],
+            [segment] => segment.bytes.clone(),
+            _ => return Err(format!("Cannot write {} segments in bin format, use the segments format", segments.len())),
+        },
+        Format::Segments => {
+            let mut bytes = vec![];
+            for segment in segments {
+                bytes.extend_from_slice(&segment.origin.to_be_bytes());
+                bytes.extend_from_slice(&(segment.bytes.len() as u32).to_be_bytes());
+                bytes.extend_from_slice(&segment.bytes);
+            }
+            bytes
+        }
+        Format::Ihex => write_ihex(segments, container.entry).into_bytes(),
+        Format::Srec => write_srec(segments, container.entry).into_bytes(),
+        Format::Thm => return container.write(out),
+    };
+
+    out.write_all(bytes.as_slice()).map_err(|e| e.to_string())
+}
+
+/// Reads back a file written in `format`, with what it keeps of the container. A `bin` file is
+/// loaded at 0 and an `srec` entry point of 0 is read as none.
+pub fn read(format: Format, bytes: &[u8]) -> Result<Container> {
+    let (segments, entry) = match format {
+        Format::Bin => (vec![Segment { origin: 0, bytes: bytes.to_vec() }], None),
+        Format::Segments => (read_segments(bytes)?, None),
+        Format::Ihex => read_ihex(std::str::from_utf8(bytes).map_err(|e| e.to_string())?)?,
+        Format::Srec => read_srec(std::str::from_utf8(bytes).map_err(|e| e.to_string())?)?,
+        Format::Thm => return Container::read(bytes),
+    };
+    Ok(Container { entry, registers: 0, segments })
+}
+
+fn write_ihex(segments: &[Segment], entry: Option<u32>) -> String {
+    fn record(kind: u8, address: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        let checksum = bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)).wrapping_neg();
+        bytes.push(checksum);
+        format!(":{}\n", hex(&bytes))
+    }
+
+    let mut text = String::new();
+    let mut upper: Option<u16> = None;
+    for segment in segments {
+        let mut address = segment.origin;
+        let mut data = segment.bytes.as_slice();
+        while !data.is_empty() {
+            if upper != Some((address >> 16) as u16) {
+                upper = Some((address >> 16) as u16);
+                text.push_str(&record(4, 0, &upper.unwrap().to_be_bytes()));
+            }
+            // a record cannot cross a 64 KiB boundary
+            let length = RECORD_LENGTH
+                .min(data.len())
+                .min(0x10000 - (address & 0xffff) as usize);
+            text.push_str(&record(0, address as u16, &data[..length]));
+            address = address.wrapping_add(length as u32);
+            data = &data[length..];
+        }
+    }
+    if let Some(entry) = entry {
+        text.push_str(&record(5, 0, &entry.to_be_bytes()));
+    }
+    text.push_str(&record(1, 0, &[]));
+    text
+}
+
+fn write_srec(segments: &[Segment], entry: Option<u32>) -> String {
+    fn record(kind: char, address: &[u8], data: &[u8]) -> String {
+        let mut bytes = vec![(address.len() + data.len() + 1) as u8];
+        bytes.extend_from_slice(address);
+        bytes.extend_from_slice(data);
+        let checksum = !bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+        bytes.push(checksum);
+        format!("S{}{}\n", kind, hex(&bytes))
+    }
+
+    let mut text = record('0', &[0, 0], b"tha");
+    for segment in segments {
+        for (i, data) in segment.bytes.chunks(RECORD_LENGTH).enumerate() {
+            let address = segment.origin.wrapping_add((i * RECORD_LENGTH) as u32);
+            text.push_str(&record('3', &address.to_be_bytes(), data));
+        }
+    }
+    text.push_str(&record('7', &entry.unwrap_or(0).to_be_bytes(), &[]));
+    text
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn read_segments(mut bytes: &[u8]) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
+    while !bytes.is_empty() {
+        if bytes.len() < 8 {
+            return Err("Truncated segment header".to_string());
+        }
+        let origin = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let length = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) as usize;
+        if bytes.len() < 8 + length {
+            return Err(format!("Truncated segment at 0x{:08x}", origin));
+        }
+        segments.push(Segment { origin, bytes: bytes[8..8 + length].to_vec() });
+        bytes = &bytes[8 + length..];
+    }
+    Ok(segments)
+}
+
+/// Appends `data` to the last segment if it directly follows it, or starts a new segment.
+fn append(segments: &mut Vec<Segment>, address: u32, data: &[u8]) {
+    match segments.last_mut() {
+        Some(segment) if segment.origin.wrapping_add(segment.bytes.len() as u32) == address => {
+            segment.bytes.extend_from_slice(data)
+        }
+        _ => segments.push(Segment { origin: address, bytes: data.to_vec() }),
+    }
+}
+
+/// Decodes the hexadecimal digits of a record, after its start code.
+fn record_bytes(line: &str, number: usize) -> Result<Vec<u8>> {
+    if !line.len().is_multiple_of(2) || !line.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("Invalid record at line {}", number));
+    }
+    Ok((0..line.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+        .collect())
+}
+
+fn read_ihex(text: &str) -> Result<(Vec<Segment>, Option<u32>)> {
+    let mut segments = vec![];
+    let mut base = 0u32;
+    let mut entry = None;
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let number = i + 1;
+        let line = line.trim();
+        if !line.starts_with(':') {
+            return Err(format!("Expected ':' at line {}", number));
+        }
+        let bytes = record_bytes(&line[1..], number)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(format!("Invalid record length at line {}", number));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0 {
+            return Err(format!("Invalid checksum at line {}", number));
+        }
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            0 => append(&mut segments, base.wrapping_add(address), data),
+            1 => return Ok((segments, entry)),
+            2 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 4,
+            4 if data.len() == 2 => base = (u16::from_be_bytes([data[0], data[1]]) as u32) << 16,
+            5 if data.len() == 4 => entry = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]])),
+            3 => (),
+            kind => return Err(format!("Unsupported record type {:02X} at line {}", kind, number)),
+        }
+    }
+    Err("Missing end of file record".to_string())
+}
+
+fn read_srec(text: &str) -> Result<(Vec<Segment>, Option<u32>)> {
+    let mut segments = vec![];
+    for (i, line) in text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let number = i + 1;
+        let line = line.trim();
+        if !line.is_ascii() {
+            return Err(format!("Invalid S-record line {}", number));
+        }
+        if line.len() < 2 || !line.starts_with('S') {
+            return Err(format!("Expected 'S' at line {}", number));
+        }
+        let bytes = record_bytes(&line[2..], number)?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(format!("Invalid record length at line {}", number));
+        }
+        if bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) != 0xff {
+            return Err(format!("Invalid checksum at line {}", number));
+        }
+        let (address_length, end) = match &line[1..2] {
+            "1" => (2, false),
+            "2" => (3, false),
+            "3" => (4, false),
+            "9" => (2, true),
+            "8" => (3, true),
+            "7" => (4, true),
+            "0" | "5" | "6" => continue,
+            kind => return Err(format!("Unsupported record type S{} at line {}", kind, number)),
+        };
+        if bytes.len() < address_length + 2 {
+            return Err(format!("Invalid record length at line {}", number));
+        }
+        let address = bytes[1..=address_length].iter().fold(0u32, |a, b| a << 8 | *b as u32);
+        if end {
+            return Ok((segments, if address == 0 { None } else { Some(address) }));
+        }
+        append(&mut segments, address, &bytes[address_length + 1..bytes.len() - 1]);
+    }
+    Err("Missing termination record".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container(segments: Vec<Segment>, entry: Option<u32>) -> Container {
+        Container { entry, registers: 0, segments }
+    }
+
+    #[test]
+    fn write_bin() {
+        let segments = vec![Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] }];
+        let mut out = vec![];
+
+        let r = write(Format::Bin, &container(segments, None), &mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(vec![1, 2, 3, 4], out);
+    }
+
+    #[test]
+    fn write_bin_several_segments() {
+        let segments = vec![
+            Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] },
+            Segment { origin: 0x2000, bytes: vec![5, 6, 7, 8] },
+        ];
+        let mut out = vec![];
+
+        let r = write(Format::Bin, &container(segments, None), &mut out);
+
+        assert_eq!(Err("Cannot write 2 segments in bin format, use the segments format".to_string()), r);
+    }
+
+    #[test]
+    fn write_segments() {
+        let segments = vec![
+            Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] },
+            Segment { origin: 0x2000, bytes: vec![5, 6, 7, 8] },
+        ];
+        let mut out = vec![];
+
+        let r = write(Format::Segments, &container(segments.clone(), None), &mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(vec![
+            0x00, 0x00, 0x10, 0x00, 0, 0, 0, 4, 1, 2, 3, 4,
+            0x00, 0x00, 0x20, 0x00, 0, 0, 0, 4, 5, 6, 7, 8,
+        ], out);
+        assert_eq!(Ok(container(segments, None)), read(Format::Segments, &out));
+    }
+
+    #[test]
+    fn write_ihex() {
+        let segments = vec![
+            Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] },
+            Segment { origin: 0xfe00fffe, bytes: vec![5, 6, 7, 8] },
+        ];
+        let mut out = vec![];
+
+        let r = write(Format::Ihex, &container(segments.clone(), Some(0x1000)), &mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(":020000040000FA\n\
+                    :0410000001020304E2\n\
+                    :02000004FE00FC\n\
+                    :02FFFE000506F6\n\
+                    :02000004FE01FB\n\
+                    :020000000708EF\n\
+                    :0400000500001000E7\n\
+                    :00000001FF\n", String::from_utf8(out.clone()).unwrap());
+        assert_eq!(Ok(container(segments, Some(0x1000))), read(Format::Ihex, &out));
+    }
+
+    #[test]
+    fn write_srec() {
+        let segments = vec![
+            Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] },
+            Segment { origin: 0xfe000000, bytes: (0..20).collect() },
+        ];
+        let mut out = vec![];
+
+        let r = write(Format::Srec, &container(segments.clone(), Some(0xfe000000)), &mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!("S0060000746861BC\n\
+                    S3090000100001020304DC\n\
+                    S315FE000000000102030405060708090A0B0C0D0E0F74\n\
+                    S309FE00001010111213A2\n\
+                    S705FE000000FC\n", String::from_utf8(out.clone()).unwrap());
+        assert_eq!(Ok(container(segments, Some(0xfe000000))), read(Format::Srec, &out));
+    }
+
+    #[test]
+    fn read_ihex_invalid_checksum() {
+        let r = read(Format::Ihex, b":0410000001020304E3\n:00000001FF\n");
+
+        assert_eq!(Err("Invalid checksum at line 1".to_string()), r);
+    }
+
+    #[test]
+    fn read_srec_16_bits_addresses() {
+        let r = read(Format::Srec, b"S107100001020304DE\nS9030000FC\n");
+
+        assert_eq!(Ok(container(vec![Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] }], None)), r);
+    }
+
+    #[test]
+    fn read_srec_non_ascii() {
+        let r = read(Format::Srec, "S\u{e9}\n".as_bytes());
+
+        assert_eq!(Err("Invalid S-record line 1".to_string()), r);
+    }
+
+    #[test]
+    fn write_thm() {
+        let container = Container {
+            entry: Some(0x1000),
+            registers: 8,
+            segments: vec![Segment { origin: 0x1000, bytes: vec![1, 2, 3, 4] }],
+        };
+        let mut out = vec![];
+
+        let r = write(Format::Thm, &container, &mut out);
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        assert_eq!(Ok(container), read(Format::Thm, &out));
+    }
+}
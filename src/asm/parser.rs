@@ -1,7 +1,10 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Add;
+use std::path::Path;
 use peek_nth::{IteratorExt, PeekableNth};
 
+use crate::cst::Cst;
+use crate::image::Image;
 use crate::op::Op;
 use crate::lexer::{AddressKind as LexerAddressKind, Lexer, Position, Token};
 use crate::parser::AddressKind::{Absolute, Segment};
@@ -19,6 +22,34 @@ pub enum Node {
     Label(String),
 }
 
+/// Where a node comes from: the file (if any) and the position of its first token.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Span {
+    pub file: Option<String>,
+    pub position: Position,
+}
+
+/// An error found in the nodes: the index of the node it is reported at, the label or register it
+/// is about, and the message.
+#[derive(Debug, PartialEq, Clone)]
+pub struct NodeError {
+    pub index: usize,
+    pub name: String,
+    pub message: String,
+}
+
+/// The spans of the parsed nodes, at the same index as the nodes, and of the variables definitions.
+#[derive(Debug, PartialEq, Default)]
+pub struct Spans {
+    pub nodes: Vec<Span>,
+    /// The index of the source each node was parsed from, as given by `Parser::with_source`, at
+    /// the same index as the nodes.
+    pub sources: Vec<usize>,
+    pub variables: HashMap<String, Span>,
+    /// The variables defined as the members of an `#enum`.
+    pub enums: HashSet<String>,
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Label {
     name: String,
@@ -27,9 +58,57 @@ pub struct Label {
 #[derive(Debug, PartialEq)]
 pub enum Directive {
     Base(u32),
+    Entry(String),
+    Extern(String),
+    Global(String),
+    Image(String, Vec<u32>),
+    Section(String),
+    Space(String, u32),
     Word(String, i32),
 }
 
+/// A `#test` block: the label to start at, the registers to set beforehand and what to expect of
+/// the registers and the memory once halted.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Test {
+    pub name: String,
+    pub span: Span,
+    pub entry: Option<String>,
+    pub registers: Vec<(String, u32)>,
+    pub expectations: Vec<Expectation>,
+    pub step_limit: Option<u32>,
+}
+
+/// What a `#test` block expects `location` to hold.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expectation {
+    pub location: Location,
+    pub value: u32,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Location {
+    Register(String),
+    /// The word at an address.
+    Word(u32),
+    /// The word at a label.
+    Label(String),
+}
+
+impl Node {
+    /// The number of bytes the node emits.
+    pub fn size(&self) -> u32 {
+        match self {
+            Node::Directive(Directive::Image(_, pixels)) => 4 * pixels.len() as u32,
+            Node::Directive(Directive::Space(_, size)) => *size,
+            Node::Directive(Directive::Word(_, _)) => 4,
+            Node::Directive(_) => 0,
+            Node::Instruction(i) => i.op().length() as u32,
+            Node::Label(_) => 0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub enum AddressKind {
     Absolute,
@@ -68,22 +147,73 @@ impl Instruction {
 }
 
 pub struct Parser<'t> {
-    lexer: PeekableNth<&'t mut Lexer>,
+    lexer: PeekableNth<Box<dyn Iterator<Item = Result<Token>> + 't>>,
     symbols: &'t mut HashMap<String, Token>,
     nodes: &'t mut Vec<Node>,
+    spans: Option<&'t mut Spans>,
+    tests: Option<&'t mut Vec<Test>>,
+    file: Option<String>,
+    source: usize,
+    position: Option<Position>,
 }
 
 type Result<T> = std::result::Result<T, String>;
 
 impl<'t> Parser<'t> {
     pub fn from_lexer(lexer: &'t mut Lexer, nodes: &'t mut Vec<Node>, symbols: &'t mut HashMap<String, Token>) -> Self {
+        let file = lexer.file().map(|f| f.to_string());
+        Parser {
+            lexer: (Box::new(lexer) as Box<dyn Iterator<Item = Result<Token>>>).peekable_nth(),
+            symbols,
+            nodes,
+            spans: None,
+            tests: None,
+            file,
+            source: 0,
+            position: None,
+        }
+    }
+
+    /// Parses the tokens of `cst`, leaving out its trivia.
+    pub fn from_cst(cst: &'t Cst, nodes: &'t mut Vec<Node>, symbols: &'t mut HashMap<String, Token>) -> Self {
+        let tokens = cst.tokens().map(|t| Ok(t.token.clone()));
         Parser {
-            lexer: lexer.peekable_nth(),
+            lexer: (Box::new(tokens) as Box<dyn Iterator<Item = Result<Token>>>).peekable_nth(),
             symbols,
             nodes,
+            spans: None,
+            tests: None,
+            file: cst.file.clone(),
+            source: 0,
+            position: None,
         }
     }
 
+    /// Records the span of each parsed node and variable in `spans`.
+    pub fn with_spans(mut self, spans: &'t mut Spans) -> Self {
+        self.spans = Some(spans);
+        self
+    }
+
+    /// Records the nodes as parsed from the source at `index` among the sources recorded in the
+    /// same spans, 0 by default.
+    pub fn with_source(mut self, index: usize) -> Self {
+        self.source = index;
+        self
+    }
+
+    /// Records the `#test` blocks in `tests`; they are checked and left out otherwise.
+    pub fn with_tests(mut self, tests: &'t mut Vec<Test>) -> Self {
+        self.tests = Some(tests);
+        self
+    }
+
+    /// The position of the statement being parsed, or of the member of the `#struct`, `#enum` or
+    /// `#test` being parsed, the one an error stops the parsing at.
+    pub fn position(&self) -> Option<&Position> {
+        self.position.as_ref()
+    }
+
     pub fn parse(&mut self) -> Result<()> {
         loop {
             match self.next() {
@@ -91,6 +221,10 @@ impl<'t> Parser<'t> {
                 Some(Err(err)) => return Err(err),
                 Some(Ok(n)) => {
                     self.nodes.push(n);
+                    if let (Some(spans), Some(position)) = (self.spans.as_mut(), self.position.take()) {
+                        spans.nodes.push(Span { file: self.file.clone(), position });
+                        spans.sources.push(self.source);
+                    }
                 }
             }
         }
@@ -99,19 +233,280 @@ impl<'t> Parser<'t> {
     fn parse_variable(&mut self, name: String, position: &Position) -> Result<()> {
         match self.lexer.next() {
             Some(Ok(Token::Equal(_))) => {}
-            _ => return Err(format!("Expected '=' at {}", position).into()),
+            _ => return Err("Expected '='".into()),
         }
 
         let token = self.lexer.next();
         match token {
-            Some(Ok(Token::Integer(_, _))) => self.symbols.insert(name, token.unwrap().unwrap()),
-            // Some(Ok(Token::Address(_, _, _))) => self.symbols.insert(name, token.unwrap().unwrap()),
-            _ => return Err(format!("Expected <integer> or <addr> at {}", position).into()),
+            Some(Ok(Token::Integer(_, _))) => self.define(name, token.unwrap().unwrap(), position),
+            // Some(Ok(Token::Address(_, _, _))) => self.define(name, token.unwrap().unwrap(), position),
+            _ => return Err("Expected <integer> or <addr>".into()),
+        };
+
+        Ok(())
+    }
+
+    /// Adds the variable `name` to the symbols, recording that it is defined at `position`.
+    fn define(&mut self, name: String, token: Token, position: &Position) {
+        if let Some(spans) = self.spans.as_mut() {
+            spans.variables.insert(name.clone(), Span { file: self.file.clone(), position: position.clone() });
+        }
+        self.symbols.insert(name, token);
+    }
+
+    /// Reads the ',' or <eol> ending the member of a `#struct` or an `#enum`, leaving a '}' to be
+    /// read.
+    fn read_member_end(&mut self) -> Result<()> {
+        match self.peek(0) {
+            Some(Token::Comma(_)) | Some(Token::Eol(_)) => {
+                self.read_next();
+                Ok(())
+            }
+            Some(Token::RBrace(_)) => Ok(()),
+            Some(token) => {
+                self.position = Some(token.position().clone());
+                Err("Expected ',' or '}'".into())
+            }
+            None => Err("Expected ',' or '}'".into()),
+        }
+    }
+
+    /// parses `<identifier> '{' ( <identifier> ':' <type> ( ',' | <eol> ) )* '}' <eol>` and defines
+    /// the `$<name>.<field>` offsets and the `$<name>.size` variables. Word and struct fields are
+    /// aligned on words and the size is rounded up to a whole number of words when the struct
+    /// contains any of them.
+    fn parse_struct(&mut self, position: &Position) -> Result<()> {
+        let name = match self.read_next() {
+            Some(Token::Identifier(_, name)) => name,
+            _ => return Err("Expected <identifier> for directive '#struct'".into()),
         };
+        let size_variable = format!("${}.size", name);
+        if self.symbols.contains_key(&size_variable) {
+            return Err(format!("Struct {} defined more than once", name).into());
+        }
+        match self.read_next() {
+            Some(Token::LBrace(_)) => (),
+            _ => return Err("Expected '{'".into()),
+        }
+
+        let mut fields: Vec<(String, Token)> = vec![];
+        let mut offset = 0u32;
+        let mut alignment = 1u32;
+        loop {
+            let (field_position, field) = match self.read_next() {
+                Some(Token::Eol(_)) | Some(Token::Comma(_)) => continue,
+                Some(Token::RBrace(_)) => break,
+                Some(Token::Identifier(p, field)) => (p, field),
+                Some(token) => {
+                    self.position = Some(token.position().clone());
+                    return Err("Expected <identifier> or '}'".into());
+                }
+                None => return Err("Expected <identifier> or '}'".into()),
+            };
+            self.position = Some(field_position.clone());
+            match self.read_next() {
+                Some(Token::Colon(_)) => (),
+                _ => return Err("Expected ':'".into()),
+            }
+            let (field_size, field_alignment) = match self.read_next() {
+                Some(Token::Identifier(_, t)) if t == "byte" => (1, 1),
+                Some(Token::Identifier(_, t)) if t == "word" => (4, 4),
+                Some(Token::Identifier(_, t)) => match self.symbols.get(&format!("${}.size", t)) {
+                    Some(Token::Integer(_, size)) => (*size, 4),
+                    _ => return Err(format!("Unknown type '{}'", t).into()),
+                },
+                _ => return Err("Expected <type>".into()),
+            };
+
+            let variable = format!("${}.{}", name, field);
+            if field == "size" {
+                return Err("Field name size is reserved".into());
+            }
+            if fields.iter().any(|(v, _)| *v == variable) {
+                return Err(format!("Field {} defined more than once", field).into());
+            }
+            self.read_member_end()?;
+            offset = Self::align(offset, field_alignment);
+            fields.push((variable, Token::Integer(field_position, offset)));
+            offset += field_size;
+            alignment = alignment.max(field_alignment);
+        }
+        if !self.read_eol() {
+            return Err("Expected <eol>".into());
+        }
+
+        for (variable, token) in fields {
+            let field_position = token.position().clone();
+            self.define(variable, token, &field_position);
+        }
+        self.define(size_variable, Token::Integer(position.clone(), Self::align(offset, alignment)), position);
+        Ok(())
+    }
+
+    /// parses `<identifier> '{' ( <identifier> ( '=' ( <w> | <var> ) )? ( ',' | <eol> ) )* '}' <eol>`
+    /// and defines the `$<name>.<member>` variables. Members without a value get the value of the
+    /// previous member plus one, the first one defaulting to 0.
+    fn parse_enum(&mut self) -> Result<()> {
+        let name = match self.read_next() {
+            Some(Token::Identifier(_, name)) => name,
+            _ => return Err("Expected <identifier> for directive '#enum'".into()),
+        };
+        let prefix = format!("${}.", name);
+        if self.symbols.keys().any(|variable| variable.starts_with(&prefix)) {
+            return Err(format!("Enum {} defined more than once", name).into());
+        }
+        match self.read_next() {
+            Some(Token::LBrace(_)) => (),
+            _ => return Err("Expected '{'".into()),
+        }
+
+        let mut members: Vec<(String, Token)> = vec![];
+        let mut value = 0u32;
+        loop {
+            let (member_position, member) = match self.read_next() {
+                Some(Token::Eol(_)) | Some(Token::Comma(_)) => continue,
+                Some(Token::RBrace(_)) => break,
+                Some(Token::Identifier(p, member)) => (p, member),
+                Some(token) => {
+                    self.position = Some(token.position().clone());
+                    return Err("Expected <identifier> or '}'".into());
+                }
+                None => return Err("Expected <identifier> or '}'".into()),
+            };
+            self.position = Some(member_position.clone());
+            if let Some(Token::Equal(_)) = self.peek(0) {
+                self.read_next();
+                value = match self.read_next() {
+                    Some(Token::Integer(_, w)) => w,
+                    Some(Token::Variable(_, variable)) => match self.symbols.get(&variable) {
+                        Some(Token::Integer(_, w)) => *w,
+                        _ => return Err(format!("Unknown variable '{}'", variable).into()),
+                    },
+                    _ => return Err("Expected <w> or <variable>".into()),
+                };
+            }
+
+            let variable = format!("${}.{}", name, member);
+            if members.iter().any(|(v, _)| *v == variable) || self.symbols.contains_key(&variable) {
+                return Err(format!("Variable {} defined more than once", variable).into());
+            }
+            self.read_member_end()?;
+            members.push((variable, Token::Integer(member_position, value)));
+            value = value.wrapping_add(1);
+        }
+        if !self.read_eol() {
+            return Err("Expected <eol>".into());
+        }
 
+        for (variable, token) in members {
+            let member_position = token.position().clone();
+            if let Some(spans) = self.spans.as_mut() {
+                spans.enums.insert(variable.clone());
+            }
+            self.define(variable, token, &member_position);
+        }
         Ok(())
     }
 
+    /// parses `<identifier> '{' ( <statement> ( ',' | <eol> ) )* '}' <eol>`, the statements being
+    /// `entry <identifier>`, `set <r> '=' <value>`, `expect <r> '=' <value>`,
+    /// `expect '[' ( <w> | <variable> | <&-addr> ) ']' '=' <value>` and `limit <w>`, where
+    /// `<value>` is `<w>` or `<variable>`.
+    fn parse_test(&mut self, position: &Position) -> Result<Test> {
+        let name = match self.read_next() {
+            Some(Token::Identifier(_, name)) => name,
+            _ => return Err("Expected <identifier> for directive '#test'".into()),
+        };
+        match self.read_next() {
+            Some(Token::LBrace(_)) => (),
+            _ => return Err("Expected '{'".into()),
+        }
+
+        let mut test = Test {
+            name,
+            span: Span { file: self.file.clone(), position: position.clone() },
+            entry: None,
+            registers: vec![],
+            expectations: vec![],
+            step_limit: None,
+        };
+        loop {
+            let (statement_position, statement) = match self.read_next() {
+                Some(Token::Eol(_)) | Some(Token::Comma(_)) => continue,
+                Some(Token::RBrace(_)) => break,
+                Some(Token::Identifier(p, statement)) => (p, statement),
+                _ => return Err("Expected <identifier> or '}'".into()),
+            };
+            self.position = Some(statement_position);
+            match statement.as_str() {
+                "entry" => match self.read_next() {
+                    Some(Token::Identifier(_, label)) => test.entry = Some(label),
+                    _ => return Err("Expected <identifier>".into()),
+                },
+                "set" => {
+                    let register = match self.read_next() {
+                        Some(Token::Identifier(_, register)) => register,
+                        _ => return Err("Expected <r>".into()),
+                    };
+                    let value = self.test_value()?;
+                    test.registers.push((register, value));
+                }
+                "expect" => {
+                    let location = match self.read_next() {
+                        Some(Token::Identifier(_, register)) => Location::Register(register),
+                        Some(Token::LBracket(_)) => {
+                            let location = match self.read_next() {
+                                Some(Token::Integer(_, address)) => Location::Word(address),
+                                Some(Token::Variable(_, variable)) => match self.symbols.get(&variable) {
+                                    Some(Token::Integer(_, address)) => Location::Word(*address),
+                                    _ => return Err(format!("Unknown variable '{}'", variable).into()),
+                                },
+                                Some(Token::Address(_, label, LexerAddressKind::Absolute)) => Location::Label(label),
+                                _ => return Err("Expected <w>, <variable> or <&-addr>".into()),
+                            };
+                            match self.read_next() {
+                                Some(Token::RBracket(_)) => location,
+                                _ => return Err("Expected ']'".into()),
+                            }
+                        }
+                        _ => return Err("Expected <r> or '['".into()),
+                    };
+                    let value = self.test_value()?;
+                    test.expectations.push(Expectation { location, value });
+                }
+                "limit" => match self.read_next() {
+                    Some(Token::Integer(_, limit)) => test.step_limit = Some(limit),
+                    _ => return Err("Expected <w>".into()),
+                },
+                _ => return Err(format!("Unknown test statement '{}'", statement).into()),
+            }
+        }
+        if !self.read_eol() {
+            return Err("Expected <eol>".into());
+        }
+        Ok(test)
+    }
+
+    /// parses `'=' ( <w> | <variable> )` in a `#test` block.
+    fn test_value(&mut self) -> Result<u32> {
+        match self.read_next() {
+            Some(Token::Equal(_)) => (),
+            _ => return Err("Expected '='".into()),
+        }
+        match self.read_next() {
+            Some(Token::Integer(_, w)) => Ok(w),
+            Some(Token::Variable(_, variable)) => match self.symbols.get(&variable) {
+                Some(Token::Integer(_, w)) => Ok(*w),
+                _ => Err(format!("Unknown variable '{}'", variable).into()),
+            },
+            _ => Err("Expected <w> or <variable>".into()),
+        }
+    }
+
+    fn align(value: u32, alignment: u32) -> u32 {
+        value.div_ceil(alignment) * alignment
+    }
+
     fn parse_directive(&mut self, name: String, position: &Position) -> Result<Directive> {
         match name.to_lowercase().as_str() {
             "base" => {
@@ -124,176 +519,259 @@ impl<'t> Parser<'t> {
                         },
                         _ => None,
                     })
-                    .unwrap_or(Err(format!("Expected <w> or <variable> for directive '#{}' at {}", name, position).into()));
+                    .unwrap_or(Err(format!("Expected <w> or <variable> for directive '#{}'", name).into()));
                 if self.read_eol() {
                     return directive;
                 }
-                Err(format!("Expected <eol> at {}", position).into())
+                Err("Expected <eol>".into())
+            }
+            "image" => {
+                let path = match self.read_next() {
+                    Some(Token::String(_, str)) => str,
+                    _ => return Err(format!("Expected <string> for directive '#{}'", name).into()),
+                };
+                if !self.read_eol() {
+                    return Err("Expected <eol>".into());
+                }
+
+                let label = match Path::new(&path).file_stem().and_then(|s| s.to_str()) {
+                    Some(stem) if Self::is_identifier(stem) => stem.to_string(),
+                    _ => return Err(format!("Cannot derive a label from '{}'", path).into()),
+                };
+                let path = match self.file.as_ref().and_then(|f| Path::new(f).parent()) {
+                    Some(dir) => dir.join(&path).to_string_lossy().to_string(),
+                    None => path,
+                };
+                let image = Image::from_file(&path)?;
+
+                for (variable, value) in [(format!("${}_width", label), image.width), (format!("${}_height", label), image.height)] {
+                    if self.symbols.contains_key(&variable) {
+                        return Err(format!("Variable {} defined more than once", variable).into());
+                    }
+                    self.define(variable, Token::Integer(position.clone(), value), position);
+                }
+                Ok(Directive::Image(label, image.pixels))
+            }
+            "entry" | "extern" | "global" => {
+                let identifier = match self.read_next() {
+                    Some(Token::Identifier(_, str)) => str,
+                    _ => return Err(format!("Expected <identifier> for directive '#{}'", name).into()),
+                };
+
+                if !self.read_eol() {
+                    return Err("Expected <eol>".into());
+                }
+                match name.to_lowercase().as_str() {
+                    "entry" => Ok(Directive::Entry(identifier)),
+                    "extern" => Ok(Directive::Extern(identifier)),
+                    _ => Ok(Directive::Global(identifier)),
+                }
+            }
+            "section" => {
+                let identifier = match self.read_next() {
+                    Some(Token::Identifier(_, str)) => str,
+                    _ => return Err(format!("Expected <identifier> for directive '#{}'", name).into()),
+                };
+
+                if self.read_eol() {
+                    return Ok(Directive::Section(identifier));
+                }
+
+                Err("Expected <eol>".into())
+            }
+            "space" => {
+                let identifier = match self.read_next() {
+                    Some(Token::Identifier(_, str)) => str,
+                    _ => return Err(format!("Expected <identifier> for directive '#{}'", name).into()),
+                };
+                let size = match self.read_next() {
+                    Some(Token::Integer(_, w)) => w,
+                    Some(Token::Variable(_, variable)) => match self.symbols.get(&variable) {
+                        Some(Token::Integer(_, w)) => *w,
+                        _ => return Err(format!("Unknown variable '{}'", variable).into()),
+                    },
+                    _ => return Err(format!("Expected <w> or <variable> for directive '#{}'", name).into()),
+                };
+
+                if self.read_eol() {
+                    return Ok(Directive::Space(identifier, Self::align(size, 4)));
+                }
+
+                Err("Expected <eol>".into())
             }
             "word" => {
                 let identifier = match self.read_next() {
                     Some(Token::Identifier(_, str)) => str,
-                    _ => return Err(format!("Expected <string> for directive '#{}' at {}", name, position).into()),
+                    _ => return Err(format!("Expected <identifier> for directive '#{}'", name).into()),
                 };
                 let value = match self.read_next() {
                     Some(Token::Integer(_, val)) => val,
-                    _ => return Err(format!("Expected <value> for directive '#{}' at {}", name, position).into()),
+                    _ => return Err(format!("Expected <value> for directive '#{}'", name).into()),
                 };
 
                 if self.read_eol() {
                     return Ok(Directive::Word(identifier, value as i32));
                 }
 
-                Err(format!("Expected <eol> at {}", position).into())
+                Err("Expected <eol>".into())
             }
-            _ => Err(format!("Unknown directive '#{}' at {}", name, position).into()),
+            _ => Err(format!("Unknown directive '#{}'", name).into()),
+        }
+    }
+
+    /// Checks that `name` is in the form `[a-z][A-Za-z0-9_]*`, i.e. is usable as a label.
+    fn is_identifier(name: &str) -> bool {
+        let mut chars = name.chars();
+        match chars.next() {
+            Some(c) if c.is_ascii_lowercase() => chars.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+            _ => false,
         }
     }
 
-    fn parse_instruction(&mut self, op: &str, position: &Position) -> Result<Instruction> {
+    fn parse_instruction(&mut self, op: &str) -> Result<Instruction> {
         return match op {
-            "ADD" => self.parse_op(position, &[
-                (Op::AddRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::AddRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "ADD" => self.parse_op(&[
+                (Op::AddRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::AddRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "AND" => self.parse_op(position, &[
-                (Op::AndRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::AndRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "AND" => self.parse_op(&[
+                (Op::AndRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::AndRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "CALL" => self.parse_op(position, &[
-                (Op::CallS, Self::op_as as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::CallA, Self::op_aa as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::CallR, Self::op_r as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "CALL" => self.parse_op(&[
+                (Op::CallS, Self::op_as as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::CallA, Self::op_aa as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::CallR, Self::op_r as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "CMP" => self.parse_op(position, &[
-                (Op::CmpRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::CmpRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "CMP" => self.parse_op(&[
+                (Op::CmpRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::CmpRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "DEC" => self.parse_op(position, &[
-                (Op::DecR, Self::op_r as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "DEC" => self.parse_op(&[
+                (Op::DecR, Self::op_r as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "HALT" => self.parse_op(position, &[
-                (Op::Halt, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "HALT" => self.parse_op(&[
+                (Op::Halt, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "INC" => self.parse_op(position, &[
-                (Op::IncR, Self::op_r as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "INC" => self.parse_op(&[
+                (Op::IncR, Self::op_r as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "IND" => self.parse_op(position, &[
-                (Op::Ind, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "IND" => self.parse_op(&[
+                (Op::Ind, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "INE" => self.parse_op(position, &[
-                (Op::Ine, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "INE" => self.parse_op(&[
+                (Op::Ine, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "INT" => self.parse_op(position, &[
-                (Op::IntB, Self::op_b as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "INT" => self.parse_op(&[
+                (Op::IntB, Self::op_b as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "J" => self.parse_op(position, &[
-                (Op::JA, Self::op_aa as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::JS, Self::op_as as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "J" => self.parse_op(&[
+                (Op::JA, Self::op_aa as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::JS, Self::op_as as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "JEQ" => self.parse_op(position, &[
-                (Op::JeqA, Self::op_aa as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::JeqS, Self::op_as as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "JEQ" => self.parse_op(&[
+                (Op::JeqA, Self::op_aa as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::JeqS, Self::op_as as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "JNE" => self.parse_op(position, &[
-                (Op::JneA, Self::op_aa as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::JneS, Self::op_as as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "JNE" => self.parse_op(&[
+                (Op::JneA, Self::op_aa as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::JneS, Self::op_as as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "LOAD" => self.parse_op(position, &[
-                (Op::LoadRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::LoadRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::LoadRW, Self::op_raa as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::LoadRRW, Self::op_ro as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "LOAD" => self.parse_op(&[
+                (Op::LoadRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::LoadRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::LoadRW, Self::op_raa as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::LoadRRW, Self::op_ro as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "MI" => self.parse_op(position, &[
-                (Op::MiB, Self::op_b as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "MI" => self.parse_op(&[
+                (Op::MiB, Self::op_b as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "MOV" => self.parse_op(position, &[
-                (Op::MovRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::MovRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::MovRW, Self::op_raa as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::MovRW, Self::op_ras as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "MOV" => self.parse_op(&[
+                (Op::MovRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::MovRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::MovRW, Self::op_raa as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::MovRW, Self::op_ras as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "MUL" => self.parse_op(position, &[
-                (Op::MulRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::MulRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "MUL" => self.parse_op(&[
+                (Op::MulRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::MulRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "NOP" => self.parse_op(position, &[
-                (Op::Nop, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "NOP" => self.parse_op(&[
+                (Op::Nop, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "OR" => self.parse_op(position, &[
-                (Op::OrRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::OrRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "OR" => self.parse_op(&[
+                (Op::OrRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::OrRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "PANIC" => self.parse_op(position, &[
-                (Op::Panic, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "PANIC" => self.parse_op(&[
+                (Op::Panic, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "POP" => self.parse_op(position, &[
-                (Op::PopR, Self::op_r as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::PopRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::PopRRR, Self::op_rrr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "POP" => self.parse_op(&[
+                (Op::PopR, Self::op_r as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::PopRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::PopRRR, Self::op_rrr as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "POPA" => self.parse_op(position, &[
-                (Op::Popa, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "POPA" => self.parse_op(&[
+                (Op::Popa, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "PUSH" => self.parse_op(position, &[
-                (Op::PushR, Self::op_r as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::PushRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::PushRRR, Self::op_rrr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::PushW, Self::op_w as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "PUSH" => self.parse_op(&[
+                (Op::PushR, Self::op_r as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::PushRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::PushRRR, Self::op_rrr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::PushW, Self::op_w as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "PUSHA" => self.parse_op(position, &[
-                (Op::Pusha, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "PUSHA" => self.parse_op(&[
+                (Op::Pusha, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "RET" => self.parse_op(position, &[
-                (Op::Ret, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "RET" => self.parse_op(&[
+                (Op::Ret, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "STOR" => self.parse_op(position, &[
-                (Op::StorRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::StorRW, Self::op_wr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::StorRW, Self::op_aar as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "STOR" => self.parse_op(&[
+                (Op::StorRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::StorRW, Self::op_wr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::StorRW, Self::op_aar as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "SUB" => self.parse_op(position, &[
-                (Op::SubRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::SubRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "SUB" => self.parse_op(&[
+                (Op::SubRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::SubRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "UMI" => self.parse_op(position, &[
-                (Op::UmiB, Self::op_b as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "UMI" => self.parse_op(&[
+                (Op::UmiB, Self::op_b as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "IRET" => self.parse_op(position, &[
-                (Op::Iret, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "IRET" => self.parse_op(&[
+                (Op::Iret, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "WFI" => self.parse_op(position, &[
-                (Op::Wfi, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "WFI" => self.parse_op(&[
+                (Op::Wfi, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "XBM" => self.parse_op(position, &[
-                (Op::Xbm, Self::op_b as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "XBM" => self.parse_op(&[
+                (Op::Xbm, Self::op_b as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "XBRK" => self.parse_op(position, &[
-                (Op::Xbrk, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "XBRK" => self.parse_op(&[
+                (Op::Xbrk, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "XDBG" => self.parse_op(position, &[
-                (Op::Xdbg, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "XDBG" => self.parse_op(&[
+                (Op::Xdbg, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "XPSE" => self.parse_op(position, &[
-                (Op::Xpse, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "XPSE" => self.parse_op(&[
+                (Op::Xpse, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "XPSD" => self.parse_op(position, &[
-                (Op::Xpsd, Self::op_void as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "XPSD" => self.parse_op(&[
+                (Op::Xpsd, Self::op_void as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            "XOR" => self.parse_op(position, &[
-                (Op::XorRR, Self::op_rr as fn(&mut Self, Op, &Position) -> Result<Instruction>),
-                (Op::XorRW, Self::op_rw as fn(&mut Self, Op, &Position) -> Result<Instruction>),
+            "XOR" => self.parse_op(&[
+                (Op::XorRR, Self::op_rr as fn(&mut Self, Op) -> Result<Instruction>),
+                (Op::XorRW, Self::op_rw as fn(&mut Self, Op) -> Result<Instruction>),
             ]),
-            op => Err(format!("Invalid mnemonic '{}' at {}", op, position).into())
+            op => Err(format!("Invalid mnemonic '{}'", op).into())
         };
     }
 
-    fn parse_op<F>(&mut self, position: &Position, fs: &[(Op, F)]) -> Result<Instruction>
-        where F: Fn(&mut Self, Op, &Position) -> Result<Instruction>
+    fn parse_op<F>(&mut self, fs: &[(Op, F)]) -> Result<Instruction>
+        where F: Fn(&mut Self, Op) -> Result<Instruction>
     {
         let results: Vec<Result<Instruction>> = fs.iter()
-            .map(|op| op.1(self, op.0, position))
+            .map(|op| op.1(self, op.0))
             .collect();
 
         let success: Vec<&Instruction> = results.iter()
@@ -316,36 +794,36 @@ impl<'t> Parser<'t> {
                              merge_errors(results)
             ).into()),
             1 => Ok((**success.get(0).unwrap()).clone()),
-            _ => Err(format!("No unique alternative at {}", position).into()),
+            _ => Err("No unique alternative".into()),
         }
     }
 
-    fn op_aa(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_aa(position) {
+    fn op_aa(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_aa() {
             Ok(a1) => Ok(Instruction::IA(op, a1, Absolute)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_as(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_as(position) {
+    fn op_as(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_as() {
             Ok(a1) => Ok(Instruction::IA(op, a1, Segment)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_aar(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_aar(position) {
+    fn op_aar(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_aar() {
             Ok((a1, r1)) => Ok(Instruction::IRA(op, r1, a1, Absolute)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_b(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        let w_result = match self.parse_w(position) {
+    fn op_b(&mut self, op: Op) -> Result<Instruction> {
+        let w_result = match self.parse_w() {
             Ok(w1) => match w1 {
                 0..=255 => Ok(w1 as u8),
-                _ => Err(format!("<b> at {}", position).into()),
+                _ => Err("<b>".into()),
             },
             Err(e) => Err(e),
         };
@@ -356,71 +834,71 @@ impl<'t> Parser<'t> {
         Err(format!("Expected {}", w_result.err().unwrap()))
     }
 
-    fn op_r(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_r(position) {
+    fn op_r(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_r() {
             Ok(r1) => Ok(Instruction::IR(op, r1)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_raa(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_raa(position) {
+    fn op_raa(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_raa() {
             Ok((r1, a1)) => Ok(Instruction::IRA(op, r1, a1, Absolute)),
             Err(e) => Err(e)
         }
     }
 
-    fn op_ras(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_ras(position) {
+    fn op_ras(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_ras() {
             Ok((r1, a1)) => Ok(Instruction::IRA(op, r1, a1, Segment)),
             Err(e) => Err(e)
         }
     }
 
-    fn op_ro(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_ro(position) {
+    fn op_ro(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_ro() {
             Ok((r1, r2, o1)) => Ok(Instruction::IRRW(op, r1, r2, o1)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_rr(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_rr(position) {
+    fn op_rr(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_rr() {
             Ok((r1, r2)) => Ok(Instruction::IRR(op, r1, r2)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_rw(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_rw(position) {
+    fn op_rw(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_rw() {
             Ok((r1, w1)) => Ok(Instruction::IRW(op, r1, w1)),
             Err(e) => Err(e)
         }
     }
 
-    fn op_rrr(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_rrr(position) {
+    fn op_rrr(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_rrr() {
             Ok((r1, r2, r3)) => Ok(Instruction::IRRR(op, r1, r2, r3)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_void(&mut self, op: Op, position: &Position) -> Result<Instruction> {
+    fn op_void(&mut self, op: Op) -> Result<Instruction> {
         if self.read_eol() {
             return Ok(Instruction::I(op));
         }
-        Err(format!("Expected <eol> at {}", position).into())
+        Err("Expected <eol>".into())
     }
 
-    fn op_w(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_w(position) {
+    fn op_w(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_w() {
             Ok(w1) => Ok(Instruction::IW(op, w1)),
             Err(e) => Err(e),
         }
     }
 
-    fn op_wr(&mut self, op: Op, position: &Position) -> Result<Instruction> {
-        match self.parse_wr(position) {
+    fn op_wr(&mut self, op: Op) -> Result<Instruction> {
+        match self.parse_wr() {
             Ok((w1, r1)) => Ok(Instruction::IRW(op, r1, w1)),
             Err(e) => Err(e),
         }
@@ -429,12 +907,12 @@ impl<'t> Parser<'t> {
     // --- parse
 
     /// parses `<&-addr> <eol>`
-    fn parse_aa(&mut self, position: &Position) -> Result<String> {
+    fn parse_aa(&mut self) -> Result<String> {
         if !self.peek_abs_address(0) {
-            return Err(format!("<&-addr> at {}", position).into());
+            return Err("<&-addr>".into());
         }
         if !self.peek_eol(1) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let a1 = self.read_address().unwrap();
@@ -443,12 +921,12 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<@-addr> <eol>`
-    fn parse_as(&mut self, position: &Position) -> Result<String> {
+    fn parse_as(&mut self) -> Result<String> {
         if !self.peek_seg_address(0) {
-            return Err(format!("<@-addr> at {}", position).into());
+            return Err("<@-addr>".into());
         }
         if !self.peek_eol(1) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let a1 = self.read_address().unwrap();
@@ -457,18 +935,18 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<&-addr> ',' <r> <eol>`
-    fn parse_aar(&mut self, position: &Position) -> Result<(String, String)> {
+    fn parse_aar(&mut self) -> Result<(String, String)> {
         if !self.peek_abs_address(0) {
-            return Err(format!("<&-addr> at {}", position).into());
+            return Err("<&-addr>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' at {}", position).into());
+            return Err("','".into());
         }
         if !self.peek_register(2) {
-            return Err(format!("<r> at {}", position).into());
+            return Err("<r>".into());
         }
         if !self.peek_eol(3) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let a1 = self.read_address().unwrap();
@@ -479,12 +957,12 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<r> <eol>`
-    fn parse_r(&mut self, position: &Position) -> Result<String> {
+    fn parse_r(&mut self) -> Result<String> {
         if !self.peek_register(0) {
-            return Err(format!("<r> at {}", position).into());
+            return Err("<r>".into());
         }
         if !self.peek_eol(1) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -493,18 +971,18 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<r> ',' <&-addr> <eol>`
-    fn parse_raa(&mut self, position: &Position) -> Result<(String, String)> {
+    fn parse_raa(&mut self) -> Result<(String, String)> {
         if !self.peek_register(0) {
-            return Err(format!("<r> ',' <&-addr> <eol> at {}", position).into());
+            return Err("<r> ',' <&-addr> <eol>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' <&-addr> <eol> at {}", position).into());
+            return Err("',' <&-addr> <eol>".into());
         }
         if !self.peek_abs_address(2) {
-            return Err(format!("<&-addr> <eol> at {}", position).into());
+            return Err("<&-addr> <eol>".into());
         }
         if !self.peek_eol(3) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -515,18 +993,18 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<r> ',' <@-addr> <eol>`
-    fn parse_ras(&mut self, position: &Position) -> Result<(String, String)> {
+    fn parse_ras(&mut self) -> Result<(String, String)> {
         if !self.peek_register(0) {
-            return Err(format!("<r> ',' <@-addr> <eol> at {}", position).into());
+            return Err("<r> ',' <@-addr> <eol>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' <@-addr> <eol> at {}", position).into());
+            return Err("',' <@-addr> <eol>".into());
         }
         if !self.peek_seg_address(2) {
-            return Err(format!("<@-addr> <eol> at {}", position).into());
+            return Err("<@-addr> <eol>".into());
         }
         if !self.peek_eol(3) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -537,31 +1015,31 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<r> ',' '[' <r> '+' <w> ']' <eol>`
-    fn parse_ro(&mut self, position: &Position) -> Result<(String, String, u32)> {
+    fn parse_ro(&mut self) -> Result<(String, String, u32)> {
         if !self.peek_register(0) {
-            return Err(format!("<r> ',' '[' <r> '+' ( <w> | <var> ) ']' <eol> at {}", position).into());
+            return Err("<r> ',' '[' <r> '+' ( <w> | <var> ) ']' <eol>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' '[' <r> '+' ( <w> | <var> ) ']' <eol> at {}", position).into());
+            return Err("',' '[' <r> '+' ( <w> | <var> ) ']' <eol>".into());
         }
         if !self.peek_lbracket(2) {
-            return Err(format!("'[' <r> '+' ( <w> | <var> ) ']' <eol> at {}", position).into());
+            return Err("'[' <r> '+' ( <w> | <var> ) ']' <eol>".into());
         }
         if !self.peek_register(3) {
-            return Err(format!("<r> '+' ( <w> | <var> ) ']' <eol> at {}", position).into());
+            return Err("<r> '+' ( <w> | <var> ) ']' <eol>".into());
         }
         match self.peek(4) {
             Some(Token::Plus(_)) => (),
-            _ => return Err(format!("'+' ( <w> | <var> ) ']' <eol> at {}", position).into()),
+            _ => return Err("'+' ( <w> | <var> ) ']' <eol>".into()),
         };
-        if !self.peek_word(5) && !self.peek_variable(3) {
-            return Err(format!("( <w> | <var> ) ']' <eol> at {}", position).into());
+        if !self.peek_word(5) && !self.peek_variable(5) {
+            return Err("( <w> | <var> ) ']' <eol>".into());
         }
         if !self.peek_rbracket(6) {
-            return Err(format!("']' <eol> at {}", position).into());
+            return Err("']' <eol>".into());
         }
         if !self.peek_eol(7) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -573,9 +1051,9 @@ impl<'t> Parser<'t> {
             Token::Integer(_, w) => w,
             Token::Variable(_, name) => match self.symbols.get(&name) {
                 Some(Token::Integer(_, w)) => *w,
-                _ => return Err(format!("Unknown variable '{}' at {}", name, position).into()),
+                _ => return Err(format!("Unknown variable '{}'", name).into()),
             },
-            _ => return Err(format!("Unexpected token at {}", position).into())
+            _ => return Err("Unexpected token".into())
         };
         self.read_rbracket();
         self.read_eol();
@@ -583,18 +1061,18 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<r> ',' <r>`
-    fn parse_rr(&mut self, position: &Position) -> Result<(String, String)> {
+    fn parse_rr(&mut self) -> Result<(String, String)> {
         if !self.peek_register(0) {
-            return Err(format!("<r> ',' <r> <eol> at {}", position).into());
+            return Err("<r> ',' <r> <eol>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' <r> <eol> at {}", position).into());
+            return Err("',' <r> <eol>".into());
         }
         if !self.peek_register(2) {
-            return Err(format!("<r> <eol> at {}", position).into());
+            return Err("<r> <eol>".into());
         }
         if !self.peek_eol(3) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -605,18 +1083,18 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `<r> ',' ( <w> | <var> ) <eol>`
-    fn parse_rw(&mut self, position: &Position) -> Result<(String, u32)> {
+    fn parse_rw(&mut self) -> Result<(String, u32)> {
         if !self.peek_register(0) {
-            return Err(format!("<r> ',' ( <w> | <var> ) <eol> at {}", position).into());
+            return Err("<r> ',' ( <w> | <var> ) <eol>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' ( <w> | <var> ) <eol> at {}", position).into());
+            return Err("',' ( <w> | <var> ) <eol>".into());
         }
         if !self.peek_word(2) && !self.peek_variable(2) {
-            return Err(format!("( <w> | <var> ) <eol> at {}", position).into());
+            return Err("( <w> | <var> ) <eol>".into());
         }
         if !self.peek_eol(3) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -625,33 +1103,33 @@ impl<'t> Parser<'t> {
             Token::Integer(_, w) => w,
             Token::Variable(_, name) => match self.symbols.get(&name) {
                 Some(Token::Integer(_, w)) => *w,
-                _ => return Err(format!("Unknown variable '{}' at {}", name, position).into()),
+                _ => return Err(format!("Unknown variable '{}'", name).into()),
             },
-            _ => return Err(format!("Unexpected token at {}", position).into())
+            _ => return Err("Unexpected token".into())
         };
         self.read_eol();
         return Ok((r1, w1));
     }
 
     /// parses `<r> ',' <r> ',' <r> <eol>`
-    fn parse_rrr(&mut self, position: &Position) -> Result<(String, String, String)> {
+    fn parse_rrr(&mut self) -> Result<(String, String, String)> {
         if !self.peek_register(0) {
-            return Err(format!("<r> at {}", position).into());
+            return Err("<r>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' at {}", position).into());
+            return Err("','".into());
         }
         if !self.peek_register(2) {
-            return Err(format!("<r> at {}", position).into());
+            return Err("<r>".into());
         }
         if !self.peek_comma(3) {
-            return Err(format!("',' at {}", position).into());
+            return Err("','".into());
         }
         if !self.peek_register(4) {
-            return Err(format!("<r> at {}", position).into());
+            return Err("<r>".into());
         }
         if !self.peek_eol(5) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let r1 = self.read_register().unwrap();
@@ -664,48 +1142,48 @@ impl<'t> Parser<'t> {
     }
 
     /// parses `( <w> | <var> ) <eol>`
-    fn parse_w(&mut self, position: &Position) -> Result<u32> {
+    fn parse_w(&mut self) -> Result<u32> {
         if !self.peek_word(0) && !self.peek_variable(0) {
-            return Err(format!("<w> or <var> at {}", position).into());
+            return Err("<w> or <var>".into());
         }
         if !self.peek_eol(1) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let w1 = match self.read_next().unwrap() {
             Token::Integer(_, w) => w,
             Token::Variable(_, name) => match self.symbols.get(&name) {
                 Some(Token::Integer(_, w)) => *w,
-                _ => return Err(format!("Unknown variable '{}' at {}", name, position).into()),
+                _ => return Err(format!("Unknown variable '{}'", name).into()),
             },
-            _ => return Err(format!("Unexpected token at {}", position).into())
+            _ => return Err("Unexpected token".into())
         };
         self.read_eol();
         return Ok(w1);
     }
 
     /// parses `( <w> | <var> ) ',' <r> <eol>`
-    fn parse_wr(&mut self, position: &Position) -> Result<(u32, String)> {
+    fn parse_wr(&mut self) -> Result<(u32, String)> {
         if !self.peek_word(0) && !self.peek_variable(0) {
-            return Err(format!("<w> or <var> at {}", position).into());
+            return Err("<w> or <var>".into());
         }
         if !self.peek_comma(1) {
-            return Err(format!("',' at {}", position).into());
+            return Err("','".into());
         }
         if !self.peek_register(2) {
-            return Err(format!("<r> at {}", position).into());
+            return Err("<r>".into());
         }
         if !self.peek_eol(3) {
-            return Err(format!("<eol> at {}", position).into());
+            return Err("<eol>".into());
         }
 
         let w1 = match self.read_next().unwrap() {
             Token::Integer(_, w) => w,
             Token::Variable(_, name) => match self.symbols.get(&name) {
                 Some(Token::Integer(_, w)) => *w,
-                _ => return Err(format!("Unknown variable '{}' at {}", name, position).into()),
+                _ => return Err(format!("Unknown variable '{}'", name).into()),
             },
-            _ => return Err(format!("Unexpected token at {}", position).into())
+            _ => return Err("Unexpected token".into())
         };
         self.read_comma();
         let r1 = self.read_register().unwrap();
@@ -846,21 +1324,41 @@ impl<'t> Iterator for Parser<'t> {
             return match self.lexer.next() {
                 None => None,
                 Some(Err(err)) => Some(Err(err)),
-                Some(Ok(token)) => match token {
-                    Token::Eol(_) => continue,
-                    Token::Directive(position, name) => Some(self.parse_directive(name, &position).map(|d| { Node::Directive(d) })),
-                    Token::Label(position, label) => match self.lexer.next() {
-                        Some(Ok(Token::Eol(_))) => Some(Ok(Node::Label(label))),
-                        _ => Some(Err(format!("Expected <eol> at {}", position).into())),
-                    },
-                    Token::Section(_, _) => None,
-                    Token::Op(position, op) => Some(self.parse_instruction(op.as_str(), &position).map(|i| Node::Instruction(i))),
-                    Token::Variable(position, name) => match self.parse_variable(name, &position) {
-                        Ok(_) => continue,
-                        Err(err) => Some(Err(err))
+                Some(Ok(token)) => {
+                    self.position = Some(token.position().clone());
+                    match token {
+                        Token::Eol(_) => continue,
+                        Token::Directive(position, name) if name.to_lowercase() == "struct" => match self.parse_struct(&position) {
+                            Ok(_) => continue,
+                            Err(err) => Some(Err(err))
+                        }
+                        Token::Directive(_, name) if name.to_lowercase() == "enum" => match self.parse_enum() {
+                            Ok(_) => continue,
+                            Err(err) => Some(Err(err))
+                        }
+                        Token::Directive(position, name) if name.to_lowercase() == "test" => match self.parse_test(&position) {
+                            Ok(test) => {
+                                if let Some(tests) = self.tests.as_mut() {
+                                    tests.push(test);
+                                }
+                                continue;
+                            }
+                            Err(err) => Some(Err(err))
+                        }
+                        Token::Directive(position, name) => Some(self.parse_directive(name, &position).map(|d| { Node::Directive(d) })),
+                        Token::Label(_, label) => match self.lexer.next() {
+                            Some(Ok(Token::Eol(_))) => Some(Ok(Node::Label(label))),
+                            _ => Some(Err("Expected <eol>".into())),
+                        },
+                        Token::Section(_, _) => None,
+                        Token::Op(_, op) => Some(self.parse_instruction(op.as_str()).map(|i| Node::Instruction(i))),
+                        Token::Variable(position, name) => match self.parse_variable(name, &position) {
+                            Ok(_) => continue,
+                            Err(err) => Some(Err(err))
+                        }
+                        _ => Some(Err("Expected directive, label, section, label, op or variable".into())),
                     }
-                    other => Some(Err(format!("Expected directive, label, section, label, op or variable at {}", other.position()).into())),
-                },
+                }
             };
         }
     }
@@ -1292,6 +1790,332 @@ mod tests {
         assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
     }
 
+    #[test]
+    fn test_parse_directive_image() {
+        let path = std::env::temp_dir().join("tha_test_parse_directive_image.ppm");
+        std::fs::write(&path, "P3 2 1 255 255 0 0 0 0 255\n").unwrap();
+
+        let mut lexer = Lexer::from_text(format!("#image \"{}\"\n", path.display()).as_str());
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let expected = vec![
+            Node::Directive(Directive::Image("tha_test_parse_directive_image".into(), vec![0x00ff0000, 0x000000ff])),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+        match symbols.get("$tha_test_parse_directive_image_width") {
+            Some(Token::Integer(_, v)) => assert_eq!(2, *v),
+            other => assert_eq!(true, false, "Expected Token::Integer(_, 2), got {:?}", other),
+        }
+        match symbols.get("$tha_test_parse_directive_image_height") {
+            Some(Token::Integer(_, v)) => assert_eq!(1, *v),
+            other => assert_eq!(true, false, "Expected Token::Integer(_, 1), got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_directive_image_variable_defined() {
+        let path = std::env::temp_dir().join("tha_test_parse_directive_image_defined.ppm");
+        std::fs::write(&path, "P3 1 1 255 255 0 0\n").unwrap();
+
+        let source = format!("$tha_test_parse_directive_image_defined_height = 1\n#image \"{}\"\n", path.display());
+        let mut lexer = Lexer::from_text(source.as_str());
+        let r = Parser::from_lexer(&mut lexer, &mut vec![], &mut HashMap::new()).parse();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(Err("Variable $tha_test_parse_directive_image_defined_height defined more than once".to_string()), r);
+    }
+
+    #[test]
+    fn test_parse_directive_image_invalid_label() {
+        let mut lexer = Lexer::from_text("#image \"Logo.ppm\"\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Cannot derive a label from 'Logo.ppm'".to_string()), r);
+        assert_eq!(Some(&Position::new(1, 1)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_offset_variable() {
+        let mut lexer = Lexer::from_text("$offset = 8\nLOAD r1, [r0 + $offset]\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = vec![
+            Node::Instruction(Instruction::IRRW(Op::LoadRRW, "r1".into(), "r0".into(), 8)),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+
+        let mut lexer = Lexer::from_text("LOAD r1, [r0 + $offset]\n");
+        let r = Parser::from_lexer(&mut lexer, &mut vec![], &mut HashMap::new()).parse();
+        assert!(r.err().is_some_and(|e| e.contains("Unknown variable '$offset'")));
+    }
+
+    #[test]
+    fn test_parse_directive_space_without_label() {
+        for (source, directive) in [("#space 4\n", "space"), ("#word 4\n", "word")] {
+            let mut lexer = Lexer::from_text(source);
+            let mut nodes = vec![];
+            let mut symbols = HashMap::new();
+            let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+            let r = parser.parse();
+
+            assert_eq!(Err(format!("Expected <identifier> for directive '#{}'", directive)), r);
+            assert_eq!(Some(&Position::new(1, 1)), parser.position());
+        }
+    }
+
+    #[test]
+    fn test_parse_directive_space() {
+        let mut lexer = Lexer::from_text("$len = 6\n#space buffer $len\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let expected = vec![
+            Node::Directive(Directive::Space("buffer".into(), 8)),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+    }
+
+    #[test]
+    fn test_parse_directive_global_extern_entry() {
+        let mut lexer = Lexer::from_text("#global main\n#extern memcpy\n#entry main\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let expected = vec![
+            Node::Directive(Directive::Global("main".into())),
+            Node::Directive(Directive::Extern("memcpy".into())),
+            Node::Directive(Directive::Entry("main".into())),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+    }
+
+    #[test]
+    fn test_parse_directive_section() {
+        let mut lexer = Lexer::from_text("#section text\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let expected = vec![
+            Node::Directive(Directive::Section("text".into())),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+    }
+
+    #[test]
+    fn test_parse_directive_struct() {
+        let mut lexer = Lexer::from_text(
+            "#struct point { x: word, y: word }\n#struct pixel {\n c: byte\n p: point\n}\n#space origin $pixel.size\nLOAD r0, [r1 + $pixel.p]\n"
+        );
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        for (variable, value) in [
+            ("$point.x", 0), ("$point.y", 4), ("$point.size", 8),
+            ("$pixel.c", 0), ("$pixel.p", 4), ("$pixel.size", 12),
+        ] {
+            match symbols.get(variable) {
+                Some(Token::Integer(_, v)) => assert_eq!(value, *v, "Expected {} = {}, got {}", variable, value, v),
+                other => assert_eq!(true, false, "Expected Token::Integer(_, {}), got {:?}", value, other),
+            }
+        }
+
+        let expected = vec![
+            Node::Directive(Directive::Space("origin".into(), 12)),
+            Node::Instruction(Instruction::IRRW(Op::LoadRRW, "r0".into(), "r1".into(), 4)),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+    }
+
+    #[test]
+    fn test_parse_directive_struct_duplicate_field() {
+        let mut lexer = Lexer::from_text("#struct point { x: word, x: word }\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Field x defined more than once".to_string()), r);
+        assert_eq!(Some(&Position::new(1, 26)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_struct_unknown_type() {
+        let mut lexer = Lexer::from_text("#struct line { a: point }\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Unknown type 'point'".to_string()), r);
+        assert_eq!(Some(&Position::new(1, 16)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_struct_missing_separator() {
+        let mut lexer = Lexer::from_text("#struct point { x: word y: word }\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Expected ',' or '}'".to_string()), r);
+        assert_eq!(Some(&Position::new(1, 25)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_struct_invalid_field() {
+        let mut lexer = Lexer::from_text("#struct point {\n  x: word\n  1: word\n}\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Expected <identifier> or '}'".to_string()), r);
+        assert_eq!(Some(&Position::new(3, 3)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_enum() {
+        let mut lexer = Lexer::from_text(
+            "$__int_timer = 3\n#enum int { timer = $__int_timer, vsync\n keyboard }\n#enum e { a, b = 10, c }\nMOV r0, $int.vsync\n"
+        );
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        for (variable, value) in [
+            ("$int.timer", 3), ("$int.vsync", 4), ("$int.keyboard", 5),
+            ("$e.a", 0), ("$e.b", 10), ("$e.c", 11),
+        ] {
+            match symbols.get(variable) {
+                Some(Token::Integer(_, v)) => assert_eq!(value, *v, "Expected {} = {}, got {}", variable, value, v),
+                other => assert_eq!(true, false, "Expected Token::Integer(_, {}), got {:?}", value, other),
+            }
+        }
+
+        let expected = vec![
+            Node::Instruction(Instruction::IRW(Op::MovRW, "r0".into(), 4)),
+        ];
+        assert_eq!(expected, nodes, "Expected {:?}, got {:?}", expected, nodes);
+    }
+
+    #[test]
+    fn test_parse_directive_enum_duplicate_member() {
+        let mut lexer = Lexer::from_text("#enum e { a, b, a }\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Variable $e.a defined more than once".to_string()), r);
+        assert_eq!(Some(&Position::new(1, 17)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_enum_missing_separator() {
+        let mut lexer = Lexer::from_text("#enum e { a = 1 b }\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Expected ',' or '}'".to_string()), r);
+        assert_eq!(Some(&Position::new(1, 17)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_enum_duplicate_enum() {
+        let mut lexer = Lexer::from_text("#enum e { a }\n#enum e { b }\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Enum e defined more than once".to_string()), r);
+        assert_eq!(Some(&Position::new(2, 1)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_directive_test() {
+        let mut lexer = Lexer::from_text(
+            "$n = 5\n#test fact_5 {\n    entry start\n    set r0 = $n\n    expect r3 = 120, expect [&data] = 1\n    expect [0x10] = 2\n    limit 100\n}\n:start\n    HALT\n"
+        );
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut tests = vec![];
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_tests(&mut tests).parse();
+
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = vec![Test {
+            name: "fact_5".to_string(),
+            span: Span { file: None, position: Position::new(2, 1) },
+            entry: Some("start".to_string()),
+            registers: vec![("r0".to_string(), 5)],
+            expectations: vec![
+                Expectation { location: Location::Register("r3".to_string()), value: 120 },
+                Expectation { location: Location::Label("data".to_string()), value: 1 },
+                Expectation { location: Location::Word(0x10), value: 2 },
+            ],
+            step_limit: Some(100),
+        }];
+        assert_eq!(expected, tests, "Expected {:?}, got {:?}", expected, tests);
+        assert_eq!(vec![Node::Label("start".to_string()), Node::Instruction(Instruction::I(Op::Halt))], nodes);
+    }
+
+    #[test]
+    fn test_parse_directive_test_invalid() {
+        let mut lexer = Lexer::from_text("#test t {\n    expect r0 120\n}\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut parser = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols);
+        let r = parser.parse();
+
+        assert_eq!(Err("Expected '='".to_string()), r);
+        assert_eq!(Some(&Position::new(2, 5)), parser.position());
+    }
+
+    #[test]
+    fn test_parse_spans() {
+        let mut lexer = Lexer::from_text("$v = 1\n:label\n  MOV r1, $v\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        let r = Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).parse();
+        assert_eq!(true, r.is_ok(), "Expected Ok(...), got {:?}", r);
+
+        let expected = vec![
+            Span { file: None, position: Position::new(2, 1) },
+            Span { file: None, position: Position::new(3, 3) },
+        ];
+        assert_eq!(expected, spans.nodes, "Expected {:?}, got {:?}", expected, spans.nodes);
+        assert_eq!(Some(&Span { file: None, position: Position::new(1, 1) }), spans.variables.get("$v"));
+    }
+
     #[test]
     fn test_parse() {
         let mut lexer = Lexer::from_text("//test\n  :label\nMOV r1, 0\n");
@@ -0,0 +1,65 @@
+use crate::constants::{REG_BP, REG_COUNT, REG_CS, REG_IDT, REG_IR, REG_PC, REG_SP};
+
+/// The special registers, as written in assembly, and their number, as encoded in the instructions.
+pub const SPECIAL: [(&str, usize); 6] = [
+    ("pc", REG_PC),
+    ("sp", REG_SP),
+    ("bp", REG_BP),
+    ("cs", REG_CS),
+    ("ir", REG_IR),
+    ("idt", REG_IDT),
+];
+
+/// The number of the register `name`: `r0` to `r31`, or a special register.
+pub fn number(name: &str) -> Option<usize> {
+    number_among(name, REG_COUNT)
+}
+
+/// The number of the register `name` when the VM has `count` general purpose registers.
+pub fn number_among(name: &str, count: usize) -> Option<usize> {
+    if let Some((_, number)) = SPECIAL.iter().find(|(special, _)| *special == name) {
+        return Some(*number);
+    }
+    match name.strip_prefix('r').and_then(|n| n.parse::<usize>().ok()) {
+        Some(r) if r < count && format!("r{}", r) == name => Some(r),
+        _ => None,
+    }
+}
+
+/// The name of the register numbered `number`, if any.
+pub fn name(number: usize) -> Option<String> {
+    match number {
+        r if r < REG_COUNT => Some(format!("r{}", r)),
+        _ => SPECIAL.iter().find(|(_, special)| *special == number).map(|(name, _)| name.to_string()),
+    }
+}
+
+/// The names of all the registers, the general purpose ones first.
+pub fn names() -> impl Iterator<Item = String> {
+    (0..REG_COUNT).map(|r| format!("r{}", r)).chain(SPECIAL.iter().map(|(name, _)| name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for name in names() {
+            assert_eq!(Some(name.clone()), number(&name).and_then(self::name), "{}", name);
+        }
+        assert_eq!(38, names().count());
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(None, number("r32"));
+        assert_eq!(None, number("r01"));
+        assert_eq!(None, number("r+1"));
+        assert_eq!(None, number("foo"));
+        assert_eq!(None, number_among("r8", 8));
+        assert_eq!(Some(REG_SP), number_among("sp", 8));
+        assert_eq!(None, name(32));
+        assert_eq!(None, name(249));
+    }
+}
@@ -0,0 +1,733 @@
+use std::fmt;
+
+use crate::constants::{REG_BP, REG_COUNT, REG_CS, REG_IDT, REG_IR, REG_PC, REG_SP};
+use crate::op::Op;
+
+// The memory map of the VM, see src/vm/vmarch.h.
+pub const WORD_SIZE: u32 = 4;
+pub const STACK_SIZE: u32 = 1024 * WORD_SIZE;
+pub const DEFAULT_RAM_SIZE: u32 = STACK_SIZE + 1024;
+pub const ROM_SIZE: u32 = 32 * 1024 * 1024;
+pub const ROM_ADDRESS: u32 = 0u32.wrapping_sub(ROM_SIZE);
+pub const VIDEO_BUFFER_SIZE: u32 = 320 * 200 * 4;
+pub const VIDEO_BUFFER_1_ADDRESS: u32 = ROM_ADDRESS - VIDEO_BUFFER_SIZE;
+pub const VIDEO_BUFFER_0_ADDRESS: u32 = VIDEO_BUFFER_1_ADDRESS - VIDEO_BUFFER_SIZE;
+pub const VIDEO_META_ADDRESS: u32 = VIDEO_BUFFER_0_ADDRESS - WORD_SIZE;
+pub const INTERRUPTS_COUNT: u32 = 256;
+pub const INTERRUPT_MASK_ADDRESS: u32 = VIDEO_META_ADDRESS - INTERRUPTS_COUNT / 8;
+pub const INTERRUPT_DESCRIPTOR_TABLE_ADDRESS: u32 = INTERRUPT_MASK_ADDRESS - INTERRUPTS_COUNT * WORD_SIZE;
+pub const KEYBOARD_OUT_ADDRESS: u32 = INTERRUPT_DESCRIPTOR_TABLE_ADDRESS - WORD_SIZE;
+pub const KEYBOARD_IN_ADDRESS: u32 = KEYBOARD_OUT_ADDRESS - WORD_SIZE;
+
+pub const INT_TIMER: u8 = 3;
+pub const INT_VSYNC: u8 = 4;
+pub const INT_KEYBOARD: u8 = 5;
+
+/// Where the CPU jumps to when it handles an interrupt: the dispatcher in the ROM, after its first
+/// instruction.
+const INTERRUPT_DISPATCH_ADDRESS: u32 = ROM_ADDRESS + 8;
+const VIDEO_BIT_ENABLED: u32 = 2;
+
+/// The variables of the memory map, as `thm --gen-header` writes them.
+pub fn header() -> String {
+    format!("// addresses\n\
+             $__rom_start = 0x{:08x}\n\
+             $__video_meta = 0x{:08x}\n\
+             $__video_buffer0 = 0x{:08x}\n\
+             $__video_buffer1 = 0x{:08x}\n\
+             $__video_buffer_size = {}\n\
+             $__idt_start = 0x{:08x}\n\
+             $__imask_start = 0x{:08x}\n\
+             $__keyboard_out = 0x{:08x}\n\n\
+             $__keyboard_in = 0x{:08x}\n\n\
+             // interrupts\n\
+             $__int_timer = 0x{:02x}\n\
+             $__int_vsync = 0x{:02x}\n\
+             $__int_keyboard = 0x{:02x}\n",
+            ROM_ADDRESS, VIDEO_META_ADDRESS, VIDEO_BUFFER_0_ADDRESS, VIDEO_BUFFER_1_ADDRESS, VIDEO_BUFFER_SIZE,
+            INTERRUPT_DESCRIPTOR_TABLE_ADDRESS, INTERRUPT_MASK_ADDRESS, KEYBOARD_OUT_ADDRESS, KEYBOARD_IN_ADDRESS,
+            INT_TIMER, INT_VSYNC, INT_KEYBOARD)
+}
+
+/// Why the CPU stopped.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Stop {
+    Halt,
+    Panic(Error),
+    /// `WFI` while no interrupt is pending, nothing being left to trigger one.
+    Wait,
+    /// The step limit was reached.
+    Limit,
+}
+
+/// The errors the VM panics with, along with the address or the byte at fault.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Error {
+    /// `PANIC`.
+    Panic,
+    CannotReadMemory(u32),
+    CannotWriteMemory(u32),
+    UnimplementedOpcode(u8),
+    InvalidRegister(u8),
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Halt => write!(f, "halted"),
+            Stop::Panic(error) => write!(f, "panicked: {}", error),
+            Stop::Wait => write!(f, "waiting for an interrupt"),
+            Stop::Limit => write!(f, "reached the step limit"),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Panic => write!(f, "PANIC"),
+            Error::CannotReadMemory(address) => write!(f, "cannot read memory at 0x{:08x}", address),
+            Error::CannotWriteMemory(address) => write!(f, "cannot write memory at 0x{:08x}", address),
+            Error::UnimplementedOpcode(opcode) => write!(f, "unimplemented opcode 0x{:02x}", opcode),
+            Error::InvalidRegister(register) => write!(f, "invalid register {}", register),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Flags {
+    pub zero: bool,
+    pub negative: bool,
+    pub interrupts_enabled: bool,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Cpu {
+    pub registers: Vec<u32>,
+    pub pc: u32,
+    pub sp: u32,
+    pub bp: u32,
+    pub cs: u32,
+    pub idt: u32,
+    pub ir: u32,
+    pub flags: Flags,
+    /// The number of instructions executed.
+    pub steps: u64,
+}
+
+impl Cpu {
+    /// The value of the register numbered `register`, as encoded in the instructions.
+    pub fn register(&self, register: u8) -> Option<u32> {
+        match register as usize {
+            REG_SP => Some(self.sp),
+            REG_PC => Some(self.pc),
+            REG_CS => Some(self.cs),
+            REG_IDT => Some(self.idt),
+            REG_IR => Some(self.ir),
+            REG_BP => Some(self.bp),
+            r => self.registers.get(r).copied(),
+        }
+    }
+}
+
+/// A memory attached to the bus, its bytes being allocated as they are written.
+#[derive(Debug, PartialEq, Clone)]
+struct Zone {
+    name: &'static str,
+    origin: u32,
+    size: u32,
+    writable: bool,
+    bytes: Vec<u8>,
+}
+
+/// The memories attached to the bus, as in the VM. Words are read and written at addresses
+/// aligned on a word, in big endian.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Memory {
+    zones: Vec<Zone>,
+}
+
+impl Memory {
+    fn new(ram_size: u32) -> Memory {
+        let zone = |name, origin, size, writable| Zone { name, origin, size, writable, bytes: vec![] };
+        Memory {
+            zones: vec![
+                zone("RAM", 0, ram_size, true),
+                zone("KBIn", KEYBOARD_IN_ADDRESS, WORD_SIZE, true),
+                zone("KBOut", KEYBOARD_OUT_ADDRESS, WORD_SIZE, true),
+                zone("IDT", INTERRUPT_DESCRIPTOR_TABLE_ADDRESS, INTERRUPTS_COUNT * WORD_SIZE, true),
+                zone("IMask", INTERRUPT_MASK_ADDRESS, INTERRUPTS_COUNT / 8, true),
+                zone("VMeta", VIDEO_META_ADDRESS, WORD_SIZE, true),
+                zone("VBuf0", VIDEO_BUFFER_0_ADDRESS, VIDEO_BUFFER_SIZE, true),
+                zone("VBuf1", VIDEO_BUFFER_1_ADDRESS, VIDEO_BUFFER_SIZE, true),
+                zone("ROM", ROM_ADDRESS, ROM_SIZE, false),
+            ],
+        }
+    }
+
+    fn zone(&self, address: u32) -> Option<usize> {
+        self.zones.iter().position(|z| z.origin <= address && address - z.origin < z.size)
+    }
+
+    /// The word at `address`, if it is aligned and attached.
+    pub fn word(&self, address: u32) -> Option<u32> {
+        let zone = &self.zones[self.zone(address)?];
+        let offset = (address - zone.origin) as usize;
+        if !offset.is_multiple_of(WORD_SIZE as usize) || offset + WORD_SIZE as usize > zone.size as usize {
+            return None;
+        }
+        let mut bytes = [0u8; 4];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = zone.bytes.get(offset + i).copied().unwrap_or(0);
+        }
+        Some(u32::from_be_bytes(bytes))
+    }
+
+    /// The name of the memory `address` belongs to, as in the VM's state.
+    pub fn name(&self, address: u32) -> Option<&str> {
+        self.zone(address).map(|z| self.zones[z].name)
+    }
+
+    fn set_word(&mut self, address: u32, word: u32) -> bool {
+        match self.zone(address) {
+            Some(z) if self.zones[z].writable => self.store(z, address, &word.to_be_bytes()),
+            _ => false,
+        }
+    }
+
+    /// Writes `bytes` at `address`, even in a read only memory, as the VM does to load the images.
+    fn load(&mut self, address: u32, bytes: &[u8]) -> bool {
+        match self.zone(address) {
+            Some(z) => self.store(z, address, bytes),
+            None => bytes.is_empty(),
+        }
+    }
+
+    fn store(&mut self, z: usize, address: u32, bytes: &[u8]) -> bool {
+        let zone = &mut self.zones[z];
+        let offset = (address - zone.origin) as usize;
+        if !offset.is_multiple_of(WORD_SIZE as usize) || offset + bytes.len() > zone.size as usize {
+            return false;
+        }
+        if zone.bytes.len() < offset + bytes.len() {
+            zone.bytes.resize(offset + bytes.len(), 0);
+        }
+        zone.bytes[offset..offset + bytes.len()].copy_from_slice(bytes);
+        true
+    }
+}
+
+/// The state of the machine once stopped.
+#[derive(Debug, PartialEq, Clone)]
+pub struct State {
+    pub stop: Stop,
+    pub cpu: Cpu,
+    pub memory: Memory,
+}
+
+/// Executes an image the way the VM does: the image is loaded at the end of the stack, where `pc`,
+/// `cs`, `sp` and `bp` point to unless configured otherwise, and the ROM at `ROM_ADDRESS`. The
+/// ROM starts with the interrupts dispatcher of src/common/rom.a, that the CPU calls when handling
+/// an interrupt. Nothing triggers interrupts but `INT` and `with_interrupt`: there is no timer,
+/// keyboard or video.
+pub struct Simulator<'t> {
+    image: &'t [u8],
+    rom: &'t [u8],
+    ram_size: u32,
+    register_count: u8,
+    registers: Vec<(u8, u32)>,
+    pc: u32,
+    cs: Option<u32>,
+    interrupts: Vec<u8>,
+    step_limit: Option<u64>,
+}
+
+type Result<T> = std::result::Result<T, String>;
+
+impl<'t> Simulator<'t> {
+    pub fn new(image: &'t [u8]) -> Simulator<'t> {
+        Simulator {
+            image,
+            rom: &[],
+            ram_size: DEFAULT_RAM_SIZE,
+            register_count: REG_COUNT as u8,
+            registers: vec![],
+            pc: STACK_SIZE,
+            cs: None,
+            interrupts: vec![],
+            step_limit: None,
+        }
+    }
+
+    pub fn with_rom(mut self, rom: &'t [u8]) -> Self {
+        self.rom = rom;
+        self
+    }
+
+    pub fn with_ram_size(mut self, size: u32) -> Self {
+        self.ram_size = size;
+        self
+    }
+
+    pub fn with_register_count(mut self, count: u8) -> Self {
+        self.register_count = count;
+        self
+    }
+
+    /// Sets the register numbered `register` to `value` before running.
+    pub fn with_register(mut self, register: u8, value: u32) -> Self {
+        self.registers.push((register, value));
+        self
+    }
+
+    /// Starts at `address`, also used as `cs` unless set with `with_cs`.
+    pub fn with_pc(mut self, address: u32) -> Self {
+        self.pc = address;
+        self
+    }
+
+    pub fn with_cs(mut self, address: u32) -> Self {
+        self.cs = Some(address);
+        self
+    }
+
+    /// Triggers `interrupt` before running.
+    pub fn with_interrupt(mut self, interrupt: u8) -> Self {
+        self.interrupts.push(interrupt);
+        self
+    }
+
+    /// Stops after `steps` instructions.
+    pub fn with_step_limit(mut self, steps: u64) -> Self {
+        self.step_limit = Some(steps);
+        self
+    }
+
+    pub fn run(self) -> Result<State> {
+        let mut memory = Memory::new(self.ram_size);
+        if !memory.load(STACK_SIZE, self.image) {
+            return Err(format!("Cannot load {} bytes at 0x{:08x}", self.image.len(), STACK_SIZE));
+        }
+        if !memory.load(ROM_ADDRESS, self.rom) {
+            return Err(format!("Cannot load {} bytes at 0x{:08x}", self.rom.len(), ROM_ADDRESS));
+        }
+        memory.set_word(VIDEO_META_ADDRESS, VIDEO_BIT_ENABLED);
+
+        let mut machine = Machine {
+            cpu: Cpu {
+                registers: vec![0; self.register_count as usize],
+                pc: self.pc,
+                sp: STACK_SIZE,
+                bp: STACK_SIZE,
+                cs: self.cs.unwrap_or(self.pc),
+                idt: INTERRUPT_DESCRIPTOR_TABLE_ADDRESS,
+                ir: 0,
+                flags: Flags::default(),
+                steps: 0,
+            },
+            memory,
+            active: [0; INTERRUPTS_COUNT as usize / 32],
+        };
+        for interrupt in [INT_VSYNC, INT_TIMER, INT_KEYBOARD] {
+            machine.mask(interrupt, true);
+        }
+        for (register, value) in self.registers {
+            machine.set_register(register, value).map_err(|_| format!("Invalid register r{}", register))?;
+        }
+        self.interrupts.iter().for_each(|i| machine.trigger(*i));
+
+        let stop = loop {
+            if self.step_limit.is_some_and(|limit| machine.cpu.steps >= limit) {
+                break Stop::Limit;
+            }
+            match machine.step() {
+                Ok(None) => (),
+                Ok(Some(stop)) => break stop,
+                Err(error) => break Stop::Panic(error),
+            }
+        };
+
+        Ok(State { stop, cpu: machine.cpu, memory: machine.memory })
+    }
+}
+
+struct Machine {
+    cpu: Cpu,
+    memory: Memory,
+    /// The triggered interrupts, a bit each.
+    active: [u32; INTERRUPTS_COUNT as usize / 32],
+}
+
+impl Machine {
+    /// Handles the pending interrupt, if any, then executes an instruction.
+    fn step(&mut self) -> std::result::Result<Option<Stop>, Error> {
+        if self.cpu.flags.interrupts_enabled {
+            if let Some(interrupt) = self.pending() {
+                self.cpu.flags.interrupts_enabled = false;
+                self.cpu.ir = interrupt as u32;
+                self.active[interrupt as usize / 32] &= !(1 << (interrupt % 32));
+                self.push(self.cpu.pc)?;
+                self.cpu.pc = INTERRUPT_DISPATCH_ADDRESS;
+            }
+        }
+
+        let [opcode, b1, b2, b3] = self.fetch()?.to_be_bytes();
+        let op = Op::from(opcode);
+        if op.bytecode() != opcode {
+            return Err(Error::UnimplementedOpcode(opcode));
+        }
+        self.cpu.steps += 1;
+
+        match op {
+            Op::Nop | Op::Xbm | Op::Xdbg | Op::Xpse | Op::Xpsd | Op::Xbrk => (),
+            Op::Halt => return Ok(Some(Stop::Halt)),
+            Op::Panic => return Err(Error::Panic),
+            Op::MovRW => {
+                let w = self.fetch()?;
+                self.set_register(b1, w)?;
+            }
+            Op::MovRR => {
+                let value = self.register(b2)?;
+                self.set_register(b1, value)?;
+            }
+            Op::AddRR | Op::SubRR | Op::MulRR | Op::AndRR | Op::OrRR | Op::XorRR => {
+                let (a, b) = (self.register(b1)?, self.register(b2)?);
+                self.set_register(b1, arithmetic(op, a, b))?;
+            }
+            Op::AddRW | Op::SubRW | Op::MulRW | Op::AndRW | Op::OrRW | Op::XorRW => {
+                let b = self.fetch()?;
+                let a = self.register(b1)?;
+                self.set_register(b1, arithmetic(op, a, b))?;
+            }
+            Op::IncR => {
+                let value = self.register(b1)?;
+                self.set_register(b1, value.wrapping_add(1))?;
+            }
+            Op::DecR => {
+                let value = self.register(b1)?;
+                self.set_register(b1, value.wrapping_sub(1))?;
+            }
+            Op::CmpRR => {
+                let (a, b) = (self.register(b1)?, self.register(b2)?);
+                self.update_flags(a.wrapping_sub(b));
+            }
+            Op::CmpRW => {
+                let a = self.register(b1)?;
+                let b = self.fetch()?;
+                self.update_flags(a.wrapping_sub(b));
+            }
+            Op::PushR | Op::PushRR | Op::PushRRR => {
+                let count = match op {
+                    Op::PushR => 1,
+                    Op::PushRR => 2,
+                    _ => 3,
+                };
+                for r in [b1, b2, b3].iter().take(count) {
+                    let value = self.register(*r)?;
+                    self.push(value)?;
+                }
+            }
+            Op::PushW => {
+                let w = self.fetch()?;
+                self.push(w)?;
+            }
+            Op::Pusha => {
+                for r in 0..self.cpu.registers.len() as u8 {
+                    let value = self.register(r)?;
+                    self.push(value)?;
+                }
+            }
+            Op::PopR | Op::PopRR | Op::PopRRR => {
+                let count = match op {
+                    Op::PopR => 1,
+                    Op::PopRR => 2,
+                    _ => 3,
+                };
+                for r in [b1, b2, b3].iter().take(count) {
+                    let value = self.pop()?;
+                    self.set_register(*r, value)?;
+                }
+            }
+            Op::Popa => {
+                for r in (0..self.cpu.registers.len() as u8).rev() {
+                    let value = self.pop()?;
+                    self.set_register(r, value)?;
+                }
+            }
+            Op::JeqS | Op::JeqA | Op::JneS | Op::JneA | Op::JS | Op::JA => {
+                let jump = match op {
+                    Op::JeqS | Op::JeqA => self.cpu.flags.zero,
+                    Op::JneS | Op::JneA => !self.cpu.flags.zero,
+                    _ => true,
+                };
+                if jump {
+                    let address = self.fetch()?;
+                    self.cpu.pc = match op {
+                        Op::JeqS | Op::JneS | Op::JS => self.cpu.cs.wrapping_add(address),
+                        _ => address,
+                    };
+                } else {
+                    self.cpu.pc = self.cpu.pc.wrapping_add(WORD_SIZE);
+                }
+            }
+            Op::CallS | Op::CallA | Op::CallR => {
+                let address = match op {
+                    Op::CallS => self.fetch()?.wrapping_add(self.cpu.cs),
+                    Op::CallA => self.fetch()?,
+                    _ => self.register(b1)?,
+                };
+                self.push(self.cpu.pc)?;
+                self.cpu.pc = address;
+            }
+            Op::Ret => self.cpu.pc = self.pop()?,
+            Op::Iret => {
+                self.cpu.pc = self.pop()?;
+                self.cpu.flags.interrupts_enabled = true;
+            }
+            Op::IntB => self.trigger(b1),
+            Op::MiB => self.mask(b1, true),
+            Op::UmiB => self.mask(b1, false),
+            Op::Ind => self.cpu.flags.interrupts_enabled = false,
+            Op::Ine => self.cpu.flags.interrupts_enabled = true,
+            Op::Wfi => {
+                if self.pending().is_none() {
+                    return Ok(Some(Stop::Wait));
+                }
+            }
+            Op::StorRR => {
+                let (address, value) = (self.register(b1)?, self.register(b2)?);
+                self.write(address, value)?;
+            }
+            Op::StorRW => {
+                let address = self.fetch()?;
+                let value = self.register(b1)?;
+                self.write(address, value)?;
+            }
+            Op::LoadRR => {
+                let address = self.register(b2)?;
+                let value = self.read(address)?;
+                self.set_register(b1, value)?;
+            }
+            Op::LoadRRW => {
+                let address = self.register(b2)?;
+                let offset = self.fetch()?;
+                let value = self.read(address.wrapping_add(offset))?;
+                self.set_register(b1, value)?;
+            }
+            Op::LoadRW => {
+                let address = self.fetch()?;
+                let value = self.read(address)?;
+                self.set_register(b1, value)?;
+            }
+        }
+        Ok(None)
+    }
+
+    fn fetch(&mut self) -> std::result::Result<u32, Error> {
+        let word = self.read(self.cpu.pc)?;
+        self.cpu.pc = self.cpu.pc.wrapping_add(WORD_SIZE);
+        Ok(word)
+    }
+
+    fn read(&self, address: u32) -> std::result::Result<u32, Error> {
+        self.memory.word(address).ok_or(Error::CannotReadMemory(address))
+    }
+
+    fn write(&mut self, address: u32, value: u32) -> std::result::Result<(), Error> {
+        match self.memory.set_word(address, value) {
+            true => Ok(()),
+            false => Err(Error::CannotWriteMemory(address)),
+        }
+    }
+
+    /// `[sp - 4] = value; sp = sp - 4`: the stack grows downwards.
+    fn push(&mut self, value: u32) -> std::result::Result<(), Error> {
+        self.cpu.sp = self.cpu.sp.wrapping_sub(WORD_SIZE);
+        self.write(self.cpu.sp, value)
+    }
+
+    fn pop(&mut self) -> std::result::Result<u32, Error> {
+        let value = self.read(self.cpu.sp)?;
+        self.cpu.sp = self.cpu.sp.wrapping_add(WORD_SIZE);
+        Ok(value)
+    }
+
+    fn register(&self, register: u8) -> std::result::Result<u32, Error> {
+        self.cpu.register(register).ok_or(Error::InvalidRegister(register))
+    }
+
+    /// Sets the register, updating the flags whatever the register, as the VM does.
+    fn set_register(&mut self, register: u8, value: u32) -> std::result::Result<(), Error> {
+        match register as usize {
+            REG_SP => self.cpu.sp = value,
+            REG_PC => self.cpu.pc = value,
+            REG_CS => self.cpu.cs = value,
+            REG_IDT => self.cpu.idt = value,
+            REG_IR => self.cpu.ir = value,
+            REG_BP => self.cpu.bp = value,
+            r => *self.cpu.registers.get_mut(r).ok_or(Error::InvalidRegister(register))? = value,
+        }
+        self.update_flags(value);
+        Ok(())
+    }
+
+    fn update_flags(&mut self, value: u32) {
+        self.cpu.flags.zero = value == 0;
+        self.cpu.flags.negative = (value as i32) < 0;
+    }
+
+    fn trigger(&mut self, interrupt: u8) {
+        self.active[interrupt as usize / 32] |= 1 << (interrupt % 32);
+    }
+
+    /// The mask is kept in memory, where the code may change it as well.
+    fn mask(&mut self, interrupt: u8, masked: bool) {
+        let address = INTERRUPT_MASK_ADDRESS + (interrupt as u32 / 32) * WORD_SIZE;
+        let mask = self.memory.word(address).unwrap_or(0);
+        let bit = 1 << (interrupt % 32);
+        self.memory.set_word(address, if masked { mask | bit } else { mask & !bit });
+    }
+
+    /// The lowest triggered interrupt that is not masked.
+    fn pending(&self) -> Option<u8> {
+        (0..INTERRUPTS_COUNT).find_map(|i| {
+            let mask = self.memory.word(INTERRUPT_MASK_ADDRESS + (i / 32) * WORD_SIZE).unwrap_or(0);
+            let bit = 1 << (i % 32);
+            match self.active[i as usize / 32] & bit != 0 && mask & bit == 0 {
+                true => Some(i as u8),
+                false => None,
+            }
+        })
+    }
+}
+
+fn arithmetic(op: Op, a: u32, b: u32) -> u32 {
+    match op {
+        Op::AddRR | Op::AddRW => a.wrapping_add(b),
+        Op::SubRR | Op::SubRW => a.wrapping_sub(b),
+        Op::MulRR | Op::MulRW => (a as i32).wrapping_mul(b as i32) as u32,
+        Op::AndRR | Op::AndRW => a & b,
+        Op::OrRR | Op::OrRW => a | b,
+        _ => a ^ b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::assembler::{assemble, Options, Source};
+
+    use super::*;
+
+    fn image(sources: &[&str]) -> Vec<u8> {
+        let mut sources: Vec<Source> = sources.iter().map(|s| Source::from_text(s)).collect();
+        sources.insert(0, Source::from_text(&header()));
+        let image = assemble(&sources, &Options::new()).unwrap();
+        image.container.segments.into_iter().flat_map(|s| s.bytes).collect()
+    }
+
+    fn example(name: &str) -> Vec<u8> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        image(&[&fs::read_to_string(root.join("examples").join(name)).unwrap()])
+    }
+
+    fn rom() -> Vec<u8> {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        image(&[&fs::read_to_string(root.join("src/common/rom.a")).unwrap()])
+    }
+
+    #[test]
+    fn memory_map() {
+        assert_eq!(0xfe000000, ROM_ADDRESS);
+        assert_eq!(0xfdf82ffc, VIDEO_META_ADDRESS);
+        assert_eq!(0xfdf82bdc, INTERRUPT_DESCRIPTOR_TABLE_ADDRESS);
+        assert_eq!(0xfdf82fdc, INTERRUPT_MASK_ADDRESS);
+        assert_eq!(0xfdf82bd4, KEYBOARD_IN_ADDRESS);
+        assert!(header().contains("$__idt_start = 0xfdf82bdc\n$__imask_start = 0xfdf82fdc\n"));
+    }
+
+    /// The tests of bin/test-vm.sh.
+    #[test]
+    fn examples() {
+        let rom = rom();
+        for (name, r0, register, expected) in [
+            ("fibonacci.a", 0, 3, 0),
+            ("fibonacci.a", 1, 3, 1),
+            ("fibonacci.a", 16, 3, 987),
+            ("fibonacci_rec.a", 16, 3, 987),
+            ("fact.a", 5, 3, 120),
+            ("jumps.a", 0, 0, 7),
+            ("interrupts.a", 0, 0, 42),
+            ("call_convention.a", 0, 0, 3),
+        ] {
+            let image = example(name);
+            let r = Simulator::new(&image).with_rom(&rom).with_register(0, r0).with_step_limit(100_000).run();
+
+            assert!(r.is_ok(), "Expected Ok(...), got {:?}", r.err());
+            let state = r.unwrap();
+            assert_eq!(Stop::Halt, state.stop, "{}", name);
+            assert_eq!(expected, state.cpu.registers[register], "{}({})", name, r0);
+        }
+    }
+
+    #[test]
+    fn stack() {
+        let image = image(&["    MOV   r0, 1\n    MOV   r1, 2\n    PUSH  r0, r1\n    PUSH  3\n\
+                             POP   r2\n    POP   r3, r4\n    HALT\n"]);
+
+        let state = Simulator::new(&image).run().unwrap();
+
+        assert_eq!(Stop::Halt, state.stop);
+        assert_eq!(vec![1, 2, 3, 2, 1], state.cpu.registers[0..5].to_vec());
+        assert_eq!(STACK_SIZE, state.cpu.sp);
+        assert_eq!(Some(1), state.memory.word(STACK_SIZE - 4));
+        assert_eq!(Some(3), state.memory.word(STACK_SIZE - 12));
+    }
+
+    #[test]
+    fn flags() {
+        let image = image(&["    MOV   r0, 1\n    CMP   r0, 2\n    HALT\n"]);
+
+        let state = Simulator::new(&image).run().unwrap();
+
+        assert_eq!(Flags { zero: false, negative: true, interrupts_enabled: false }, state.cpu.flags);
+        assert_eq!(3, state.cpu.steps);
+    }
+
+    #[test]
+    fn stops() {
+        let run = |source: &str| Simulator::new(&image(&[source])).with_step_limit(100).run().unwrap();
+
+        assert_eq!(Stop::Panic(Error::Panic), run("    PANIC\n").stop);
+        assert_eq!(Stop::Wait, run("    WFI\n").stop);
+        assert_eq!(Stop::Limit, run(":l\n    J     @l\n").stop);
+        assert_eq!(Stop::Panic(Error::CannotReadMemory(0x2000)), run("    LOAD  r0, 0x2000\n").stop);
+        assert_eq!(Stop::Panic(Error::CannotWriteMemory(ROM_ADDRESS)), run("    STOR  $__rom_start, r0\n").stop);
+
+        let state = Simulator::new(&image(&["    INC   r5\n"])).with_register_count(4).run().unwrap();
+        assert_eq!(Stop::Panic(Error::InvalidRegister(5)), state.stop);
+
+        let state = Simulator::new(&[0x80, 0, 0, 0]).run().unwrap();
+        assert_eq!(Stop::Panic(Error::UnimplementedOpcode(0x80)), state.stop);
+    }
+
+    #[test]
+    fn interrupts() {
+        let rom = rom();
+        let image = image(&["#base 0x1000\n    MOV   r0, &handler\n    MOV   r1, $__idt_start\n    ADD   r1, 12\n\
+                             STOR  r1, r0\n    UMI   $__int_timer\n    INE\n    NOP\n    HALT\n\
+                             :handler\n    MOV   r2, 1\n    RET\n"]);
+
+        let masked = Simulator::new(&image).with_rom(&rom).with_interrupt(INT_VSYNC).run().unwrap();
+        assert_eq!(Stop::Halt, masked.stop);
+        assert_eq!(0, masked.cpu.registers[2]);
+
+        let state = Simulator::new(&image).with_rom(&rom).with_interrupt(INT_TIMER).run().unwrap();
+        assert_eq!(Stop::Halt, state.stop);
+        assert_eq!(1, state.cpu.registers[2]);
+        assert_eq!(INT_TIMER as u32, state.cpu.ir);
+        assert!(state.cpu.flags.interrupts_enabled);
+    }
+}
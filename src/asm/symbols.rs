@@ -0,0 +1,237 @@
+use core::fmt;
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::fs;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::address_resolver::Address;
+use crate::lexer::Token;
+use crate::parser::{Directive, Node, Spans};
+use crate::visibility::unqualified;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Label,
+    Word,
+    Image,
+    Space,
+    Variable,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            Kind::Label => "label",
+            Kind::Word => "word",
+            Kind::Image => "image",
+            Kind::Space => "space",
+            Kind::Variable => "variable",
+        })
+    }
+}
+
+impl FromStr for Kind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "label" => Ok(Kind::Label),
+            "word" => Ok(Kind::Word),
+            "image" => Ok(Kind::Image),
+            "space" => Ok(Kind::Space),
+            "variable" => Ok(Kind::Variable),
+            _ => Err(format!("Unknown kind '{}'", s)),
+        }
+    }
+}
+
+/// A named address or value. Variables have no size and their value is used as the address.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub address: u32,
+    pub kind: Kind,
+    pub size: u32,
+    pub name: String,
+    pub file: Option<String>,
+}
+
+/// Collects the labels, the data directives and the variables of a program.
+///
+/// They are written one per line, sorted by address then name, as tab-separated fields:
+///
+/// ```text
+/// <address> <kind> <size> <name> <file>
+/// ```
+///
+/// where `address` is the absolute address (or the value of a variable) as 8 hexadecimal digits,
+/// `kind` is one of `label`, `word`, `image`, `space` or `variable`, `size` is the decimal number
+/// of bytes, `name` is the label, as written in its file even when local to it, or the variable
+/// (starting with `$`) and `file` is the defining file or `-` when unknown. Lines starting with `#`
+/// are comments.
+///
+/// The members of the `#enum` directives are left out unless `with_enums` says otherwise.
+pub struct SymbolMap<'t> {
+    nodes: &'t [Node],
+    spans: &'t Spans,
+    addresses: &'t HashMap<String, Address>,
+    symbols: &'t HashMap<String, Token>,
+    enums: bool,
+}
+
+pub const HEADER: &str = "# thm symbols v1";
+
+type Result<T> = std::result::Result<T, String>;
+
+impl<'t> SymbolMap<'t> {
+    pub fn new(
+        nodes: &'t [Node],
+        spans: &'t Spans,
+        addresses: &'t HashMap<String, Address>,
+        symbols: &'t HashMap<String, Token>,
+    ) -> SymbolMap<'t> {
+        SymbolMap { nodes, spans, addresses, symbols, enums: false }
+    }
+
+    /// Lists the members of the `#enum` directives along with the other variables.
+    pub fn with_enums(mut self, enums: bool) -> Self {
+        self.enums = enums;
+        self
+    }
+
+    pub fn symbols(&self) -> Vec<Symbol> {
+        let mut symbols: Vec<Symbol> = self.nodes.iter()
+            .enumerate()
+            .filter_map(|(i, node)| {
+                let (name, kind, size) = match node {
+                    Node::Label(name) => (name, Kind::Label, 0),
+                    Node::Directive(Directive::Word(name, _)) => (name, Kind::Word, 4),
+                    Node::Directive(Directive::Image(name, pixels)) => (name, Kind::Image, 4 * pixels.len() as u32),
+                    Node::Directive(Directive::Space(name, size)) => (name, Kind::Space, *size),
+                    _ => return None,
+                };
+                Some(Symbol {
+                    address: self.addresses.get(name)?.absolute(),
+                    kind,
+                    size,
+                    name: unqualified(name).to_owned(),
+                    file: self.spans.nodes.get(i).and_then(|s| s.file.clone()),
+                })
+            })
+            .chain(self.symbols.iter().filter_map(|(name, token)| match token {
+                _ if !self.enums && self.spans.enums.contains(name) => None,
+                Token::Integer(_, value) => Some(Symbol {
+                    address: *value,
+                    kind: Kind::Variable,
+                    size: 0,
+                    name: name.to_owned(),
+                    file: self.spans.variables.get(name).and_then(|s| s.file.clone()),
+                }),
+                _ => None,
+            }))
+            .collect();
+
+        symbols.sort_by(|a, b| a.address.cmp(&b.address).then(a.name.cmp(&b.name)));
+        symbols
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<()> {
+        let mut text = format!("{}\n# address\tkind\tsize\tname\tfile\n", HEADER);
+        for symbol in self.symbols() {
+            text.push_str(&format!("{:08x}\t{}\t{}\t{}\t{}\n",
+                                   symbol.address,
+                                   symbol.kind,
+                                   symbol.size,
+                                   symbol.name,
+                                   symbol.file.as_deref().unwrap_or("-"),
+            ));
+        }
+        out.write_all(text.as_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+pub fn from_file(file_path: &str) -> Result<Vec<Symbol>> {
+    match fs::read_to_string(file_path) {
+        Ok(text) => read(&text).map_err(|e| format!("{} in {}", e, file_path)),
+        Err(e) => Err(format!("Cannot read {}: {}", file_path, e)),
+    }
+}
+
+/// Reads the symbols back from a symbol map.
+pub fn read(text: &str) -> Result<Vec<Symbol>> {
+    let mut lines = text.lines().enumerate();
+    match lines.next() {
+        Some((_, HEADER)) => (),
+        _ => return Err("Not a symbol map".to_string()),
+    }
+
+    let mut symbols = vec![];
+    for (number, line) in lines.filter(|(_, l)| !l.starts_with('#') && !l.trim().is_empty()) {
+        let number = number + 1;
+        let fields: Vec<&str> = line.split('\t').collect();
+        let (address, kind, size, name, file) = match fields.as_slice() {
+            [address, kind, size, name, file] => (address, kind, size, name, file),
+            _ => return Err(format!("Expected 5 fields at line {}", number)),
+        };
+        symbols.push(Symbol {
+            address: u32::from_str_radix(address, 16).map_err(|_| format!("Invalid address at line {}", number))?,
+            kind: kind.parse().map_err(|e| format!("{} at line {}", e, number))?,
+            size: size.parse().map_err(|_| format!("Invalid size at line {}", number))?,
+            name: name.to_string(),
+            file: if *file == "-" { None } else { Some(file.to_string()) },
+        });
+    }
+    Ok(symbols)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::address_resolver::AddressResolver;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    use super::*;
+
+    #[test]
+    fn write() {
+        let mut lexer = Lexer::from_text("$v = 0x10\n#base 0x1000\n:start\n    NOP\n#word data 0\n#space buffer 8\n");
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).parse().unwrap();
+        let addresses = AddressResolver::new(&nodes).resolve().unwrap();
+
+        let mut out = vec![];
+        let r = SymbolMap::new(&nodes, &spans, &addresses, &symbols).write(&mut out);
+
+        assert!(r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let expected = "# thm symbols v1\n\
+                        # address\tkind\tsize\tname\tfile\n\
+                        00000010\tvariable\t0\t$v\t-\n\
+                        00001000\tlabel\t0\tstart\t-\n\
+                        00001004\tword\t4\tdata\t-\n\
+                        00001008\tspace\t8\tbuffer\t-\n";
+        assert_eq!(expected, String::from_utf8(out).unwrap());
+        assert_eq!(Ok(SymbolMap::new(&nodes, &spans, &addresses, &symbols).symbols()), read(expected));
+    }
+
+    #[test]
+    fn enums() {
+        let mut lexer = Lexer::from_text("$v = 1\n#enum e { a, b }\n");
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        Parser::from_lexer(&mut lexer, &mut vec![], &mut symbols).with_spans(&mut spans).parse().unwrap();
+        let addresses = HashMap::new();
+        let names = |map: SymbolMap| map.symbols().into_iter().map(|s| s.name).collect::<Vec<String>>();
+
+        assert_eq!(vec!["$v"], names(SymbolMap::new(&[], &spans, &addresses, &symbols)));
+        assert_eq!(vec!["$e.a", "$e.b", "$v"], names(SymbolMap::new(&[], &spans, &addresses, &symbols).with_enums(true)));
+    }
+
+    #[test]
+    fn read_invalid_kind() {
+        let symbols = read("# thm symbols v1\n00001000\tlabels\t0\tstart\t-\n");
+
+        assert_eq!(Err("Unknown kind 'labels' at line 2".to_string()), symbols);
+    }
+}
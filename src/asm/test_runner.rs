@@ -0,0 +1,221 @@
+use crate::assembler::{assemble, Image, Options, Source};
+use crate::output::{self, Format};
+use crate::parser::{Location, Span, Test};
+use crate::registers;
+use crate::simulator::{self, Simulator, Stop, STACK_SIZE};
+use crate::visibility;
+
+/// The number of instructions a test may execute, unless its `limit` says otherwise.
+pub const DEFAULT_STEP_LIMIT: u32 = 1_000_000;
+
+/// A test once run, with what went wrong, if anything.
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub name: String,
+    pub span: Span,
+    pub failures: Vec<String>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Runs the `#test` blocks of sources in the simulator. Each source is assembled after the
+/// variables of the memory map, see `simulator::header`, and loaded as the VM does; a test starts
+/// at its `entry` label, or at the `#entry` of the source, or at the start of the image, sets
+/// its registers and expects the CPU to halt with the registers and the memory it lists.
+pub struct Runner {
+    rom: Vec<u8>,
+    step_limit: u32,
+}
+
+type Result<T> = std::result::Result<T, Vec<String>>;
+
+impl Runner {
+    pub fn new() -> Runner {
+        Runner { rom: vec![], step_limit: DEFAULT_STEP_LIMIT }
+    }
+
+    /// Loads `rom` in the ROM, as assembled by `rom`.
+    pub fn with_rom(mut self, rom: Vec<u8>) -> Self {
+        self.rom = rom;
+        self
+    }
+
+    pub fn with_step_limit(mut self, step_limit: u32) -> Self {
+        self.step_limit = step_limit;
+        self
+    }
+
+    /// Runs the tests of `source`, in order.
+    pub fn run(&self, source: &Source) -> Result<Vec<Outcome>> {
+        let (image, bytes) = assemble_image(source)?;
+        Ok(image.tests.iter().map(|test| self.run_test(&image, &bytes, test)).collect())
+    }
+
+    fn run_test(&self, image: &Image, bytes: &[u8], test: &Test) -> Outcome {
+        let mut outcome = Outcome { name: test.name.clone(), span: test.span.clone(), failures: vec![] };
+
+        // the image is loaded at the end of the stack whatever its origin
+        let origin = image.container.segments.first().map_or(0, |segment| segment.origin);
+        let load = |address: u32| address.wrapping_sub(origin).wrapping_add(STACK_SIZE);
+        let label = |name: &str| {
+            // the source is assembled after the header, see `assemble_image`
+            let local = visibility::local(test.span.file.as_deref(), 1, name);
+            image.addresses.get(&local).or_else(|| image.addresses.get(name)).map(|a| load(a.absolute()))
+        };
+
+        let pc = match &test.entry {
+            Some(entry) => match label(entry) {
+                Some(pc) => pc,
+                None => {
+                    outcome.failures.push(format!("Unknown label '{}'", entry));
+                    return outcome;
+                }
+            },
+            None => image.container.entry.map_or(STACK_SIZE, load),
+        };
+        let mut simulator = Simulator::new(bytes)
+            .with_rom(&self.rom)
+            .with_pc(pc)
+            .with_cs(STACK_SIZE)
+            .with_step_limit(test.step_limit.unwrap_or(self.step_limit) as u64);
+        for (name, value) in test.registers.iter() {
+            match register(name) {
+                Some(register) => simulator = simulator.with_register(register, *value),
+                None => outcome.failures.push(format!("Unknown register '{}'", name)),
+            }
+        }
+        if !outcome.passed() {
+            return outcome;
+        }
+
+        let state = match simulator.run() {
+            Ok(state) => state,
+            Err(err) => {
+                outcome.failures.push(err);
+                return outcome;
+            }
+        };
+        if state.stop != Stop::Halt {
+            outcome.failures.push(format!("Expected to halt, {} after {} steps", state.stop, state.cpu.steps));
+        }
+        for expectation in test.expectations.iter() {
+            let (location, actual) = match &expectation.location {
+                Location::Register(name) => (name.clone(), register(name).and_then(|r| state.cpu.register(r))),
+                Location::Word(address) => (format!("[0x{:08x}]", address), state.memory.word(*address)),
+                Location::Label(name) => (format!("[&{}]", name), label(name).and_then(|a| state.memory.word(a))),
+            };
+            match actual {
+                Some(actual) if actual == expectation.value => (),
+                Some(actual) => outcome.failures.push(format!(
+                    "{}: expected {}, got {}", location, value(expectation.value), value(actual)
+                )),
+                None => outcome.failures.push(format!(
+                    "{}: expected {}, got nothing", location, value(expectation.value)
+                )),
+            }
+        }
+        outcome
+    }
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Runner::new()
+    }
+}
+
+/// Assembles `source` after the variables of the memory map, to be loaded with `Runner::with_rom`.
+pub fn rom(source: &Source) -> Result<Vec<u8>> {
+    assemble_image(source).map(|(_, bytes)| bytes)
+}
+
+fn assemble_image(source: &Source) -> Result<(Image, Vec<u8>)> {
+    let image = assemble(&[Source::from_text(&simulator::header()), source.clone()], &Options::new())
+        .map_err(|diagnostics| diagnostics.iter()
+            .map(|d| match &d.position {
+                Some(position) => format!("{}: {}", position, d),
+                None => d.to_string(),
+            })
+            .collect::<Vec<String>>())?;
+    let mut bytes = vec![];
+    output::write(Format::Bin, &image.container, &mut bytes).map_err(|e| vec![format!("Output error: {}", e)])?;
+    Ok((image, bytes))
+}
+
+/// The number of the register `name`, as encoded in the instructions.
+fn register(name: &str) -> Option<u8> {
+    registers::number(name).map(|r| r as u8)
+}
+
+fn value(value: u32) -> String {
+    format!("{} (0x{:08x})", value, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::lexer::Position;
+
+    use super::*;
+
+    fn root() -> &'static Path {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+    }
+
+    #[test]
+    fn run_examples() {
+        let rom = rom(&Source::from_file(root().join("src/common/rom.a").to_str().unwrap()).unwrap()).unwrap();
+        let runner = Runner::new().with_rom(rom);
+
+        let mut names = vec![];
+        for example in ["call_convention", "fact", "fibonacci", "fibonacci_rec", "interrupts", "jumps"] {
+            let path = root().join("examples").join(format!("{}.a", example));
+            let r = runner.run(&Source::from_file(path.to_str().unwrap()).unwrap());
+
+            assert!(r.is_ok(), "Expected Ok(...), got {:?}", r);
+            for outcome in r.unwrap() {
+                assert_eq!(Vec::<String>::new(), outcome.failures, "{}", outcome.name);
+                names.push(outcome.name);
+            }
+        }
+        assert_eq!(vec![
+            "call_convention", "fact_5", "fibonacci_0", "fibonacci_1", "fibonacci_16", "fibonacci_rec_16", "interrupts", "jumps",
+        ], names);
+    }
+
+    #[test]
+    fn run_failures() {
+        let source = Source::from_text("#test a {\n    set r0 = 2\n    expect r0 = 3, expect [&w] = 1\n}\n\
+                                        #test b {\n    entry loop\n    expect sp = 4096\n    limit 10\n}\n\
+                                        #test c {\n    entry nowhere\n}\n\
+                                        #test d {\n    expect [0x2000] = 0\n    expect r40 = 0\n}\n\
+                                            HALT\n:loop\n    J     @loop\n#word w 1\n");
+
+        let r = Runner::new().run(&source);
+
+        assert!(r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let outcomes = r.unwrap();
+        assert_eq!(vec![
+            vec!["r0: expected 3 (0x00000003), got 2 (0x00000002)".to_string()],
+            vec!["Expected to halt, reached the step limit after 10 steps".to_string()],
+            vec!["Unknown label 'nowhere'".to_string()],
+            vec![
+                "[0x00002000]: expected 0 (0x00000000), got nothing".to_string(),
+                "r40: expected 0 (0x00000000), got nothing".to_string(),
+            ],
+        ], outcomes.iter().map(|o| o.failures.clone()).collect::<Vec<_>>());
+        assert_eq!(Span { file: None, position: Position::new(5, 1) }, outcomes[1].span);
+    }
+
+    #[test]
+    fn run_invalid() {
+        let r = Runner::new().run(&Source::from_text("    FOO\n"));
+
+        assert_eq!(Err(vec!["1:5: Syntax error: Invalid mnemonic 'FOO'".to_string()]), r);
+    }
+}
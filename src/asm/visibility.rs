@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+use crate::parser::{Directive, Instruction, Node, NodeError, Span, Spans};
+
+/// Makes the labels local to the source defining them, unless it exports them with `#global`. A
+/// source uses the labels of another one by declaring them with `#extern`.
+///
+/// Local labels are renamed as given by `local`, which cannot clash with another source's labels.
+pub struct Visibility<'t> {
+    nodes: &'t mut [Node],
+    spans: &'t Spans,
+    allow_externs: bool,
+}
+
+type Result<T> = std::result::Result<T, Vec<NodeError>>;
+
+/// Where a label is defined or declared, by index of the node.
+type Sites = HashMap<String, usize>;
+
+impl<'t> Visibility<'t> {
+    /// Applies to `nodes`, whose sources and files are given by the `spans` at the same index.
+    pub fn new(nodes: &'t mut [Node], spans: &'t Spans) -> Visibility<'t> {
+        Visibility { nodes, spans, allow_externs: false }
+    }
+
+    /// Leaves the `#extern` labels no file defines to be resolved by the linker.
+    pub fn allow_externs(mut self) -> Self {
+        self.allow_externs = true;
+        self
+    }
+
+    pub fn apply(&mut self) -> Result<()> {
+        let mut sources: Vec<(usize, Vec<usize>)> = vec![];
+        for index in 0..self.nodes.len() {
+            let source = self.spans.sources.get(index).copied().unwrap_or(0);
+            match sources.iter_mut().find(|(s, _)| *s == source) {
+                Some((_, indexes)) => indexes.push(index),
+                None => sources.push((source, vec![index])),
+            }
+        }
+
+        let mut errors = vec![];
+        let mut globals: Sites = HashMap::new();
+        let mut externs: Vec<(String, usize)> = vec![];
+        let mut renames = vec![];
+
+        for (source, indexes) in sources.iter() {
+            let (defined, file_globals, file_externs) = self.declarations(indexes, &mut errors);
+
+            for (label, index) in file_globals.iter() {
+                if !defined.contains_key(label) {
+                    errors.push(error(*index, label, format!("Label {} declared #global at {} is not defined there", label, self.site(*index))));
+                } else if let Some(other) = globals.insert(label.to_owned(), defined[label]) {
+                    errors.push(error(defined[label], label, format!(
+                        "Label {} defined #global at {} and at {}", label, self.site(other), self.site(defined[label])
+                    )));
+                }
+            }
+            for (label, index) in file_externs.iter() {
+                match defined.get(label) {
+                    Some(definition) => errors.push(error(*index, label, format!(
+                        "Label {} declared #extern at {} is defined at {}", label, self.site(*index), self.site(*definition)
+                    ))),
+                    None => externs.push((label.to_owned(), *index)),
+                }
+            }
+            for index in indexes.iter() {
+                match Self::reference(&self.nodes[*index]) {
+                    Some(label) if !defined.contains_key(label) && !file_externs.contains_key(label) => errors.push(error(*index, label, format!(
+                        "Label {} is neither defined nor declared #extern at {}", label, self.site(*index)
+                    ))),
+                    _ => (),
+                }
+            }
+
+            let file = indexes.first().and_then(|index| self.spans.nodes.get(*index)).and_then(|s| s.file.as_deref());
+            renames.push((indexes, defined.into_keys()
+                .filter(|label| !file_globals.contains_key(label))
+                .map(|label| (local(file, *source, &label), label))
+                .collect::<HashMap<String, String>>()));
+        }
+
+        if !self.allow_externs {
+            externs.sort_by_key(|(_, index)| *index);
+            for (label, index) in externs {
+                if !globals.contains_key(&label) {
+                    errors.push(error(index, &label, format!(
+                        "Label {} declared #extern at {} is not defined #global in any file", label, self.site(index)
+                    )));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        for (indexes, locals) in renames {
+            let locals: HashMap<&String, &String> = locals.iter().map(|(local, label)| (label, local)).collect();
+            for index in indexes.iter() {
+                Self::rename(&mut self.nodes[*index], &locals);
+            }
+        }
+        Ok(())
+    }
+
+    /// Collects the labels a source defines, declares `#global` and declares `#extern`.
+    fn declarations(&self, indexes: &[usize], errors: &mut Vec<NodeError>) -> (Sites, Sites, Sites) {
+        let mut defined: Sites = HashMap::new();
+        let mut globals: Sites = HashMap::new();
+        let mut externs: Sites = HashMap::new();
+
+        for index in indexes.iter() {
+            let (sites, label) = match &self.nodes[*index] {
+                Node::Directive(Directive::Global(label)) => (&mut globals, label),
+                Node::Directive(Directive::Extern(label)) => (&mut externs, label),
+                node => match Self::definition(node) {
+                    Some(label) => (&mut defined, label),
+                    None => continue,
+                },
+            };
+            if let Some(other) = sites.insert(label.to_owned(), *index) {
+                errors.push(error(*index, label, format!(
+                    "Label {} used more than once, at {} and at {}", label, self.site(other), self.site(*index)
+                )));
+            }
+        }
+
+        (defined, globals, externs)
+    }
+
+    fn definition(node: &Node) -> Option<&String> {
+        match node {
+            Node::Label(label)
+            | Node::Directive(Directive::Image(label, _))
+            | Node::Directive(Directive::Space(label, _))
+            | Node::Directive(Directive::Word(label, _)) => Some(label),
+            _ => None,
+        }
+    }
+
+    fn reference(node: &Node) -> Option<&String> {
+        match node {
+            Node::Directive(Directive::Entry(label))
+            | Node::Instruction(Instruction::IA(_, label, _))
+            | Node::Instruction(Instruction::IRA(_, _, label, _)) => Some(label),
+            _ => None,
+        }
+    }
+
+    fn rename(node: &mut Node, locals: &HashMap<&String, &String>) {
+        let label = match node {
+            Node::Label(label)
+            | Node::Directive(Directive::Image(label, _))
+            | Node::Directive(Directive::Space(label, _))
+            | Node::Directive(Directive::Word(label, _))
+            | Node::Directive(Directive::Entry(label))
+            | Node::Instruction(Instruction::IA(_, label, _))
+            | Node::Instruction(Instruction::IRA(_, _, label, _)) => label,
+            _ => return,
+        };
+        if let Some(local) = locals.get(label) {
+            *label = local.to_string();
+        }
+    }
+
+    /// The file and position of the node at `index`.
+    fn site(&self, index: usize) -> String {
+        match self.spans.nodes.get(index) {
+            Some(Span { file: Some(file), position }) => format!("{}:{}", file, position),
+            Some(Span { file: None, position }) => position.to_string(),
+            None => format!("node {}", index),
+        }
+    }
+}
+
+fn error(index: usize, label: &str, message: String) -> NodeError {
+    NodeError { index, name: label.to_string(), message }
+}
+
+/// The name of `label` once local to the source at `index`: `<file>:<label>`, or `<index>:<label>`
+/// when the source has no file.
+pub fn local(file: Option<&str>, index: usize, label: &str) -> String {
+    match file {
+        Some(file) => format!("{}:{}", file, label),
+        None => format!("{}:{}", index, label),
+    }
+}
+
+/// The label as written in its file, without the file a local label is prefixed with.
+pub fn unqualified(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::lexer::Lexer;
+    use crate::parser::{Parser, Spans};
+
+    use super::*;
+
+    fn parse(sources: &[(&str, &str)]) -> (Vec<Node>, Spans) {
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        for (index, (file, source)) in sources.iter().enumerate() {
+            let mut lexer = Lexer::from_text(source);
+            let start = spans.nodes.len();
+            Parser::from_lexer(&mut lexer, &mut nodes, &mut symbols).with_spans(&mut spans).with_source(index).parse().unwrap();
+            spans.nodes[start..].iter_mut().for_each(|span| span.file = Some(file.to_string()));
+        }
+        (nodes, spans)
+    }
+
+    fn messages(r: Result<()>) -> std::result::Result<(), Vec<String>> {
+        r.map_err(|errors| errors.into_iter().map(|e| e.message).collect())
+    }
+
+    #[test]
+    fn apply() {
+        let (mut nodes, spans) = parse(&[
+            ("a.a", "#global main\n#extern helper\n:main\n    CALL &helper\n:loop\n    J @loop\n"),
+            ("b.a", "#global helper\n:helper\n    J @loop\n:loop\n    RET\n"),
+        ]);
+
+        let r = Visibility::new(&mut nodes, &spans).apply();
+
+        assert!(r.is_ok(), "Expected Ok(...), got {:?}", r);
+        let labels: Vec<&String> = nodes.iter().filter_map(Visibility::definition).collect();
+        assert_eq!(vec!["main", "a.a:loop", "helper", "b.a:loop"], labels);
+        let references: Vec<&String> = nodes.iter().filter_map(Visibility::reference).collect();
+        assert_eq!(vec!["helper", "a.a:loop", "b.a:loop"], references);
+    }
+
+    #[test]
+    fn apply_undeclared_reference() {
+        let (mut nodes, spans) = parse(&[
+            ("a.a", "    CALL &helper\n"),
+            ("b.a", "#global helper\n:helper\n    RET\n"),
+        ]);
+
+        let r = Visibility::new(&mut nodes, &spans).apply();
+
+        assert_eq!(Err(vec![NodeError {
+            index: 0,
+            name: "helper".to_string(),
+            message: "Label helper is neither defined nor declared #extern at a.a:1:5".to_string(),
+        }]), r);
+    }
+
+    #[test]
+    fn apply_unresolved_extern() {
+        let (mut nodes, spans) = parse(&[("a.a", "#extern helper\n    CALL &helper\n")]);
+
+        let r = Visibility::new(&mut nodes, &spans).apply();
+
+        assert_eq!(Err(vec!["Label helper declared #extern at a.a:1:1 is not defined #global in any file".to_string()]), messages(r));
+    }
+
+    #[test]
+    fn apply_unresolved_extern_allowed() {
+        let (mut nodes, spans) = parse(&[("a.a", "#extern helper\n    CALL &helper\n")]);
+
+        let r = Visibility::new(&mut nodes, &spans).allow_externs().apply();
+
+        assert_eq!(Ok(()), r);
+    }
+
+    #[test]
+    fn apply_duplicate_global() {
+        let (mut nodes, spans) = parse(&[
+            ("a.a", "#global helper\n:helper\n    RET\n"),
+            ("b.a", "    NOP\n#global helper\n:helper\n    RET\n"),
+        ]);
+
+        let r = Visibility::new(&mut nodes, &spans).apply();
+
+        assert_eq!(Err(vec!["Label helper defined #global at a.a:2:1 and at b.a:3:1".to_string()]), messages(r));
+    }
+
+    #[test]
+    fn unqualified_name() {
+        assert_eq!("loop", unqualified("examples/fact.a:loop"));
+        assert_eq!("main", unqualified("main"));
+    }
+}
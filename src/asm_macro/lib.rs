@@ -0,0 +1,195 @@
+extern crate proc_macro;
+
+use proc_macro::{Delimiter, Group, Span, TokenStream, TokenTree};
+
+use tha::assembler::{self, Options, Source};
+use tha::output::{self, Format};
+use tha::symbols::SymbolMap;
+
+/// Assembles Thorium source at compile time, as `tha` does with a single input file and the `bin`
+/// format. It expands to a `(&'static [u8], &'static [(&'static str, u32)])`: the bytes and, for
+/// each symbol of the symbol map, its name and its address (or value for a variable).
+///
+/// ```
+/// use thorium_asm::thorium_asm;
+///
+/// let (bytes, symbols) = thorium_asm! {
+///     #base 0x100
+///     :start
+///         MOV   r0, 5
+///         J     @start
+/// };
+/// assert_eq!(16, bytes.len());
+/// assert_eq!(&[("start", 0x100)], symbols);
+/// ```
+///
+/// The source is written as Rust tokens, the lines and columns being those of the Rust source;
+/// comments are Rust comments. Assembly errors are compile errors, spanning the token they are
+/// reported at, or the whole macro when they have no position, the macro then expanding to empty
+/// slices:
+///
+/// ```compile_fail
+/// use thorium_asm::thorium_asm;
+///
+/// let (bytes, _) = thorium_asm! {
+///     MOV   r0, 5
+///     FOO   r0
+/// };
+/// ```
+#[proc_macro]
+pub fn thorium_asm(input: TokenStream) -> TokenStream {
+    let mut source = Text::default();
+    source.push_stream(input);
+    source.text.push('\n');
+
+    match assemble(&source.text) {
+        Ok((bytes, symbols)) => {
+            let symbols: Vec<String> = symbols.iter()
+                .map(|(name, address)| format!("({:?}, 0x{:08x})", name, address))
+                .collect();
+            format!(
+                "{{ const BYTES: &[u8] = &{:?}; const SYMBOLS: &[(&str, u32)] = &[{}]; (BYTES, SYMBOLS) }}",
+                bytes,
+                symbols.join(", ")
+            ).parse().unwrap()
+        }
+        Err(errors) => {
+            // the errors are followed by a value of the type of the bytes and the symbols, so that the
+            // code using them reports no error of its own
+            let mut errors: TokenStream = errors.iter().map(|(error, position)| compile_error(error, source.span(*position))).collect();
+            errors.extend("(&[] as &[u8], &[] as &[(&str, u32)])".parse::<TokenStream>().unwrap());
+            TokenTree::Group(Group::new(Delimiter::Brace, errors)).into()
+        }
+    }
+}
+
+/// The bytes and the symbols with their address.
+type Assembled = (Vec<u8>, Vec<(String, u32)>);
+
+/// An error, and the line and column it is reported at, if any.
+type Error = (String, Option<(usize, usize)>);
+
+fn assemble(text: &str) -> Result<Assembled, Vec<Error>> {
+    let image = assembler::assemble(&[Source::from_text(text)], &Options::new())
+        .map_err(|diagnostics| diagnostics.iter()
+            .map(|d| (d.to_string(), d.position.as_ref().map(|p| (p.line() as usize, p.column() as usize))))
+            .collect::<Vec<Error>>())?;
+
+    let mut bytes = vec![];
+    output::write(Format::Bin, &image.container, &mut bytes).map_err(|e| vec![(format!("Output error: {}", e), None)])?;
+
+    let symbols = SymbolMap::new(&image.nodes, &image.spans, &image.addresses, &image.symbols)
+        .symbols()
+        .into_iter()
+        .map(|symbol| (symbol.name, symbol.address))
+        .collect();
+    Ok((bytes, symbols))
+}
+
+/// The source text rebuilt from the tokens, each of them at its line (relative to the first one)
+/// and column, along with where each token is in the text.
+#[derive(Default)]
+struct Text {
+    text: String,
+    tokens: Vec<((usize, usize), Span)>,
+    first_line: Option<usize>,
+    line: usize,
+    column: usize,
+}
+
+impl Text {
+    fn push_stream(&mut self, stream: TokenStream) {
+        for token in stream {
+            match token {
+                TokenTree::Group(group) => self.push_group(&group),
+                TokenTree::Ident(ident) => self.push(&ident.to_string(), ident.span()),
+                TokenTree::Punct(punct) => self.push(&punct.as_char().to_string(), punct.span()),
+                TokenTree::Literal(literal) => self.push(&literal.to_string(), literal.span()),
+            }
+        }
+    }
+
+    fn push_group(&mut self, group: &Group) {
+        let (open, close) = match group.delimiter() {
+            Delimiter::Parenthesis => ("(", ")"),
+            Delimiter::Brace => ("{", "}"),
+            Delimiter::Bracket => ("[", "]"),
+            Delimiter::None => ("", ""),
+        };
+        self.push(open, group.span_open());
+        self.push_stream(group.stream());
+        self.push(close, group.span_close());
+    }
+
+    fn push(&mut self, text: &str, span: Span) {
+        if text.is_empty() {
+            return;
+        }
+        let first_line = *self.first_line.get_or_insert(span.line());
+        let line = span.line().saturating_sub(first_line) + 1;
+        if self.line < line {
+            (self.line..line).skip(if self.line == 0 { 1 } else { 0 }).for_each(|_| self.text.push('\n'));
+            self.line = line;
+            self.column = 1;
+        }
+        if self.column < span.column() {
+            self.text.push_str(&" ".repeat(span.column() - self.column));
+            self.column = span.column();
+        }
+        self.tokens.push(((self.line, self.column), span));
+        self.text.push_str(text);
+        self.column += text.chars().count();
+    }
+
+    /// The span of the token at `position`, if any.
+    fn span(&self, position: Option<(usize, usize)>) -> Span {
+        match position {
+            Some(position) => self.tokens.iter()
+                .take_while(|(start, _)| *start <= position)
+                .last()
+                .map(|(_, span)| *span)
+                .unwrap_or_else(Span::call_site),
+            None => Span::call_site(),
+        }
+    }
+}
+
+/// `compile_error!("<error>")`, spanning `span`.
+fn compile_error(error: &str, span: Span) -> TokenStream {
+    format!("compile_error!({:?});", error)
+        .parse::<TokenStream>()
+        .unwrap()
+        .into_iter()
+        .map(|token| with_span(token, span))
+        .collect()
+}
+
+fn with_span(mut token: TokenTree, span: Span) -> TokenTree {
+    if let TokenTree::Group(group) = &token {
+        let stream = group.stream().into_iter().map(|t| with_span(t, span)).collect();
+        token = TokenTree::Group(Group::new(group.delimiter(), stream));
+    }
+    token.set_span(span);
+    token
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_position() {
+        let r = assemble("    MOV   r0, 1\n    FOO   r0\n    MOV   r40, 1\n");
+        assert_eq!(Err(vec![("Syntax error: Invalid mnemonic 'FOO'".to_string(), Some((2, 5)))]), r);
+
+        let r = assemble("    MOV   r0, 1\n    MOV   r40, 1\n");
+        assert_eq!(Err(vec![("Semantic error: r40 is not a valid register".to_string(), Some((2, 5)))]), r);
+    }
+
+    #[test]
+    fn assemble_text() {
+        let r = assemble("$v = 2\n:start\n    MOV   r0, $v\n");
+
+        assert_eq!(Ok((vec![3, 0, 0, 0, 0, 0, 0, 2], vec![("start".to_string(), 0), ("$v".to_string(), 2)])), r);
+    }
+}
@@ -0,0 +1,41 @@
+use thorium_asm::thorium_asm;
+
+#[test]
+fn assemble() {
+    let (bytes, symbols) = thorium_asm! {
+        $count = 3
+        #base 0x1000
+        #entry main
+        :main
+            MOV   r0, $count    // loop counter
+        :loop
+            LOAD  r1, [r2 + 4]
+            DEC   r0
+            JNE   @loop
+            HALT
+        #word data 0x01020304
+    };
+
+    assert_eq!(&[
+        0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03,
+        0x2d, 0x01, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04,
+        0x0c, 0x00, 0x00, 0x00,
+        0x20, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08,
+        0x01, 0x00, 0x00, 0x00,
+        0x01, 0x02, 0x03, 0x04,
+    ], bytes);
+    assert_eq!(&[("$count", 3), ("main", 0x1000), ("loop", 0x1008), ("data", 0x1020)], symbols);
+}
+
+#[test]
+fn empty() {
+    let (bytes, symbols) = thorium_asm! {};
+
+    assert_eq!(0, bytes.len());
+    assert_eq!(0, symbols.len());
+}
+
+#[test]
+fn errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
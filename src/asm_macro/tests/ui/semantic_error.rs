@@ -0,0 +1,10 @@
+use thorium_asm::thorium_asm;
+
+fn main() {
+    let (bytes, symbols) = thorium_asm! {
+        MOV   r0, 5
+        MOV   r40, 5
+        MOV   r41, 5
+    };
+    assert_eq!(0, bytes.len() + symbols.len());
+}
@@ -0,0 +1,9 @@
+use thorium_asm::thorium_asm;
+
+fn main() {
+    let (bytes, symbols) = thorium_asm! {
+        MOV   r0, 5
+        FOO   r0
+    };
+    assert_eq!(0, bytes.len() + symbols.len());
+}
@@ -0,0 +1,111 @@
+use std::fs::{self, OpenOptions};
+use std::io;
+
+use clap::{App, Arg, ArgMatches, crate_authors, crate_version};
+
+use tha::disassembler::Disassembler;
+use tha::output::{self, Format};
+use tha::symbols;
+
+fn main() {
+    let matches = parse_opts();
+    let input = matches.value_of("input").unwrap();
+    let format: Format = matches.value_of("format").unwrap().parse().unwrap();
+
+    let mut container = match fs::read(input).map_err(|e| e.to_string()).and_then(|bytes| output::read(format, &bytes)) {
+        Ok(container) => container,
+        Err(err) => {
+            println!("Input error: {} in {}", err, input);
+            return;
+        }
+    };
+    if let Some(origin) = matches.value_of("origin") {
+        let origin = match origin.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16),
+            None => origin.parse(),
+        };
+        match origin {
+            Ok(origin) => container.segments.iter_mut().for_each(|s| s.origin = s.origin.wrapping_add(origin)),
+            Err(_) => {
+                println!("Input error: invalid origin");
+                return;
+            }
+        }
+    }
+
+    let symbols = match matches.value_of("symbols").map(symbols::from_file) {
+        None => vec![],
+        Some(Ok(symbols)) => symbols,
+        Some(Err(err)) => {
+            println!("Symbols error: {}", err);
+            return;
+        }
+    };
+
+    let disassembler = Disassembler::new(&container.segments)
+        .with_symbols(&symbols)
+        .with_entry(container.entry);
+    let result = match matches.value_of("output") {
+        None => disassembler.write(&mut io::stdout()),
+        Some(output) => OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(output)
+            .map_err(|e| e.to_string())
+            .and_then(|mut file| disassembler.write(&mut file)),
+    };
+    if let Err(err) = result {
+        println!("Output error: {}", err);
+    }
+}
+
+fn parse_opts<'a>() -> ArgMatches<'a> {
+    App::new("Thorium Disassembler")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Disassembles images written by tha or thld")
+        .arg(
+            Arg::with_name("input")
+                .help("Input file")
+                .long("input")
+                .short("i")
+                .multiple(false)
+                .number_of_values(1)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Output file, the standard output if none")
+                .long("output")
+                .short("o")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Input format")
+                .long("format")
+                .short("f")
+                .multiple(false)
+                .number_of_values(1)
+                .possible_values(&["bin", "segments", "ihex", "srec", "thm"])
+                .default_value("bin")
+        )
+        .arg(
+            Arg::with_name("symbols")
+                .help("Symbols file written by tha --symbols, to name the addresses")
+                .long("symbols")
+                .short("s")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .arg(
+            Arg::with_name("origin")
+                .help("Address the segments were assembled at, added to their origin (for bin images)")
+                .long("origin")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .get_matches()
+}
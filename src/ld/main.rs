@@ -0,0 +1,115 @@
+use std::fs::{self, OpenOptions};
+
+use clap::{App, Arg, ArgMatches, crate_authors, crate_version};
+
+use tha::archive::{self, Archive};
+use tha::container::Container;
+use tha::layout::Layout;
+use tha::linker::Linker;
+use tha::object::Object;
+use tha::output::{self, Format};
+
+fn main() {
+    let matches = parse_opts();
+    let input: Vec<_> = matches.values_of("input").unwrap().collect();
+    let output = matches.value_of("output").unwrap();
+    let format: Format = matches.value_of("format").unwrap().parse().unwrap();
+
+    let mut objects = vec![];
+    let mut archives = vec![];
+    for f in input {
+        let result = match fs::read(f) {
+            Ok(bytes) if bytes.starts_with(archive::MAGIC) => Archive::read(&bytes)
+                .map(|a| archives.push((f.to_string(), a))),
+            Ok(bytes) => Object::read(&bytes)
+                .map(|o| objects.push((f.to_string(), o))),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(err) = result {
+            println!("Input error: {} in {}", err, f);
+            return;
+        }
+    }
+    let members = archive::select(&objects, &archives);
+    objects.extend(members);
+
+    let layout = match matches.value_of("layout").map(Layout::from_file) {
+        None => Layout::default(),
+        Some(Ok(layout)) => layout,
+        Some(Err(err)) => {
+            println!("Layout error: {}", err);
+            return;
+        }
+    };
+
+    let linker = Linker::new(&objects).with_layout(&layout);
+    let container = match linker.link().and_then(|(segments, addresses)| Ok(Container {
+        entry: linker.entry(&addresses)?,
+        registers: linker.registers(),
+        segments,
+    })) {
+        Ok(container) => container,
+        Err(err) => {
+            println!("Link error: {}", err);
+            return;
+        }
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(output)
+        .unwrap();
+    if let Err(err) = output::write(format, &container, &mut file) {
+        println!("Output error: {}", err);
+        return;
+    }
+
+    for segment in container.segments.iter() {
+        println!("Wrote {} bytes at 0x{:08x} to {}", segment.bytes.len(), segment.origin, output);
+    }
+}
+
+fn parse_opts<'a>() -> ArgMatches<'a> {
+    App::new("Thorium Linker")
+        .version(crate_version!())
+        .author(crate_authors!())
+        .about("Links objects written by tha -c")
+        .arg(
+            Arg::with_name("input")
+                .help("Input objects and archives")
+                .long("input")
+                .short("i")
+                .multiple(true)
+                .number_of_values(1)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("output")
+                .help("Output file")
+                .long("output")
+                .short("o")
+                .multiple(false)
+                .number_of_values(1)
+                .required(true)
+        )
+        .arg(
+            Arg::with_name("format")
+                .help("Output format")
+                .long("format")
+                .short("f")
+                .multiple(false)
+                .number_of_values(1)
+                .possible_values(&["bin", "segments", "ihex", "srec", "thm"])
+                .default_value("bin")
+        )
+        .arg(
+            Arg::with_name("layout")
+                .help("Memory layout file placing the sections started by #section")
+                .long("layout")
+                .multiple(false)
+                .number_of_values(1)
+        )
+        .get_matches()
+}
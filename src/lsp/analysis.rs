@@ -0,0 +1,421 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use tha::address_resolver::{Address, AddressResolver};
+use tha::checker::{Checker, VmConfig};
+use tha::constants::REG_COUNT;
+use tha::cst::{Cst, Kind as StatementKind};
+use tha::layout::Layout;
+use tha::lexer::{Lexer, Position, Token};
+use tha::parser::{Directive, Node, NodeError, Parser, Spans};
+use tha::visibility::Visibility;
+
+/// A position in a document, zero-based as in the protocol. The character is counted in UTF-16
+/// code units.
+#[derive(Debug, PartialEq, Clone, Copy, PartialOrd)]
+pub struct Point {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Range {
+    pub start: Point,
+    pub end: Point,
+}
+
+impl Range {
+    /// The range of the text starting at `position`.
+    fn new(position: &Position, text: &str) -> Range {
+        let start = Point { line: position.line() as u32 - 1, character: position.column() as u32 - 1 };
+        Range { start, end: Point { line: start.line, character: start.character + text.chars().count() as u32 } }
+    }
+
+    /// The range, its characters counted in chars, with its characters counted in UTF-16 code
+    /// units instead, `text` being the text it is in.
+    fn utf16(&self, text: &str) -> Range {
+        let point = |p: Point| {
+            let line = text.lines().nth(p.line as usize).unwrap_or_default();
+            Point { line: p.line, character: line.chars().take(p.character as usize).map(char::len_utf16).sum::<usize>() as u32 }
+        };
+        Range { start: point(self.start), end: point(self.end) }
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        self.start <= point && point <= self.end
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub range: Range,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Label,
+    Mnemonic,
+    Register,
+    Variable,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Role {
+    Definition,
+    /// A `#global` or `#extern`.
+    Declaration,
+    Reference,
+}
+
+/// A name, as written in a file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Occurrence {
+    pub file: String,
+    pub range: Range,
+    pub kind: Kind,
+    pub role: Role,
+    pub name: String,
+}
+
+/// What is known of a document, assembled after the included files as `tha` would: the
+/// diagnostics, the names it and the included files use, and the resolved addresses and values.
+pub struct Analysis {
+    pub file: String,
+    pub diagnostics: Vec<Diagnostic>,
+    pub occurrences: Vec<Occurrence>,
+    pub variables: HashMap<String, u32>,
+    addresses: HashMap<String, Address>,
+}
+
+impl Analysis {
+    /// Analyses the document `text` of `file`, after the `includes` (path and text).
+    pub fn new(file: &str, text: &str, includes: &[(String, String)], layout: &Layout) -> Analysis {
+        let mut analysis = Analysis {
+            file: file.to_string(),
+            diagnostics: vec![],
+            occurrences: vec![],
+            variables: HashMap::new(),
+            addresses: HashMap::new(),
+        };
+
+        analysis.analyse(file, text, includes, layout);
+
+        // the lexer counts the characters in chars, the protocol in UTF-16 code units
+        let mut texts: HashMap<&str, &str> = includes.iter().map(|(path, text)| (path.as_str(), text.as_str())).collect();
+        texts.insert(file, text);
+        for occurrence in analysis.occurrences.iter_mut() {
+            if let Some(text) = texts.get(occurrence.file.as_str()) {
+                occurrence.range = occurrence.range.utf16(text);
+            }
+        }
+        for diagnostic in analysis.diagnostics.iter_mut() {
+            diagnostic.range = diagnostic.range.utf16(text);
+        }
+        analysis
+    }
+
+    /// Assembles the document after the includes, the characters of the ranges counted in chars.
+    fn analyse(&mut self, file: &str, text: &str, includes: &[(String, String)], layout: &Layout) {
+        let mut nodes = vec![];
+        let mut symbols = HashMap::new();
+        let mut spans = Spans::default();
+        for (index, (path, include)) in includes.iter().enumerate() {
+            let result = Cst::from_lexer(&mut Lexer::from_text(include).with_file(path)).and_then(|cst| {
+                self.occurrences.extend(occurrences(path, &cst));
+                Parser::from_cst(&cst, &mut nodes, &mut symbols).with_spans(&mut spans).with_source(index).parse()
+            });
+            if let Err(err) = result {
+                let start = Point { line: 0, character: 0 };
+                self.diagnostics.push(Diagnostic { range: Range { start, end: start }, message: format!("In {}: {}", path, err) });
+            }
+        }
+
+        let mut lexer = Lexer::from_text(text).with_file(file);
+        let parsed = match Cst::from_lexer(&mut lexer) {
+            Ok(cst) => {
+                self.occurrences.extend(occurrences(file, &cst));
+                let mut parser = Parser::from_cst(&cst, &mut nodes, &mut symbols).with_spans(&mut spans).with_source(includes.len());
+                parser.parse().map_err(|err| (err, parser.position().cloned()))
+            }
+            Err(err) => Err((err, Some(lexer.position().clone()))),
+        };
+
+        self.variables = symbols.iter()
+            .filter_map(|(name, token)| match token {
+                Token::Integer(_, value) => Some((name.to_owned(), *value)),
+                _ => None,
+            })
+            .collect();
+        for (name, span) in spans.variables.iter() {
+            let file = span.file.as_deref().unwrap_or(file);
+            self.define_variable(file, name, &span.position);
+        }
+
+        if let Err((err, position)) = parsed {
+            self.error(err, position.as_ref(), None);
+            return;
+        }
+
+        if let Err(errors) = Visibility::new(&mut nodes, &spans).allow_externs().apply() {
+            errors.into_iter().for_each(|e| self.node_error(e, &spans));
+            return;
+        }
+        if let Err(err) = layout.place_nodes(&mut nodes) {
+            self.error(err, None, None);
+            return;
+        }
+        // the labels of other files are given an address, to resolve those of this one
+        let externs: Vec<Node> = nodes.iter()
+            .filter_map(|node| match node {
+                Node::Directive(Directive::Extern(label)) => Some(Node::Label(label.to_owned())),
+                _ => None,
+            })
+            .collect();
+        nodes.extend(externs);
+        match AddressResolver::new(&nodes).resolve() {
+            Ok(addresses) => self.addresses = addresses,
+            Err(err) => self.error(err, None, None),
+        }
+        if let Some(errors) = Checker::new(VmConfig { register_count: REG_COUNT as u8 }).check(&nodes) {
+            errors.into_iter().for_each(|e| self.node_error(e, &spans));
+        }
+    }
+
+    /// The occurrence of the document at `point`.
+    pub fn occurrence_at(&self, point: Point) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|o| o.file == self.file && o.range.contains(point))
+    }
+
+    /// The definitions of the name of `occurrence`, in the document and the included files.
+    pub fn definitions(&self, occurrence: &Occurrence) -> Vec<&Occurrence> {
+        self.occurrences.iter()
+            .filter(|o| o.kind == occurrence.kind && o.name == occurrence.name && o.role == Role::Definition)
+            // labels are local to their file
+            .filter(|o| o.kind != Kind::Label || o.file == occurrence.file)
+            .collect()
+    }
+
+    /// The occurrences of the name of `occurrence`, in the document and the included files.
+    pub fn references(&self, occurrence: &Occurrence) -> Vec<&Occurrence> {
+        self.occurrences.iter()
+            .filter(|o| o.kind == occurrence.kind && o.name == occurrence.name)
+            .filter(|o| o.kind != Kind::Label || o.file == occurrence.file)
+            .collect()
+    }
+
+    /// Whether the document declares the label `#global` or `#extern`, i.e. shares it with others.
+    pub fn shares(&self, label: &str) -> bool {
+        self.occurrences.iter()
+            .any(|o| o.file == self.file && o.kind == Kind::Label && o.role == Role::Declaration && o.name == label)
+    }
+
+    /// The labels defined by the document.
+    pub fn labels(&self) -> Vec<&str> {
+        let mut labels: Vec<&str> = self.occurrences.iter()
+            .filter(|o| o.file == self.file && o.kind == Kind::Label && o.role == Role::Definition)
+            .map(|o| o.name.as_str())
+            .collect();
+        labels.sort_unstable();
+        labels.dedup();
+        labels
+    }
+
+    /// The address of a label of the document, if it was resolved.
+    pub fn address(&self, label: &str) -> Option<&Address> {
+        self.addresses.get(&format!("{}:{}", self.file, label)).or_else(|| self.addresses.get(label))
+    }
+
+    /// Reports `message` at the name it is about on the line of `position`, or at `position`, a
+    /// position in the document.
+    fn error(&mut self, message: String, position: Option<&Position>, name: Option<&str>) {
+        let range = self.locate(position, name).unwrap_or(Range {
+            start: Point { line: 0, character: 0 },
+            end: Point { line: 0, character: 0 },
+        });
+        self.diagnostics.push(Diagnostic { range, message });
+    }
+
+    /// Reports an error found in the nodes at the node it is about, if the document defines it.
+    fn node_error(&mut self, error: NodeError, spans: &Spans) {
+        let position = spans.nodes.get(error.index)
+            .filter(|span| span.file.as_deref() == Some(self.file.as_str()))
+            .map(|span| span.position.clone());
+        self.error(error.message, position.as_ref(), Some(&error.name));
+    }
+
+    fn locate(&self, position: Option<&Position>, name: Option<&str>) -> Option<Range> {
+        let position = position.map(|position| Range::new(position, "").start);
+        let occurrences = self.occurrences.iter().filter(|o| o.file == self.file);
+        match (position, name) {
+            (Some(point), Some(name)) => occurrences.filter(|o| o.range.start.line == point.line).find(|o| o.name == name).map(|o| o.range),
+            (Some(point), None) => occurrences.filter(|o| o.range.contains(point)).map(|o| o.range).next(),
+            (None, Some(name)) => occurrences.filter(|o| o.name == name).map(|o| o.range).next(),
+            (None, None) => None,
+        }.or_else(|| position.map(|start| Range { start, end: start }))
+    }
+
+    /// Marks the variable at `position` as defined there, adding it when the defining token is not
+    /// a variable, such as the field of a `#struct`.
+    fn define_variable(&mut self, file: &str, name: &str, position: &Position) {
+        let start = Range::new(position, "").start;
+        match self.occurrences.iter_mut().find(|o| o.file == file && o.range.start == start && o.name == name) {
+            Some(occurrence) => occurrence.role = Role::Definition,
+            None => {
+                let range = self.occurrences.iter()
+                    .find(|o| o.file == file && o.range.start == start)
+                    .map(|o| o.range)
+                    .unwrap_or(Range { start, end: start });
+                self.occurrences.push(Occurrence {
+                    file: file.to_string(),
+                    range,
+                    kind: Kind::Variable,
+                    role: Role::Definition,
+                    name: name.to_string(),
+                });
+            }
+        }
+    }
+}
+
+/// The labels, mnemonics and variables of `cst`, and the identifiers of the blocks, named after
+/// the block, for the definitions of their variables to be found.
+fn occurrences(file: &str, cst: &Cst) -> Vec<Occurrence> {
+    let mut occurrences = vec![];
+    let mut push = |token: &Token, text: &str, kind: Kind, role: Role, name: &str| occurrences.push(Occurrence {
+        file: file.to_string(),
+        range: Range::new(token.position(), text),
+        kind,
+        role,
+        name: name.to_string(),
+    });
+
+    for statement in cst.statements.iter() {
+        let directive = match statement.tokens.first().map(|t| &t.token) {
+            Some(Token::Directive(_, name)) => name.to_lowercase(),
+            _ => String::new(),
+        };
+        for (index, token) in statement.tokens.iter().enumerate() {
+            match &token.token {
+                Token::Label(_, name) => push(&token.token, &token.text, Kind::Label, Role::Definition, name),
+                Token::Address(_, name, _) => push(&token.token, &token.text, Kind::Label, Role::Reference, name),
+                Token::Op(_, name) => push(&token.token, &token.text, Kind::Mnemonic, Role::Reference, name),
+                Token::Variable(_, name) => push(&token.token, &token.text, Kind::Variable, Role::Reference, name),
+                Token::Identifier(_, name) if matches!(statement.kind, StatementKind::Instruction) => {
+                    push(&token.token, &token.text, Kind::Register, Role::Reference, name)
+                }
+                Token::Identifier(_, name) if index == 1 => match directive.as_str() {
+                    "space" | "word" => push(&token.token, &token.text, Kind::Label, Role::Definition, name),
+                    "extern" | "global" => push(&token.token, &token.text, Kind::Label, Role::Declaration, name),
+                    "entry" => push(&token.token, &token.text, Kind::Label, Role::Reference, name),
+                    _ => (),
+                },
+                Token::Identifier(_, name) if directive == "test" && is_entry(&statement.tokens[index - 1].token) => {
+                    push(&token.token, &token.text, Kind::Label, Role::Reference, name)
+                }
+                Token::Identifier(_, name) if directive == "struct" || directive == "enum" => {
+                    push(&token.token, &token.text, Kind::Variable, Role::Reference, name)
+                }
+                Token::String(_, path) if index == 1 && directive == "image" => {
+                    if let Some(stem) = Path::new(path).file_stem().and_then(|s| s.to_str()) {
+                        push(&token.token, &token.text, Kind::Label, Role::Definition, stem);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    occurrences
+}
+
+/// Whether `token` is the `entry` of a `#test` block, followed by the label to start at.
+fn is_entry(token: &Token) -> bool {
+    matches!(token, Token::Identifier(_, name) if name == "entry")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(line: u32, character: u32) -> Point {
+        Point { line, character }
+    }
+
+    #[test]
+    fn definitions_and_references() {
+        let text = "$v = 4\n#struct s {\n    x: word\n}\n:start\n    MOV   r0, $s.x\n    J     @start\n#word data 1\n    MOV   r1, &data\n";
+        let analysis = Analysis::new("a.a", text, &[], &Layout::default());
+        assert_eq!(Vec::<Diagnostic>::new(), analysis.diagnostics);
+
+        let start = analysis.occurrence_at(point(6, 11)).unwrap();
+        assert_eq!(("start", Kind::Label, Role::Reference), (start.name.as_str(), start.kind, start.role));
+        let definitions: Vec<Range> = analysis.definitions(start).iter().map(|o| o.range).collect();
+        assert_eq!(vec![Range { start: point(4, 0), end: point(4, 6) }], definitions);
+        assert_eq!(2, analysis.references(start).len());
+
+        let field = analysis.occurrence_at(point(5, 15)).unwrap();
+        assert_eq!("$s.x", field.name);
+        let definitions: Vec<Range> = analysis.definitions(field).iter().map(|o| o.range).collect();
+        assert_eq!(vec![Range { start: point(2, 4), end: point(2, 5) }], definitions);
+
+        let data = analysis.occurrence_at(point(8, 16)).unwrap();
+        let definitions: Vec<Range> = analysis.definitions(data).iter().map(|o| o.range).collect();
+        assert_eq!(vec![Range { start: point(7, 6), end: point(7, 10) }], definitions);
+        assert_eq!(Some(16), analysis.address("data").map(Address::absolute));
+        assert_eq!(Some(&4), analysis.variables.get("$v"));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let text = "#test t {\n    entry start\n    expect [&data] = 1\n}\n:start\n    HALT\n#word data 1\n";
+        let analysis = Analysis::new("a.a", text, &[], &Layout::default());
+        assert_eq!(Vec::<Diagnostic>::new(), analysis.diagnostics);
+
+        let start = analysis.occurrence_at(point(1, 11)).unwrap();
+        assert_eq!(("start", Kind::Label, Role::Reference), (start.name.as_str(), start.kind, start.role));
+        let definitions: Vec<Range> = analysis.definitions(start).iter().map(|o| o.range).collect();
+        assert_eq!(vec![Range { start: point(4, 0), end: point(4, 6) }], definitions);
+
+        let data = analysis.occurrence_at(point(2, 14)).unwrap();
+        let definitions: Vec<Range> = analysis.definitions(data).iter().map(|o| o.range).collect();
+        assert_eq!(vec![Range { start: point(6, 6), end: point(6, 10) }], definitions);
+    }
+
+    #[test]
+    fn includes() {
+        let includes = vec![("meta.a".to_string(), "$__start = 0x1000\n".to_string())];
+        let analysis = Analysis::new("a.a", "#base $__start\n:start\n    J     @start\n", &includes, &Layout::default());
+        assert_eq!(Vec::<Diagnostic>::new(), analysis.diagnostics);
+
+        assert_eq!(Some(0x1000), analysis.address("start").map(Address::absolute));
+        let definitions: Vec<&str> = analysis.definitions(analysis.occurrence_at(point(0, 8)).unwrap()).iter()
+            .map(|o| o.file.as_str())
+            .collect();
+        assert_eq!(vec!["meta.a"], definitions);
+    }
+
+    #[test]
+    fn diagnostics() {
+        let analysis = Analysis::new("a.a", "#base 0\n    MOV   r0, 1\n    J     @missing\n", &[], &Layout::default());
+        assert_eq!(vec![Diagnostic {
+            range: Range { start: point(2, 10), end: point(2, 18) },
+            message: "Label missing is neither defined nor declared #extern at a.a:3:5".to_string(),
+        }], analysis.diagnostics);
+
+        let analysis = Analysis::new("a.a", "    MOV   r0, 1\n    MOV   r0,\n", &[], &Layout::default());
+        assert_eq!(1, analysis.diagnostics.len());
+        assert_eq!(1, analysis.diagnostics[0].range.start.line, "{:?}", analysis.diagnostics);
+
+        let analysis = Analysis::new("a.a", "    MOV   r99, 1\n", &[], &Layout::default());
+        assert_eq!(vec![Diagnostic {
+            range: Range { start: point(0, 10), end: point(0, 13) },
+            message: "r99 is not a valid register".to_string(),
+        }], analysis.diagnostics);
+    }
+
+    #[test]
+    fn utf16() {
+        let range = Range { start: point(1, 2), end: point(1, 3) };
+        assert_eq!(Range { start: point(1, 3), end: point(1, 4) }, range.utf16("\n\u{1f600}\u{e9} x\n"));
+    }
+}
@@ -0,0 +1,75 @@
+use tha::op::Op;
+
+/// A mnemonic, with the ops it is assembled to, one for each of its operands lists.
+#[derive(Debug, PartialEq)]
+pub struct Mnemonic {
+    pub name: &'static str,
+    pub ops: Vec<Op>,
+}
+
+impl Mnemonic {
+    /// The mnemonics of the instruction set, as described by instructions.thi.
+    pub fn all() -> Vec<Mnemonic> {
+        let mut mnemonics: Vec<Mnemonic> = vec![];
+        for op in (0..=255u8).filter(|b| Op::from(*b).bytecode() == *b).map(Op::from) {
+            match mnemonics.iter_mut().find(|m| m.name == op.mnemonic()) {
+                Some(mnemonic) => mnemonic.ops.push(op),
+                None => mnemonics.push(Mnemonic { name: op.mnemonic(), ops: vec![op] }),
+            }
+        }
+        mnemonics
+    }
+
+    /// The effect of the mnemonic's first form, in Markdown.
+    pub fn effect(&self) -> Option<&'static str> {
+        self.ops.first().and_then(Op::effect)
+    }
+
+    /// The description of the mnemonic, in Markdown.
+    pub fn hover(&self) -> String {
+        let mut text = vec![];
+        for op in self.ops.iter() {
+            let mut lines = vec![match op.syntax() {
+                "" => format!("**{}**", self.name),
+                syntax => format!("**{}** `{}`", self.name, syntax),
+            }];
+            if let Some(effect) = op.effect() {
+                lines.push(effect.to_owned());
+            }
+            if let Some(condition) = op.condition() {
+                lines.push(format!("if {}", condition));
+            }
+            if let Some(flags) = op.flags() {
+                lines.push(format!("flags: {}", flags));
+            }
+            if let Some(comment) = op.comment() {
+                lines.push(format!("*{}*", comment));
+            }
+            text.push(lines.join("  \n"));
+        }
+        text.join("\n\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all() {
+        let mnemonics = Mnemonic::all();
+
+        assert_eq!(Some(&Mnemonic { name: "JEQ", ops: vec![Op::JeqS, Op::JeqA] }), mnemonics.iter().find(|m| m.name == "JEQ"));
+        assert_eq!(1, mnemonics.iter().filter(|m| m.name == "PANIC").count());
+    }
+
+    #[test]
+    fn hover() {
+        let mnemonics = Mnemonic::all();
+        let jeq = mnemonics.iter().find(|m| m.name == "JEQ").unwrap();
+
+        assert_eq!("**JEQ** `@0`  \n`pc = cs + @0`  \nif `z = 1`\n\n**JEQ** `&0`  \n`pc = &0`  \nif `z = 1`", jeq.hover());
+        let load = mnemonics.iter().find(|m| m.name == "LOAD").unwrap();
+        assert!(load.hover().contains("**LOAD** `r0, [r1 + w0]`  \n`r0 = [r1 + w0]`"), "{}", load.hover());
+    }
+}
@@ -0,0 +1,525 @@
+//! The Thorium assembly language server, speaking the Language Server Protocol over the standard
+//! input and output. It publishes the diagnostics of `tha` for the open documents and provides
+//! the definitions and references of labels and variables, hovers for mnemonics, labels and
+//! variables, and the completion of mnemonics, registers, labels and variables.
+//!
+//! Documents are assembled alone, as with `tha -c`, after the files listed in the `include`
+//! initialization option, such as the `meta.a` defining the `$__` variables. The `layout`
+//! option gives the layout file placing the sections. Both are relative to the workspace root.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process;
+
+use tha::json::Json;
+use tha::layout::Layout;
+use tha::registers;
+
+use crate::analysis::{Analysis, Kind, Occurrence, Point, Range, Role};
+use crate::instructions::Mnemonic;
+
+mod analysis;
+mod instructions;
+
+const PARSE_ERROR: i32 = -32700;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+
+type Result<T> = std::result::Result<T, String>;
+
+/// What is read from the input.
+#[derive(Debug, PartialEq)]
+enum Input {
+    Message(Json),
+    /// A message whose content is not valid JSON.
+    Invalid(String),
+    End,
+}
+
+struct Document {
+    text: String,
+    analysis: Analysis,
+}
+
+struct Server {
+    documents: HashMap<String, Document>,
+    includes: Vec<String>,
+    layout: Layout,
+    mnemonics: Vec<Mnemonic>,
+    shutdown: bool,
+}
+
+fn main() {
+    let mut server = Server {
+        documents: HashMap::new(),
+        includes: vec![],
+        layout: Layout::default(),
+        mnemonics: Mnemonic::all(),
+        shutdown: false,
+    };
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    loop {
+        let messages = match read_message(&mut input) {
+            Ok(Input::Message(message)) => server.handle(&message),
+            Ok(Input::Invalid(err)) => vec![error(Json::Null, PARSE_ERROR, err)],
+            Ok(Input::End) => process::exit(if server.shutdown { 0 } else { 1 }),
+            Err(err) => {
+                eprintln!("Input error: {}", err);
+                process::exit(1);
+            }
+        };
+        for message in messages {
+            if let Err(err) = write_message(&mut io::stdout(), &message) {
+                eprintln!("Output error: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+impl Server {
+    /// Handles a request or a notification, returning the messages to send.
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message.get("method").and_then(Json::as_str).unwrap_or_default();
+        let params = message.get("params").unwrap_or(&Json::Null);
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notification(method, params),
+        };
+
+        let result = match method {
+            "initialize" => Ok(self.initialize(params)),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            "textDocument/definition" => self.definition(params).ok_or(INVALID_PARAMS),
+            "textDocument/references" => self.references(params).ok_or(INVALID_PARAMS),
+            "textDocument/hover" => self.hover(params).ok_or(INVALID_PARAMS),
+            "textDocument/completion" => self.completion(params).ok_or(INVALID_PARAMS),
+            _ => Err(METHOD_NOT_FOUND),
+        };
+
+        vec![match result {
+            Ok(result) => Json::object(vec![("jsonrpc", Json::string("2.0")), ("id", id), ("result", result)]),
+            Err(METHOD_NOT_FOUND) => error(id, METHOD_NOT_FOUND, format!("Unknown method {}", method)),
+            Err(code) => error(id, code, format!("Invalid parameters for {}", method)),
+        }]
+    }
+
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params.get("textDocument").and_then(|d| d.get("uri")).and_then(Json::as_str).unwrap_or_default();
+        match method {
+            "exit" => process::exit(if self.shutdown { 0 } else { 1 }),
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").and_then(|d| d.get("text")).and_then(Json::as_str);
+                self.update(uri, text.unwrap_or_default())
+            }
+            "textDocument/didChange" => {
+                // the changes are whole documents, as asked for at initialization
+                let text = params.get("contentChanges")
+                    .and_then(Json::as_array)
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change.get("text"))
+                    .and_then(Json::as_str);
+                match text {
+                    Some(text) => self.update(uri, text),
+                    None => vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![diagnostics(uri, &[])]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn initialize(&mut self, params: &Json) -> Json {
+        let root = params.get("rootUri").and_then(Json::as_str).map(path)
+            .or_else(|| params.get("rootPath").and_then(Json::as_str).map(|p| p.to_string()))
+            .unwrap_or_else(|| ".".to_string());
+        let options = params.get("initializationOptions").unwrap_or(&Json::Null);
+
+        self.includes = options.get("include")
+            .and_then(Json::as_array)
+            .map(|includes| includes.iter()
+                .filter_map(Json::as_str)
+                .map(|include| Path::new(&root).join(include).to_string_lossy().to_string())
+                .collect())
+            .unwrap_or_default();
+        if let Some(layout) = options.get("layout").and_then(Json::as_str) {
+            match Layout::from_file(&Path::new(&root).join(layout).to_string_lossy()) {
+                Ok(layout) => self.layout = layout,
+                Err(err) => eprintln!("Layout error: {}", err),
+            }
+        }
+
+        Json::object(vec![
+            ("capabilities", Json::object(vec![
+                ("textDocumentSync", 1u32.into()),
+                ("definitionProvider", Json::Bool(true)),
+                ("referencesProvider", Json::Bool(true)),
+                ("hoverProvider", Json::Bool(true)),
+                ("completionProvider", Json::object(vec![
+                    ("triggerCharacters", Json::Array(vec![Json::string("@"), Json::string("&"), Json::string("$")])),
+                ])),
+            ])),
+            ("serverInfo", Json::object(vec![
+                ("name", Json::string("thls")),
+                ("version", Json::string(env!("CARGO_PKG_VERSION"))),
+            ])),
+        ])
+    }
+
+    /// Analyses the document again, returning its diagnostics.
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        let includes: Vec<(String, String)> = self.includes.iter()
+            .filter_map(|include| match fs::read_to_string(include) {
+                Ok(text) => Some((include.to_owned(), text)),
+                Err(err) => {
+                    eprintln!("Input error: {}: {}", include, err);
+                    None
+                }
+            })
+            .collect();
+        let analysis = Analysis::new(&path(uri), text, &includes, &self.layout);
+        let message = diagnostics(uri, &analysis.diagnostics.iter().map(|d| (d.range, d.message.as_str())).collect::<Vec<_>>());
+        self.documents.insert(uri.to_string(), Document { text: text.to_string(), analysis });
+        vec![message]
+    }
+
+    /// The document and the occurrence at the position of the parameters.
+    fn occurrence(&self, params: &Json) -> Option<(&Document, Option<&Occurrence>)> {
+        let uri = params.get("textDocument")?.get("uri")?.as_str()?;
+        let document = self.documents.get(uri)?;
+        let position = params.get("position")?;
+        let point = Point {
+            line: position.get("line")?.as_i64()? as u32,
+            character: position.get("character")?.as_i64()? as u32,
+        };
+        Some((document, document.analysis.occurrence_at(point)))
+    }
+
+    fn definition(&self, params: &Json) -> Option<Json> {
+        let (document, occurrence) = self.occurrence(params)?;
+        let occurrence = match occurrence {
+            Some(occurrence) if occurrence.kind == Kind::Label || occurrence.kind == Kind::Variable => occurrence,
+            _ => return Some(Json::Null),
+        };
+
+        let mut definitions: Vec<Json> = document.analysis.definitions(occurrence).into_iter().map(location).collect();
+        // a label of another file is defined #global there
+        if definitions.is_empty() && occurrence.kind == Kind::Label {
+            definitions = self.documents.values()
+                .filter(|d| d.analysis.shares(&occurrence.name))
+                .flat_map(|d| d.analysis.occurrences.iter()
+                    .filter(move |o| o.file == d.analysis.file && o.kind == Kind::Label && o.role == Role::Definition && o.name == occurrence.name))
+                .map(location)
+                .collect();
+        }
+        Some(Json::Array(definitions))
+    }
+
+    fn references(&self, params: &Json) -> Option<Json> {
+        let (document, occurrence) = self.occurrence(params)?;
+        let occurrence = match occurrence {
+            Some(occurrence) if occurrence.kind == Kind::Label || occurrence.kind == Kind::Variable => occurrence,
+            _ => return Some(Json::Null),
+        };
+        let declaration = params.get("context").and_then(|c| c.get("includeDeclaration")) != Some(&Json::Bool(false));
+
+        let mut references = document.analysis.references(occurrence);
+        if occurrence.kind == Kind::Label && document.analysis.shares(&occurrence.name) {
+            references.extend(self.documents.values()
+                .filter(|d| d.analysis.file != document.analysis.file && d.analysis.shares(&occurrence.name))
+                .flat_map(|d| d.analysis.occurrences.iter()
+                    .filter(move |o| o.file == d.analysis.file && o.kind == Kind::Label && o.name == occurrence.name)));
+        }
+        Some(Json::Array(references.into_iter()
+            .filter(|o| declaration || o.role == Role::Reference)
+            .map(location)
+            .collect()))
+    }
+
+    fn hover(&self, params: &Json) -> Option<Json> {
+        let (document, occurrence) = self.occurrence(params)?;
+        let occurrence = match occurrence {
+            Some(occurrence) => occurrence,
+            None => return Some(Json::Null),
+        };
+
+        let text = match occurrence.kind {
+            Kind::Mnemonic => self.mnemonics.iter().find(|m| m.name == occurrence.name).map(Mnemonic::hover),
+            Kind::Label => Some(match document.analysis.address(&occurrence.name) {
+                Some(address) => format!(
+                    "**label** `{}`  \n`&{}` = `0x{:08x}`, `@{}` = `0x{:08x}`",
+                    occurrence.name, occurrence.name, address.absolute(), occurrence.name, address.offset
+                ),
+                None => format!("**label** `{}`", occurrence.name),
+            }),
+            Kind::Variable => document.analysis.variables.get(&occurrence.name)
+                .map(|value| format!("**variable** `{}` = `{}` (`0x{:08x}`)", occurrence.name, value, value)),
+            Kind::Register => None,
+        };
+        Some(match text {
+            Some(text) => Json::object(vec![
+                ("contents", Json::object(vec![("kind", Json::string("markdown")), ("value", Json::String(text))])),
+                ("range", range(&occurrence.range)),
+            ]),
+            None => Json::Null,
+        })
+    }
+
+    fn completion(&self, params: &Json) -> Option<Json> {
+        let (document, _) = self.occurrence(params)?;
+        let position = params.get("position")?;
+        let line = document.text.lines().nth(position.get("line")?.as_i64()? as usize).unwrap_or_default();
+        let character = position.get("character")?.as_i64()? as usize;
+        // the character is counted in UTF-16 code units
+        let mut units = 0;
+        let before: String = line.chars()
+            .take_while(|c| {
+                units += c.len_utf16();
+                units <= character
+            })
+            .collect();
+        let word_start = before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+
+        // kinds of completion items
+        const KEYWORD: u32 = 14;
+        const REFERENCE: u32 = 18;
+        const VARIABLE: u32 = 6;
+        let item = |label: String, kind: u32, detail: Option<String>| Json::object(vec![
+            ("label", Json::String(label)),
+            ("kind", kind.into()),
+            ("detail", detail.map(Json::String).into()),
+        ]);
+
+        let items: Vec<Json> = if word_start.ends_with('@') || word_start.ends_with('&') {
+            document.analysis.labels().into_iter()
+                .map(|label| item(label.to_string(), REFERENCE, None))
+                .collect()
+        } else if word_start.ends_with('$') {
+            let mut variables: Vec<(&String, &u32)> = document.analysis.variables.iter().collect();
+            variables.sort();
+            variables.into_iter()
+                .map(|(name, value)| item(name[1..].to_string(), VARIABLE, Some(format!("0x{:08x}", value))))
+                .collect()
+        } else if word_start.trim().is_empty() {
+            self.mnemonics.iter()
+                .map(|m| item(m.name.to_owned(), KEYWORD, m.effect().map(str::to_owned)))
+                .collect()
+        } else {
+            registers::names().map(|r| item(r, VARIABLE, None)).collect()
+        };
+        Some(Json::Array(items))
+    }
+}
+
+fn error(id: Json, code: i32, message: String) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("id", id),
+        ("error", Json::object(vec![("code", code.into()), ("message", Json::String(message))])),
+    ])
+}
+
+fn diagnostics(uri: &str, diagnostics: &[(Range, &str)]) -> Json {
+    Json::object(vec![
+        ("jsonrpc", Json::string("2.0")),
+        ("method", Json::string("textDocument/publishDiagnostics")),
+        ("params", Json::object(vec![
+            ("uri", Json::string(uri)),
+            ("diagnostics", Json::Array(diagnostics.iter()
+                .map(|(r, message)| Json::object(vec![
+                    ("range", range(r)),
+                    // error
+                    ("severity", 1u32.into()),
+                    ("source", Json::string("tha")),
+                    ("message", Json::string(message)),
+                ]))
+                .collect())),
+        ])),
+    ])
+}
+
+fn location(occurrence: &Occurrence) -> Json {
+    Json::object(vec![("uri", Json::String(uri(&occurrence.file))), ("range", range(&occurrence.range))])
+}
+
+fn range(range: &Range) -> Json {
+    let point = |p: &Point| Json::object(vec![("line", p.line.into()), ("character", p.character.into())]);
+    Json::object(vec![("start", point(&range.start)), ("end", point(&range.end))])
+}
+
+/// The path of a `file:` URI, or the URI itself.
+fn path(uri: &str) -> String {
+    let path = match uri.strip_prefix("file://") {
+        Some(path) => path,
+        None => return uri.to_string(),
+    };
+    let mut bytes = vec![];
+    let mut chars = path.bytes();
+    while let Some(b) = chars.next() {
+        match b {
+            b'%' => {
+                let hex: Vec<u8> = chars.by_ref().take(2).collect();
+                match std::str::from_utf8(&hex).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(b) => bytes.push(b),
+                    None => bytes.extend(std::iter::once(b'%').chain(hex)),
+                }
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+/// The `file:` URI of a path.
+fn uri(path: &str) -> String {
+    if path.contains("://") || path.starts_with("untitled:") {
+        return path.to_string();
+    }
+    let path = match Path::new(path).is_absolute() {
+        true => path.to_string(),
+        false => std::env::current_dir().map(|d| d.join(path).to_string_lossy().to_string()).unwrap_or_else(|_| path.to_string()),
+    };
+    let mut uri = "file://".to_string();
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => uri.push(b as char),
+            b => uri.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    uri
+}
+
+/// Reads a message, preceded by its `Content-Length` header.
+fn read_message<R: BufRead>(input: &mut R) -> Result<Input> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| e.to_string())? == 0 {
+            return Ok(Input::End);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>().map_err(|e| e.to_string())?);
+            }
+        }
+    }
+
+    let length = length.ok_or_else(|| "Missing Content-Length header".to_string())?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content).map_err(|e| e.to_string())?;
+    let content = match String::from_utf8(content) {
+        Ok(content) => content,
+        Err(err) => return Ok(Input::Invalid(err.to_string())),
+    };
+    Ok(match Json::parse(&content) {
+        Ok(message) => Input::Message(message),
+        Err(err) => Input::Invalid(err),
+    })
+}
+
+fn write_message<W: Write>(output: &mut W, message: &Json) -> Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", content.len(), content).map_err(|e| e.to_string())?;
+    output.flush().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        Server {
+            documents: HashMap::new(),
+            includes: vec![],
+            layout: Layout::default(),
+            mnemonics: Mnemonic::all(),
+            shutdown: false,
+        }
+    }
+
+    fn request(method: &str, params: &str) -> Json {
+        Json::parse(&format!(r#"{{"jsonrpc":"2.0","id":1,"method":"{}","params":{}}}"#, method, params)).unwrap()
+    }
+
+    fn position(line: u32, character: u32) -> String {
+        format!(r#"{{"textDocument":{{"uri":"file:///w/a.a"}},"position":{{"line":{},"character":{}}}}}"#, line, character)
+    }
+
+    #[test]
+    fn read_write_messages() {
+        let message = Json::object(vec![("id", 1u32.into())]);
+        let mut bytes = vec![];
+        write_message(&mut bytes, &message).unwrap();
+        assert_eq!(b"Content-Length: 8\r\n\r\n{\"id\":1}".to_vec(), bytes);
+
+        let r = read_message(&mut &bytes[..]);
+        assert_eq!(Ok(Input::Message(message)), r);
+        assert_eq!(Ok(Input::End), read_message(&mut &b""[..]));
+        assert!(matches!(read_message(&mut &b"Content-Length: 2\r\n\r\n{]"[..]), Ok(Input::Invalid(_))));
+        assert!(read_message(&mut &b"Content-Length: 8\r\n\r\n{}"[..]).is_err());
+    }
+
+    #[test]
+    fn uris() {
+        assert_eq!("/w/a b.a", path("file:///w/a%20b.a"));
+        assert_eq!("file:///w/a%20b.a", uri("/w/a b.a"));
+    }
+
+    #[test]
+    fn session() {
+        let mut server = server();
+        let text = r#"#base 0x1000\n:start\n    MOV   r0, $v\n    J     @start\n    MOV   r99, 0\n"#;
+        let open = format!(r#"{{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"file:///w/a.a","text":"$v = 1\n{}"}}}}}}"#, text);
+
+        let messages = server.handle(&Json::parse(&open).unwrap());
+        assert_eq!(
+            r#"[{"jsonrpc":"2.0","method":"textDocument/publishDiagnostics","params":{"uri":"file:///w/a.a","diagnostics":[{"range":{"start":{"line":5,"character":10},"end":{"line":5,"character":13}},"severity":1,"source":"tha","message":"r99 is not a valid register"}]}}]"#,
+            Json::Array(messages).to_string()
+        );
+
+        let messages = server.handle(&request("textDocument/definition", &position(4, 12)));
+        assert_eq!(
+            r#"[{"jsonrpc":"2.0","id":1,"result":[{"uri":"file:///w/a.a","range":{"start":{"line":2,"character":0},"end":{"line":2,"character":6}}}]}]"#,
+            Json::Array(messages).to_string()
+        );
+
+        let messages = server.handle(&request("textDocument/references", &position(0, 1)));
+        assert_eq!(
+            r#"[{"jsonrpc":"2.0","id":1,"result":[{"uri":"file:///w/a.a","range":{"start":{"line":0,"character":0},"end":{"line":0,"character":2}}},{"uri":"file:///w/a.a","range":{"start":{"line":3,"character":14},"end":{"line":3,"character":16}}}]}]"#,
+            Json::Array(messages).to_string()
+        );
+
+        let messages = server.handle(&request("textDocument/hover", &position(2, 3)));
+        assert_eq!(
+            r#"[{"jsonrpc":"2.0","id":1,"result":{"contents":{"kind":"markdown","value":"**label** `start`  \n`&start` = `0x00001000`, `@start` = `0x00000000`"},"range":{"start":{"line":2,"character":0},"end":{"line":2,"character":6}}}}]"#,
+            Json::Array(messages).to_string()
+        );
+
+        let messages = server.handle(&request("textDocument/hover", &position(4, 5)));
+        let hover = messages[0].get("result").and_then(|r| r.get("contents")).and_then(|c| c.get("value")).and_then(Json::as_str);
+        assert_eq!(Some("**J** `@0`  \n`pc = cs + @0`\n\n**J** `&0`  \n`pc = &0`"), hover);
+
+        let messages = server.handle(&request("textDocument/completion", &position(4, 11)));
+        let labels: Vec<&str> = messages[0].get("result").and_then(Json::as_array).unwrap().iter()
+            .filter_map(|item| item.get("label").and_then(Json::as_str))
+            .collect();
+        assert_eq!(vec!["start"], labels);
+
+        let messages = server.handle(&request("unknown", "{}"));
+        assert_eq!(Some(METHOD_NOT_FOUND as i64), messages[0].get("error").and_then(|e| e.get("code")).and_then(Json::as_i64));
+    }
+}